@@ -0,0 +1,83 @@
+//! The crate's error type
+
+use std::fmt;
+
+use wasm_bindgen::JsValue;
+use web_sys::WebGl2RenderingContext;
+
+/// Why creating or using a GL resource failed. Converts into a `JsValue` (a
+/// JS string of its message) so it can be returned across the wasm boundary
+/// with `?`.
+#[derive(Debug, Clone)]
+pub enum WasmGlError {
+    /// `stage` is `"vertex"` or `"fragment"`, and `log` the GLSL info log
+    /// (or the include preprocessing error)
+    ShaderCompile { stage: &'static str, log: String },
+    ProgramLink { log: String },
+    /// A `create*` call returned null, usually because the context was lost
+    ResourceCreation { what: &'static str },
+    /// A uniform that was asked for isn't active in the linked program
+    MissingUniform { name: String },
+    /// The `checkFramebufferStatus` result
+    FramebufferIncomplete { status: u32 },
+    /// Arguments that GL would reject, such as an oversized texture
+    InvalidArgument(String),
+    Js(JsValue),
+}
+
+impl fmt::Display for WasmGlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WasmGlError::ShaderCompile { stage, log } => write!(f, "Failed to compile {stage} shader: {log}"),
+            WasmGlError::ProgramLink { log } => write!(f, "Failed to link program: {log}"),
+            WasmGlError::ResourceCreation { what } => write!(f, "Failed to create {what}"),
+            WasmGlError::MissingUniform { name } => write!(f, "Uniform {name} was not found"),
+            WasmGlError::FramebufferIncomplete { status } => {
+                let problem = match *status {
+                    WebGl2RenderingContext::FRAMEBUFFER_INCOMPLETE_ATTACHMENT => "an attachment is incomplete or unrenderable",
+                    WebGl2RenderingContext::FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT => "it has no attachments",
+                    WebGl2RenderingContext::FRAMEBUFFER_INCOMPLETE_DIMENSIONS => "its attachments differ in size",
+                    WebGl2RenderingContext::FRAMEBUFFER_UNSUPPORTED => "this combination of formats is unsupported",
+                    WebGl2RenderingContext::FRAMEBUFFER_INCOMPLETE_MULTISAMPLE => "its attachments differ in sample count",
+                    status => return write!(f, "Framebuffer is incomplete (status {status:#x})"),
+                };
+                write!(f, "Framebuffer is incomplete: {problem}")
+            }
+            WasmGlError::InvalidArgument(message) => f.write_str(message),
+            WasmGlError::Js(value) => match value.as_string() {
+                Some(message) => f.write_str(&message),
+                None => write!(f, "{value:?}"),
+            },
+        }
+    }
+}
+
+impl std::error::Error for WasmGlError {}
+
+impl From<WasmGlError> for JsValue {
+    fn from(err: WasmGlError) -> JsValue {
+        match err {
+            WasmGlError::Js(value) => value,
+            err => JsValue::from_str(&err.to_string()),
+        }
+    }
+}
+
+impl From<JsValue> for WasmGlError {
+    fn from(value: JsValue) -> WasmGlError {
+        WasmGlError::Js(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_name_the_problem() {
+        let err = WasmGlError::ShaderCompile { stage: "fragment", log: String::from("ERROR: 0:3: syntax error") };
+        assert_eq!(err.to_string(), "Failed to compile fragment shader: ERROR: 0:3: syntax error");
+        assert_eq!(WasmGlError::ResourceCreation { what: "buffer" }.to_string(), "Failed to create buffer");
+        assert_eq!(WasmGlError::MissingUniform { name: String::from("view") }.to_string(), "Uniform view was not found");
+    }
+}
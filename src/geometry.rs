@@ -0,0 +1,557 @@
+//! CPU-side mesh generation and processing
+
+mod obj;
+pub mod primitives;
+
+pub use obj::{load_obj, parse_obj, MeshData, ObjError};
+
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+
+use nalgebra::{Matrix4, Vector3};
+
+use crate::{Color, ColoredVertex, Position, TangentVertex, Vertex};
+
+/// Normal of the counter-clockwise triangle `a`, `b`, `c`, scaled by twice
+/// its area
+fn face_normal(a: Position, b: Position, c: Position) -> Position {
+    (b - a).cross(&(c - a))
+}
+
+/// Normalizes `n`, falling back to +Y for degenerate (zero length) vectors
+/// so that no NaNs end up in vertex data
+fn normalize_or_up(n: Position) -> Position {
+    if n.length() > f32::EPSILON {
+        n.normalize()
+    } else {
+        Position { x: 0., y: 1., z: 0. }
+    }
+}
+
+/// Per-vertex normals averaged from the surrounding faces, weighted by face
+/// area
+pub fn smooth_normals(positions: &[Position], indices: &[u32]) -> Vec<Position> {
+    let mut normals = vec![Position::default(); positions.len()];
+    for tri in indices.chunks_exact(3) {
+        let (a, b, c) = (tri[0] as usize, tri[1] as usize, tri[2] as usize);
+        let n = face_normal(positions[a], positions[b], positions[c]);
+        normals[a] = normals[a] + n;
+        normals[b] = normals[b] + n;
+        normals[c] = normals[c] + n;
+    }
+    normals.into_iter().map(normalize_or_up).collect()
+}
+
+/// Replaces the normals of `vertices` with smooth ones, averaging the faces
+/// around each vertex weighted by the angle of their corner there. Unlike
+/// area weighting in [`smooth_normals`], this doesn't depend on how faces
+/// were split into triangles, so a shared cube corner gets the diagonal.
+pub fn compute_smooth_normals(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut normals = vec![Position::default(); vertices.len()];
+    for tri in indices.chunks_exact(3) {
+        let corners = [tri[0] as usize, tri[1] as usize, tri[2] as usize];
+        let [a, b, c] = corners.map(|i| vertices[i].pos);
+        let n = face_normal(a, b, c);
+        if n.length() <= f32::EPSILON {
+            continue;
+        }
+        let n = n.normalize();
+        for k in 0..3 {
+            let at = vertices[corners[k]].pos;
+            let (e1, e2) = (vertices[corners[(k + 1) % 3]].pos - at, vertices[corners[(k + 2) % 3]].pos - at);
+            let angle = (e1.dot(&e2) / (e1.length() * e2.length())).clamp(-1., 1.).acos();
+            normals[corners[k]] = normals[corners[k]] + n * angle;
+        }
+    }
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        vertex.normal = normalize_or_up(normal);
+    }
+}
+
+/// Tangents for normal mapping, from how the vertices' texture coordinates
+/// run across each triangle. Each vertex sums its triangles' tangents, then
+/// has the part along its normal removed, and takes the bitangent sign from
+/// whether its triangles' bitangents agree with `normal x tangent`. Vertices
+/// whose triangles have degenerate UVs get an arbitrary tangent perpendicular
+/// to the normal.
+pub fn compute_tangents(vertices: &[Vertex], indices: &[u32]) -> Vec<TangentVertex> {
+    let mut tangents = vec![Position::default(); vertices.len()];
+    let mut bitangents = vec![Position::default(); vertices.len()];
+    for tri in indices.chunks_exact(3) {
+        let [a, b, c] = [tri[0] as usize, tri[1] as usize, tri[2] as usize];
+        let (e1, e2) = (vertices[b].pos - vertices[a].pos, vertices[c].pos - vertices[a].pos);
+        let [uv_a, uv_b, uv_c] = [vertices[a].uv, vertices[b].uv, vertices[c].uv];
+        let (du1, dv1) = (uv_b[0] - uv_a[0], uv_b[1] - uv_a[1]);
+        let (du2, dv2) = (uv_c[0] - uv_a[0], uv_c[1] - uv_a[1]);
+        let det = du1 * dv2 - du2 * dv1;
+        if det.abs() <= f32::EPSILON {
+            continue;
+        }
+        // Solves e1 = du1 * T + dv1 * B, e2 = du2 * T + dv2 * B
+        let tangent = (e1 * dv2 - e2 * dv1) * (1. / det);
+        let bitangent = (e2 * du1 - e1 * du2) * (1. / det);
+        for i in [a, b, c] {
+            tangents[i] = tangents[i] + tangent;
+            bitangents[i] = bitangents[i] + bitangent;
+        }
+    }
+    vertices
+        .iter()
+        .zip(tangents)
+        .zip(bitangents)
+        .map(|((v, t), b)| {
+            // Gram-Schmidt against the normal
+            let t = t - v.normal * v.normal.dot(&t);
+            let tangent = if t.length() > f32::EPSILON {
+                t.normalize()
+            } else {
+                let other = if v.normal.x.abs() < 0.9 { Position { x: 1., y: 0., z: 0. } } else { Position { x: 0., y: 1., z: 0. } };
+                normalize_or_up(v.normal.cross(&other))
+            };
+            let bitangent_sign = if v.normal.cross(&tangent).dot(&b) < 0. { -1. } else { 1. };
+            TangentVertex { pos: v.pos, normal: v.normal, uv: v.uv, tangent, bitangent_sign }
+        })
+        .collect()
+}
+
+/// Smooth normals that keep hard edges: each corner of a face only averages
+/// the faces around its vertex within `max_angle` (radians) of its own.
+/// Vertices are split wherever their corners end up with different normals,
+/// so this returns a new vertex and index list. With `max_angle` of PI it
+/// matches [`smooth_normals`]; near 0 it approaches [`flat_vertices`] while
+/// still sharing vertices across flat regions.
+pub fn normals_with_max_angle(positions: &[Position], indices: &[u32], max_angle: f32) -> (Vec<Vertex>, Vec<u32>) {
+    let faces: Vec<Position> = indices
+        .chunks_exact(3)
+        .map(|tri| face_normal(positions[tri[0] as usize], positions[tri[1] as usize], positions[tri[2] as usize]))
+        .collect();
+    let mut vertex_faces = vec![Vec::new(); positions.len()];
+    for (face, tri) in indices.chunks_exact(3).enumerate() {
+        for &i in tri {
+            vertex_faces[i as usize].push(face);
+        }
+    }
+    let min_cos = max_angle.cos();
+    // Compares without normalizing, so zero-area faces count as similar to
+    // everything and contribute nothing rather than producing NaNs
+    let similar = |a: Position, b: Position| a.dot(&b) >= min_cos * a.length() * b.length() - f32::EPSILON;
+
+    let mut vertices = Vec::new();
+    let mut split: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+    let mut new_indices = Vec::with_capacity(indices.len());
+    for (face, tri) in indices.chunks_exact(3).enumerate() {
+        for &i in tri {
+            let sum = vertex_faces[i as usize]
+                .iter()
+                .filter(|&&other| similar(faces[face], faces[other]))
+                .fold(Position::default(), |sum, &other| sum + faces[other]);
+            let normal = normalize_or_up(sum);
+            let key = (i, [normal.x.to_bits(), normal.y.to_bits(), normal.z.to_bits()]);
+            let index = *split.entry(key).or_insert_with(|| {
+                vertices.push(Vertex { pos: positions[i as usize], normal, uv: [0., 0.] });
+                vertices.len() as u32 - 1
+            });
+            new_indices.push(index);
+        }
+    }
+    (vertices, new_indices)
+}
+
+/// De-indexes the mesh so that every triangle gets its own three vertices
+/// carrying the face normal, for hard-edged flat shading
+pub fn flat_vertices(positions: &[Position], indices: &[u32]) -> Vec<Vertex> {
+    indices
+        .chunks_exact(3)
+        .flat_map(|tri| {
+            let corners = [positions[tri[0] as usize], positions[tri[1] as usize], positions[tri[2] as usize]];
+            let normal = normalize_or_up(face_normal(corners[0], corners[1], corners[2]));
+            corners.map(|pos| Vertex { pos, normal, uv: [0., 0.] })
+        })
+        .collect()
+}
+
+/// The same mesh prepared for both shading models, so either can be picked
+/// per draw
+pub struct ShadingVariants {
+    /// Interleaved vertices with smooth normals, drawn with `smooth_indices`
+    pub smooth: Vec<Vertex>,
+    pub smooth_indices: Vec<u32>,
+    /// Non-indexed vertices with per-face normals, drawn with `drawArrays`
+    pub flat: Vec<Vertex>,
+}
+
+pub fn shading_variants(positions: &[Position], indices: &[u32]) -> ShadingVariants {
+    let smooth = positions
+        .iter()
+        .zip(smooth_normals(positions, indices))
+        .map(|(&pos, normal)| Vertex { pos, normal, uv: [0., 0.] })
+        .collect();
+    ShadingVariants {
+        smooth,
+        smooth_indices: indices.to_vec(),
+        flat: flat_vertices(positions, indices),
+    }
+}
+
+/// Shape of a single grass blade, which rises along +Y and leans towards +Z
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GrassBladeParams {
+    pub segments: u32,
+    pub height: f32,
+    /// Half the width of the blade at its base
+    pub base_width: f32,
+    /// Fraction of the remaining height each segment climbs, so smaller
+    /// values bend the blade over more
+    pub curvature: f32,
+}
+
+impl Default for GrassBladeParams {
+    fn default() -> Self {
+        GrassBladeParams { segments: 7, height: 0.7, base_width: 0.03, curvature: 0.3 }
+    }
+}
+
+/// A tapering strip of `params.segments` quads ending in a point, facing -Z.
+/// The normals follow the bend of the blade's spine.
+pub fn grass_blade(params: GrassBladeParams) -> (Vec<Vertex>, Vec<u32>) {
+    let segments = params.segments.max(1);
+    // Height and forward lean of each row, the last being the tip
+    let lean = params.height / segments as f32;
+    let mut spine = Vec::with_capacity(segments as usize + 1);
+    let mut height = 0.;
+    for i in 0..=segments {
+        spine.push((height, lean * i as f32));
+        height += (params.height - height) * params.curvature;
+    }
+    let normal = |i: usize| {
+        let (y0, z0) = spine[i.saturating_sub(1)];
+        let (y1, z1) = spine[(i + 1).min(spine.len() - 1)];
+        // Perpendicular to the spine's tangent, on its -Z side
+        normalize_or_up(Position { x: 0., y: z1 - z0, z: -(y1 - y0) })
+    };
+
+    let mut vertices = Vec::with_capacity(segments as usize * 2 + 1);
+    let mut indices = Vec::with_capacity(segments as usize * 6);
+    let mut width = params.base_width;
+    for (i, &(y, z)) in spine[..segments as usize].iter().enumerate() {
+        for x in [-width, width] {
+            vertices.push(Vertex { pos: Position { x, y, z }, normal: normal(i), uv: [0., 0.] });
+        }
+        if i > 0 {
+            let (l0, r0, l1, r1) = (2 * i as u32 - 2, 2 * i as u32 - 1, 2 * i as u32, 2 * i as u32 + 1);
+            indices.extend_from_slice(&[l0, l1, r0, r0, l1, r1]);
+        }
+        width -= width * i as f32 * 2. / (segments * segments) as f32;
+    }
+    let (y, z) = spine[segments as usize];
+    vertices.push(Vertex { pos: Position { x: 0., y, z }, normal: normal(segments as usize), uv: [0., 0.] });
+    let tip = vertices.len() as u32 - 1;
+    indices.extend_from_slice(&[tip - 2, tip, tip - 1]);
+    (vertices, indices)
+}
+
+/// Deterministic splitmix64, so scattered geometry is the same every load
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// A blade mesh and the per-instance model matrices scattering it
+pub struct GrassField {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    /// For the `instanceModel` attribute, drawn with one instance each
+    pub instances: Vec<Matrix4<f32>>,
+}
+
+/// Scatters `count` blades uniformly over an `area` (X by Z) rectangle
+/// centered on the origin, each with a random yaw and a scale in `0.75..1.25`
+pub fn grass_field(params: GrassBladeParams, count: usize, area: (f32, f32), seed: u64) -> GrassField {
+    let (vertices, indices) = grass_blade(params);
+    let mut rng = Rng(seed);
+    let instances = (0..count)
+        .map(|_| {
+            let offset = Vector3::new((rng.next_f32() - 0.5) * area.0, 0., (rng.next_f32() - 0.5) * area.1);
+            let yaw = rng.next_f32() * TAU;
+            let scale = 0.75 + rng.next_f32() * 0.5;
+            Matrix4::new_translation(&offset)
+                * Matrix4::from_euler_angles(0., yaw, 0.)
+                * Matrix4::new_scaling(scale)
+        })
+        .collect();
+    GrassField { vertices, indices, instances }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub fn component(self, p: &Position) -> f32 {
+        match self {
+            Axis::X => p.x,
+            Axis::Y => p.y,
+            Axis::Z => p.z,
+        }
+    }
+}
+
+/// Samples a gradient of `(key, color)` stops, which must be sorted by key.
+/// Values outside the keyed range take the nearest end color.
+pub fn sample_gradient(stops: &[(f32, Color)], value: f32) -> Option<Color> {
+    let (first, last) = (stops.first()?, stops.last()?);
+    if value <= first.0 {
+        return Some(first.1);
+    }
+    let upper = stops.iter().position(|&(key, _)| key >= value);
+    Some(match upper {
+        Some(i) => {
+            let ((k0, c0), (k1, c1)) = (stops[i - 1], stops[i]);
+            c0.lerp(&c1, (value - k0) / (k1 - k0))
+        }
+        None => last.1,
+    })
+}
+
+/// Colors each vertex by sampling `stops` at its position along `axis`,
+/// e.g. `Axis::Y` for height-colored terrain. Does nothing without stops.
+pub fn apply_axis_gradient(vertices: &mut [ColoredVertex], axis: Axis, stops: &[(f32, Color)]) {
+    for vertex in vertices {
+        if let Some(color) = sample_gradient(stops, axis.component(&vertex.pos)) {
+            vertex.color = color;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(x: f32, y: f32, z: f32) -> Position {
+        Position { x, y, z }
+    }
+
+    /// Two triangles folded 90 degrees along the X axis
+    fn fold() -> (Vec<Position>, Vec<u32>) {
+        (
+            vec![p(0., 0., 0.), p(1., 0., 0.), p(0., 0., -1.), p(0., 1., 0.)],
+            vec![0, 1, 2, 0, 1, 3],
+        )
+    }
+
+    #[test]
+    fn smooth_normals_average_faces() {
+        let (positions, indices) = fold();
+        let normals = smooth_normals(&positions, &indices);
+        let diagonal = p(0., 1., 1.).normalize();
+        assert!((normals[0] - diagonal).length() < 1e-6);
+        assert!((normals[1] - diagonal).length() < 1e-6);
+        assert!((normals[2] - p(0., 1., 0.)).length() < 1e-6);
+        assert!((normals[3] - p(0., 0., 1.)).length() < 1e-6);
+    }
+
+    #[test]
+    fn shared_cube_corners_get_diagonal_normals() {
+        let mut vertices: Vec<Vertex> = (0..8)
+            .map(|i| Vertex {
+                pos: p(if i & 1 == 0 { -1. } else { 1. }, if i & 2 == 0 { -1. } else { 1. }, if i & 4 == 0 { -1. } else { 1. }),
+                ..Vertex::default()
+            })
+            .collect();
+        // Counter-clockwise from outside, each quad split along a different
+        // diagonal so some corners touch two triangles of a face
+        let quads = [[0, 2, 3, 1], [4, 5, 7, 6], [0, 1, 5, 4], [2, 6, 7, 3], [0, 4, 6, 2], [1, 3, 7, 5]];
+        let indices: Vec<u32> = quads.iter().flat_map(|&[a, b, c, d]| [a, b, c, a, c, d]).collect();
+        compute_smooth_normals(&mut vertices, &indices);
+        for v in &vertices {
+            let diagonal = v.pos.normalize();
+            assert!((v.normal - diagonal).length() < 1e-5, "{:?}", v);
+        }
+    }
+
+    #[test]
+    fn quad_tangents_follow_u() {
+        // A quad facing +Y whose U runs along -Z, with the normals tilted so
+        // the tangent has to be orthogonalized
+        let normal = p(0., 1., 0.2).normalize();
+        let corners = [p(0., 0., 0.), p(0., 0., -1.), p(1., 0., -1.), p(1., 0., 0.)];
+        let uvs = [[0., 0.], [1., 0.], [1., 1.], [0., 1.]];
+        let mut vertices: Vec<Vertex> = corners.iter().zip(uvs).map(|(&pos, uv)| Vertex { pos, normal, uv }).collect();
+        let tangents = compute_tangents(&vertices, &[0, 1, 2, 0, 2, 3]);
+        for v in &tangents {
+            let expected = p(0., 0.2, -1.).normalize();
+            assert!((v.tangent - expected).length() < 1e-5, "{:?}", v);
+            assert!(v.tangent.dot(&v.normal).abs() < 1e-6);
+            // V runs along +X, the opposite of normal x tangent
+            assert_eq!(v.bitangent_sign, -1.);
+        }
+        assert_eq!(tangents[2].uv, [1., 1.]);
+
+        // Mirroring U flips the tangent but not the bitangent
+        let mirrored: Vec<Vertex> = vertices.iter().map(|&v| Vertex { uv: [1. - v.uv[0], v.uv[1]], ..v }).collect();
+        for v in compute_tangents(&mirrored, &[0, 1, 2, 0, 2, 3]) {
+            assert!((v.tangent + p(0., 0.2, -1.).normalize()).length() < 1e-5, "{:?}", v);
+            assert_eq!(v.bitangent_sign, 1.);
+        }
+
+        // Without usable UVs there's still a perpendicular unit tangent
+        for v in &mut vertices {
+            v.uv = [0., 0.];
+        }
+        let tangents = compute_tangents(&vertices, &[0, 1, 2]);
+        assert!((tangents[3].tangent.length() - 1.).abs() < 1e-6);
+        assert!(tangents[3].tangent.dot(&normal).abs() < 1e-6);
+    }
+
+    #[test]
+    fn flat_vertices_use_face_normals() {
+        let (positions, indices) = fold();
+        let flat = flat_vertices(&positions, &indices);
+        assert_eq!(flat.len(), 6);
+        assert!(flat[..3].iter().all(|v| v.normal == p(0., 1., 0.)));
+        assert!(flat[3..].iter().all(|v| v.normal == p(0., 0., 1.)));
+    }
+
+    #[test]
+    fn degenerate_triangles_have_no_nans() {
+        let positions = vec![p(0., 0., 0.), p(1., 0., 0.), p(2., 0., 0.)];
+        let variants = shading_variants(&positions, &[0, 1, 2]);
+        assert!(variants.smooth.iter().chain(&variants.flat).all(|v| !v.normal.x.is_nan()));
+    }
+
+    /// A unit cube sharing its 8 corners between faces, wound outward.
+    /// Corner `i` has bit 0, 1 and 2 set for +X, +Y and +Z.
+    fn shared_cube() -> (Vec<Position>, Vec<u32>) {
+        let corners = (0..8)
+            .map(|i| {
+                let coord = |bit: u32| if i & (1 << bit) != 0 { 1. } else { -1. };
+                p(coord(0), coord(1), coord(2))
+            })
+            .collect();
+        let indices = vec![
+            1, 3, 7, 1, 7, 5, // +X
+            0, 6, 2, 0, 4, 6, // -X
+            2, 7, 3, 2, 6, 7, // +Y
+            0, 1, 5, 0, 5, 4, // -Y
+            4, 5, 7, 4, 7, 6, // +Z
+            0, 3, 1, 0, 2, 3, // -Z
+        ];
+        (corners, indices)
+    }
+
+    #[test]
+    fn max_angle_keeps_cube_edges_hard() {
+        let (positions, indices) = shared_cube();
+        let (vertices, new_indices) = normals_with_max_angle(&positions, &indices, 30f32.to_radians());
+        // Each corner splits into one vertex per adjoining face
+        assert_eq!(vertices.len(), 24);
+        assert_eq!(new_indices.len(), indices.len());
+        for v in &vertices {
+            let axis_aligned = [v.normal.x, v.normal.y, v.normal.z].iter().filter(|c| c.abs() == 1.).count();
+            assert_eq!(axis_aligned, 1, "{:?}", v);
+            assert!(v.pos.dot(&v.normal) > 0.);
+        }
+        for (&old, &new) in indices.iter().zip(&new_indices) {
+            assert_eq!(positions[old as usize], vertices[new as usize].pos);
+        }
+    }
+
+    #[test]
+    fn max_angle_of_pi_smooths_everything() {
+        let (positions, indices) = shared_cube();
+        let (vertices, _) = normals_with_max_angle(&positions, &indices, std::f32::consts::PI);
+        assert_eq!(vertices.len(), 8);
+        let smooth = smooth_normals(&positions, &indices);
+        for v in &vertices {
+            let corner = positions.iter().position(|&c| c == v.pos).unwrap();
+            assert!((v.normal - smooth[corner]).length() < 1e-6);
+            assert!(v.pos.dot(&v.normal) > 0.);
+        }
+    }
+
+    #[test]
+    fn max_angle_shares_vertices_across_flat_planes() {
+        let (plane, indices) = primitives::plane(2., 2., 4, 4);
+        let positions: Vec<_> = plane.iter().map(|v| v.pos).collect();
+        let (vertices, _) = normals_with_max_angle(&positions, &indices, 1f32.to_radians());
+        assert_eq!(vertices.len(), positions.len());
+        assert!(vertices.iter().all(|v| v.normal == p(0., 1., 0.)));
+    }
+
+    #[test]
+    fn max_angle_survives_degenerate_triangles() {
+        let positions = vec![p(0., 0., 0.), p(1., 0., 0.), p(2., 0., 0.), p(0., 0., -1.)];
+        let (vertices, _) = normals_with_max_angle(&positions, &[0, 1, 2, 0, 1, 3], 0.5);
+        assert!(vertices.iter().all(|v| !v.normal.x.is_nan() && (v.normal.length() - 1.).abs() < 1e-6));
+    }
+
+    #[test]
+    fn grass_blade_normals_face_its_winding() {
+        let params = GrassBladeParams::default();
+        let (vertices, indices) = grass_blade(params);
+        assert_eq!(vertices.len(), 2 * 7 + 1);
+        assert_eq!(indices.len(), 6 * 6 + 3);
+        for tri in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| vertices[tri[i] as usize]);
+            let face = face_normal(a.pos, b.pos, c.pos);
+            assert!(face.dot(&a.normal) > 0., "{:?} winds against its normal", tri);
+        }
+        assert!(vertices.iter().all(|v| (v.normal.length() - 1.).abs() < 1e-5 && v.normal.z < 0.));
+        assert!(vertices.iter().all(|v| v.pos.y < params.height));
+    }
+
+    #[test]
+    fn grass_field_is_seeded_and_inside_its_area() {
+        let field = grass_field(GrassBladeParams::default(), 500, (4., 2.), 7);
+        assert_eq!(field.instances.len(), 500);
+        for m in &field.instances {
+            assert!(m[(0, 3)].abs() <= 2. && m[(2, 3)].abs() <= 1. && m[(1, 3)] == 0.);
+            let scale = m.fixed_view::<3, 1>(0, 0).norm();
+            assert!((0.75..1.25).contains(&scale), "{}", scale);
+        }
+        let again = grass_field(GrassBladeParams::default(), 500, (4., 2.), 7);
+        assert_eq!(field.instances, again.instances);
+        let other = grass_field(GrassBladeParams::default(), 500, (4., 2.), 8);
+        assert_ne!(field.instances, other.instances);
+    }
+
+    #[test]
+    fn axis_gradient_interpolates_and_clamps() {
+        let black = Color::default();
+        let white = Color { r: 1., g: 1., b: 1., a: 1. };
+        let stops = [(0., black), (2., white)];
+        let mut vertices: Vec<_> = [-1., 0., 0.5, 2., 3.]
+            .iter()
+            .map(|&y| ColoredVertex { pos: p(0., y, 0.), ..Default::default() })
+            .collect();
+        apply_axis_gradient(&mut vertices, Axis::Y, &stops);
+        let reds: Vec<_> = vertices.iter().map(|v| v.color.r).collect();
+        assert_eq!(reds, [0., 0., 0.25, 1., 1.]);
+    }
+
+    #[test]
+    fn gradient_picks_the_enclosing_stops() {
+        let red = Color { r: 1., g: 0., b: 0., a: 1. };
+        let green = Color { r: 0., g: 1., b: 0., a: 1. };
+        let blue = Color { r: 0., g: 0., b: 1., a: 1. };
+        let stops = [(0., red), (1., green), (3., blue)];
+        assert_eq!(sample_gradient(&stops, 1.), Some(green));
+        assert_eq!(sample_gradient(&stops, 2.), Some(green.lerp(&blue, 0.5)));
+        assert_eq!(sample_gradient(&[], 2.), None);
+    }
+}
@@ -0,0 +1,295 @@
+//! Wavefront OBJ loading
+
+use std::collections::HashMap;
+use std::fmt;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::Response;
+
+use super::{face_normal, normalize_or_up};
+use crate::{Position, Vertex};
+
+/// An indexed triangle mesh
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MeshData {
+    /// Vertices without texture coordinates in the file get `[0, 0]`
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+/// Why an OBJ file couldn't be parsed, and on which (1-based) line
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "OBJ line {}: {}", self.line, self.message)
+    }
+}
+
+impl From<ObjError> for JsValue {
+    fn from(err: ObjError) -> JsValue {
+        JsValue::from_str(&err.to_string())
+    }
+}
+
+/// The first `N` of `fields`, at least `min` of which must be present.
+/// Missing ones are 0, and extra ones, such as `w` or the vertex colors some
+/// exporters append to `v`, are ignored.
+fn parse_floats<const N: usize>(fields: &[&str], min: usize) -> Result<[f32; N], String> {
+    if fields.len() < min {
+        let plural = if min == 1 { "" } else { "s" };
+        return Err(format!("Expected at least {min} number{plural}, found {}", fields.len()));
+    }
+    let mut values = [0.; N];
+    for (value, field) in values.iter_mut().zip(fields) {
+        *value = field.parse().map_err(|_| format!("Invalid number `{field}`"))?;
+    }
+    Ok(values)
+}
+
+/// Resolves a 1-based, or negative from the end, OBJ index into `0..count`
+fn resolve_index(field: &str, count: usize) -> Result<usize, String> {
+    let index: i64 = field.parse().map_err(|_| format!("Invalid index `{field}`"))?;
+    let resolved = match index {
+        0 => None,
+        i if i > 0 => Some(i - 1),
+        i => Some(count as i64 + i),
+    };
+    match resolved {
+        Some(i) if (0..count as i64).contains(&i) => Ok(i as usize),
+        _ => Err(format!("Index {index} is out of range for {count} elements")),
+    }
+}
+
+/// `v`, `v/vt`, `v//vn` or `v/vt/vn`, as indices into the element lists
+type Corner = (usize, Option<usize>, Option<usize>);
+
+fn parse_corner(field: &str, counts: (usize, usize, usize)) -> Result<Corner, String> {
+    let parts: Vec<&str> = field.split('/').collect();
+    if parts.len() > 3 {
+        return Err(format!("Malformed face vertex `{field}`"));
+    }
+    let optional = |part: Option<&&str>, count| match part {
+        None | Some(&"") => Ok(None),
+        Some(part) => resolve_index(part, count).map(Some),
+    };
+    Ok((resolve_index(parts[0], counts.0)?, optional(parts.get(1), counts.1)?, optional(parts.get(2), counts.2)?))
+}
+
+/// Parses the `v`, `vt`, `vn` and `f` records of an OBJ file, ignoring
+/// others such as groups and materials. Faces with more than three vertices
+/// are fanned into triangles, and vertices are shared between faces wherever
+/// they use the same position, UV and normal. Corners without a normal get
+/// their face's, so such faces are flat shaded.
+pub fn parse_obj(text: &str) -> Result<MeshData, ObjError> {
+    let mut positions = Vec::new();
+    let mut uvs = Vec::new();
+    let mut normals = Vec::new();
+    let mut corners: Vec<Corner> = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let error = |message: String| ObjError { line: i + 1, message };
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let Some(keyword) = fields.next() else { continue };
+        let fields: Vec<&str> = fields.collect();
+        match keyword {
+            "v" => {
+                let [x, y, z] = parse_floats(&fields, 3).map_err(error)?;
+                positions.push(Position { x, y, z });
+            }
+            "vt" => {
+                let [u, v] = parse_floats(&fields, 1).map_err(error)?;
+                uvs.push([u, v]);
+            }
+            "vn" => {
+                let [x, y, z] = parse_floats(&fields, 3).map_err(error)?;
+                normals.push(Position { x, y, z });
+            }
+            "f" => {
+                if fields.len() < 3 {
+                    return Err(error(format!("A face needs at least 3 vertices, found {}", fields.len())));
+                }
+                let counts = (positions.len(), uvs.len(), normals.len());
+                let mut face = fields
+                    .iter()
+                    .map(|field| parse_corner(field, counts))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(error)?;
+                if face.iter().any(|&(_, _, n)| n.is_none()) {
+                    let normal = (1..face.len() - 1)
+                        .map(|j| face_normal(positions[face[0].0], positions[face[j].0], positions[face[j + 1].0]))
+                        .fold(Position::default(), |sum, n| sum + n);
+                    normals.push(normalize_or_up(normal));
+                    for corner in &mut face {
+                        corner.2 = corner.2.or(Some(normals.len() - 1));
+                    }
+                }
+                for j in 1..face.len() - 1 {
+                    corners.extend_from_slice(&[face[0], face[j], face[j + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut mesh = MeshData::default();
+    let mut shared: HashMap<Corner, u32> = HashMap::new();
+    for corner in corners {
+        let index = *shared.entry(corner).or_insert_with(|| {
+            let (p, uv, n) = corner;
+            mesh.vertices.push(Vertex {
+                pos: positions[p],
+                normal: n.map_or_else(Position::default, |n| normals[n]),
+                uv: uv.map_or([0., 0.], |uv| uvs[uv]),
+            });
+            mesh.vertices.len() as u32 - 1
+        });
+        mesh.indices.push(index);
+    }
+    Ok(mesh)
+}
+
+/// Fetches and parses an OBJ file
+pub async fn load_obj(url: &str) -> Result<MeshData, JsValue> {
+    let window = web_sys::window().unwrap();
+    let response: Response = JsFuture::from(window.fetch_with_str(url)).await?.dyn_into()?;
+    if !response.ok() {
+        return Err(JsValue::from_str(&format!("Failed to load {url}: HTTP {}", response.status())));
+    }
+    let text = JsFuture::from(response.text()?).await?;
+    let text = text
+        .as_string()
+        .ok_or_else(|| JsValue::from_str(&format!("Failed to load {url}: the body is not text")))?;
+    Ok(parse_obj(&text)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD: &str = "
+        # A unit quad facing +Z
+        o Quad
+        v 0 0 0
+        v 1 0 0
+        v 1 1 0
+        v 0 1 0
+        vt 0 0
+        vt 1 0
+        vt 1 1
+        vt 0 1
+        vn 0 0 1
+        s off
+        f 1/1/1 2/2/1 3/3/1 4/4/1
+    ";
+
+    #[test]
+    fn quads_are_fanned_and_shared() {
+        let mesh = parse_obj(QUAD).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.vertices[2].uv, [1., 1.]);
+        assert!(mesh.vertices.iter().all(|v| v.normal == Position { x: 0., y: 0., z: 1. }));
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_end() {
+        let mesh = parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf -3//-1 -2//-1 -1//-1\n").unwrap();
+        assert_eq!(mesh.indices, [0, 1, 2]);
+        assert_eq!(mesh.vertices[1].pos, Position { x: 1., y: 0., z: 0. });
+        assert!(mesh.vertices.iter().all(|v| v.uv == [0., 0.]));
+    }
+
+    #[test]
+    fn vertices_split_on_differing_attributes() {
+        let text = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\nvn 0 0 1\nvn 0 0 -1\nf 1//1 2//1 3//1\nf 2//2 4//2 3//2\n";
+        let mesh = parse_obj(text).unwrap();
+        assert_eq!(mesh.vertices.len(), 6);
+    }
+
+    #[test]
+    fn missing_normals_are_computed() {
+        let mesh = parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        assert!(mesh.vertices.iter().all(|v| v.normal == Position { x: 0., y: 0., z: 1. }));
+    }
+
+    const CUBE: &str = "
+        v -1 -1 -1
+        v 1 -1 -1
+        v 1 1 -1
+        v -1 1 -1
+        v -1 -1 1
+        v 1 -1 1
+        v 1 1 1
+        v -1 1 1
+        f 5 6 7 8
+        f 2 1 4 3
+        f 6 2 3 7
+        f 1 5 8 4
+        f 8 7 3 4
+        f 1 2 6 5
+    ";
+
+    #[test]
+    fn cube_without_normals_is_flat_shaded() {
+        let mesh = parse_obj(CUBE).unwrap();
+        assert_eq!(mesh.indices.len(), 36);
+        // Corners aren't shared between faces with different normals
+        assert_eq!(mesh.vertices.len(), 24);
+        for v in &mesh.vertices {
+            let axis = [v.normal.x, v.normal.y, v.normal.z];
+            assert_eq!(axis.iter().filter(|c| c.abs() == 1.).count(), 1, "{:?}", v);
+            assert!(v.pos.dot(&v.normal) > 0., "{:?} points inwards", v);
+        }
+    }
+
+    #[test]
+    fn cube_with_normals_keeps_them() {
+        let text = CUBE.replace("f 5 6 7 8", "vn 0 0 1\nf 5//1 6//1 7//1 8//1");
+        let mesh = parse_obj(&text).unwrap();
+        assert_eq!(mesh.vertices.len(), 24);
+        assert!(mesh.vertices[..4].iter().all(|v| v.normal == Position { x: 0., y: 0., z: 1. }));
+        let err = parse_obj(&text.replace("7//1", "7//2")).unwrap_err();
+        assert_eq!(err.line, 11);
+    }
+
+    #[test]
+    fn extra_numbers_are_ignored() {
+        let mesh = parse_obj("v 0 0 0 1 0 0\nv 1 0 0 0 1 0\nv 0 1 0 1 0 0 0\nvt 0.5 1 0\nf 1/1 2/1 3/1\n").unwrap();
+        assert_eq!(mesh.vertices[2].pos, Position { x: 0., y: 1., z: 0. });
+        assert_eq!(mesh.vertices[2].uv, [0.5, 1.]);
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        let cases = [
+            ("v 0 0\n", 1, "Expected at least 3 numbers, found 2"),
+            ("v 0 0 0\nvt\n", 2, "Expected at least 1 number, found 0"),
+            ("v 0 0 zero\n", 1, "Invalid number"),
+            ("v 0 0 0\nv 1 0 0\nf 1 2\n", 3, "at least 3"),
+            ("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n", 4, "out of range"),
+            ("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 0 1 2\n", 4, "out of range"),
+            ("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -4 1 2\n", 4, "out of range"),
+            ("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/1 2/1 3/1\n", 4, "out of range"),
+            ("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/x 2 3\n", 4, "Invalid index"),
+            ("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/1/1/1 2 3\n", 4, "Malformed"),
+        ];
+        for (text, line, message) in cases {
+            let err = parse_obj(text).unwrap_err();
+            assert_eq!(err.line, line, "{:?}", text);
+            assert!(err.message.contains(message), "{:?}: {}", text, err);
+        }
+    }
+
+    #[test]
+    fn empty_and_comment_only_files_are_empty() {
+        assert_eq!(parse_obj("").unwrap(), MeshData::default());
+        assert_eq!(parse_obj("# nothing\n\nmtllib x.mtl\n").unwrap(), MeshData::default());
+    }
+}
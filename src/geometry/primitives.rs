@@ -0,0 +1,301 @@
+//! Procedural meshes, wound counter-clockwise when viewed from outside
+
+use std::f32::consts::{PI, TAU};
+
+use crate::{Position, Vertex};
+
+/// Indices for a `(cols + 1) x (rows + 1)` grid of vertices laid out row by
+/// row, two triangles per cell
+fn grid_indices(cols: u32, rows: u32) -> Vec<u32> {
+    let stride = cols + 1;
+    let mut indices = Vec::with_capacity((cols * rows * 6) as usize);
+    for row in 0..rows {
+        for col in 0..cols {
+            let a = row * stride + col;
+            let b = a + stride;
+            indices.extend_from_slice(&[a, a + 1, b, a + 1, b + 1, b]);
+        }
+    }
+    indices
+}
+
+fn p(x: f32, y: f32, z: f32) -> Position {
+    Position { x, y, z }
+}
+
+/// A `width` (X) by `depth` (Z) plane facing +Y, centered on the origin and
+/// split into `cols` cells along X and `rows` along Z, e.g. for displacing.
+/// UVs span `0..1` over the whole plane.
+pub fn plane(width: f32, depth: f32, cols: u32, rows: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let (cols, rows) = (cols.max(1), rows.max(1));
+    let mut vertices = Vec::with_capacity(((cols + 1) * (rows + 1)) as usize);
+    // Rows run towards -Z so the grid winds counter-clockwise from above
+    for row in 0..=rows {
+        let v = row as f32 / rows as f32;
+        for col in 0..=cols {
+            let u = col as f32 / cols as f32;
+            vertices.push(Vertex {
+                pos: p((u - 0.5) * width, 0., (0.5 - v) * depth),
+                normal: p(0., 1., 0.),
+                uv: [u, v],
+            });
+        }
+    }
+    (vertices, grid_indices(cols, rows))
+}
+
+/// An axis-aligned cube centered on the origin, with separate vertices per
+/// face for hard edges. Each face maps the whole texture.
+pub fn cube(size: f32) -> (Vec<Vertex>, Vec<u32>) {
+    // Normal, then the face's U and V axes, with U x V = normal
+    let faces = [
+        (p(1., 0., 0.), p(0., 0., -1.), p(0., 1., 0.)),
+        (p(-1., 0., 0.), p(0., 0., 1.), p(0., 1., 0.)),
+        (p(0., 1., 0.), p(1., 0., 0.), p(0., 0., -1.)),
+        (p(0., -1., 0.), p(1., 0., 0.), p(0., 0., 1.)),
+        (p(0., 0., 1.), p(1., 0., 0.), p(0., 1., 0.)),
+        (p(0., 0., -1.), p(-1., 0., 0.), p(0., 1., 0.)),
+    ];
+    let half = size / 2.;
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for (normal, u_axis, v_axis) in faces {
+        let base = vertices.len() as u32;
+        for [u, v] in [[0., 0.], [1., 0.], [1., 1.], [0., 1.]] {
+            let pos = (normal + u_axis * (u * 2. - 1.) + v_axis * (v * 2. - 1.)) * half;
+            vertices.push(Vertex { pos, normal, uv: [u, v] });
+        }
+        indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+    (vertices, indices)
+}
+
+/// A sphere centered on the origin, split into `rings` bands of latitude and
+/// `segments` of longitude. U wraps around the Y axis and V runs from the
+/// south pole to the north.
+pub fn uv_sphere(radius: f32, rings: u32, segments: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let (rings, segments) = (rings.max(2), segments.max(3));
+    let mut vertices = Vec::with_capacity(((rings + 1) * (segments + 1)) as usize);
+    // Rows run from the north pole down, matching `torus`'s winding
+    for ring in 0..=rings {
+        let (sp, cp) = (PI * ring as f32 / rings as f32).sin_cos();
+        for segment in 0..=segments {
+            let (st, ct) = (TAU * segment as f32 / segments as f32).sin_cos();
+            let normal = p(sp * ct, cp, sp * st);
+            vertices.push(Vertex {
+                pos: normal * radius,
+                normal,
+                uv: [segment as f32 / segments as f32, 1. - ring as f32 / rings as f32],
+            });
+        }
+    }
+    (vertices, grid_indices(segments, rings))
+}
+
+/// A unit [`uv_sphere`], whose normals are exactly its positions, as a
+/// reference shape for lighting and shadows. There are `(rings + 1) *
+/// (sectors + 1)` vertices, for at least 2 rings and 3 sectors.
+pub fn sphere(rings: u32, sectors: u32) -> (Vec<Vertex>, Vec<u32>) {
+    uv_sphere(1., rings, sectors)
+}
+
+/// A capped cylinder around the Y axis, centered on the origin. The side
+/// wraps U around the axis; the caps map the texture as a disc.
+pub fn cylinder(radius: f32, height: f32, segments: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let segments = segments.max(3);
+    let half = height / 2.;
+    let angles: Vec<(f32, f32)> = (0..=segments)
+        .map(|segment| (TAU * segment as f32 / segments as f32).sin_cos())
+        .collect();
+    let mut vertices = Vec::new();
+    for (y, v) in [(half, 1.), (-half, 0.)] {
+        for (segment, &(s, c)) in angles.iter().enumerate() {
+            vertices.push(Vertex {
+                pos: p(c * radius, y, s * radius),
+                normal: p(c, 0., s),
+                uv: [segment as f32 / segments as f32, v],
+            });
+        }
+    }
+    let mut indices = grid_indices(segments, 1);
+    for y in [half, -half] {
+        let normal = p(0., y.signum(), 0.);
+        let center = vertices.len() as u32;
+        vertices.push(Vertex { pos: p(0., y, 0.), normal, uv: [0.5, 0.5] });
+        for &(s, c) in &angles[..segments as usize] {
+            vertices.push(Vertex {
+                pos: p(c * radius, y, s * radius),
+                normal,
+                uv: [0.5 + c * 0.5, 0.5 + s * 0.5],
+            });
+        }
+        for segment in 0..segments {
+            let a = center + 1 + segment;
+            let b = center + 1 + (segment + 1) % segments;
+            // Going around by increasing angle is clockwise seen from above
+            if y > 0. {
+                indices.extend_from_slice(&[center, b, a]);
+            } else {
+                indices.extend_from_slice(&[center, a, b]);
+            }
+        }
+    }
+    (vertices, indices)
+}
+
+/// A torus around the Y axis. The seams are duplicated, so there are
+/// `(major_segments + 1) * (minor_segments + 1)` vertices. U wraps around the
+/// Y axis and V around the tube. Both segment counts are at least 3.
+pub fn torus(major_radius: f32, minor_radius: f32, major_segments: u32, minor_segments: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let (major_segments, minor_segments) = (major_segments.max(3), minor_segments.max(3));
+    let mut vertices = Vec::with_capacity(((major_segments + 1) * (minor_segments + 1)) as usize);
+    for i in 0..=major_segments {
+        let (su, cu) = (TAU * i as f32 / major_segments as f32).sin_cos();
+        for j in 0..=minor_segments {
+            let (sv, cv) = (TAU * j as f32 / minor_segments as f32).sin_cos();
+            let normal = Position { x: cv * cu, y: sv, z: cv * su };
+            let center = Position { x: major_radius * cu, y: 0., z: major_radius * su };
+            vertices.push(Vertex {
+                pos: center + normal * minor_radius,
+                normal,
+                uv: [i as f32 / major_segments as f32, j as f32 / minor_segments as f32],
+            });
+        }
+    }
+    (vertices, grid_indices(minor_segments, major_segments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every triangle's winding should agree with its vertex normals
+    fn assert_outward(vertices: &[Vertex], indices: &[u32]) {
+        for tri in indices.chunks_exact(3) {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| vertices[i as usize]);
+            let face = (b.pos - a.pos).cross(&(c.pos - a.pos));
+            if face.length() > 1e-6 {
+                assert!(face.dot(&(a.normal + b.normal + c.normal)) > 0., "{:?} winds inward", tri);
+            }
+        }
+    }
+
+    #[test]
+    fn torus_vertices_on_surface() {
+        let (r_major, r_minor) = (2., 0.5);
+        let (vertices, indices) = torus(r_major, r_minor, 24, 12);
+        assert_eq!(vertices.len(), 25 * 13);
+        assert_eq!(indices.len(), 24 * 12 * 6);
+        for v in &vertices {
+            let ring = (v.pos.x * v.pos.x + v.pos.z * v.pos.z).sqrt() - r_major;
+            assert!((ring * ring + v.pos.y * v.pos.y - r_minor * r_minor).abs() < 1e-4);
+            assert!((v.normal.length() - 1.).abs() < 1e-5);
+        }
+        assert_outward(&vertices, &indices);
+    }
+
+    #[test]
+    fn torus_clamps_segments() {
+        let (vertices, indices) = torus(1., 0.25, 0, 1);
+        assert_eq!(vertices.len(), 4 * 4);
+        assert_eq!(indices.len(), 3 * 3 * 6);
+        assert!(vertices.iter().all(|v| v.pos.x.is_finite() && v.uv.iter().all(|uv| uv.is_finite())));
+        assert_unit_normals(&vertices);
+    }
+
+    fn assert_unit_normals(vertices: &[Vertex]) {
+        assert!(vertices.iter().all(|v| (v.normal.length() - 1.).abs() < 1e-5));
+    }
+
+    #[test]
+    fn plane_faces_up() {
+        let (vertices, indices) = plane(2., 4., 3, 5);
+        assert_eq!(vertices.len(), 4 * 6);
+        assert_eq!(indices.len(), 3 * 5 * 6);
+        assert!(vertices.iter().all(|v| v.pos.x.abs() <= 1. && v.pos.z.abs() <= 2.));
+        assert!(vertices.iter().all(|v| v.normal == p(0., 1., 0.)));
+        assert_outward(&vertices, &indices);
+    }
+
+    #[test]
+    fn plane_is_centered_with_unit_uvs() {
+        let (vertices, _) = plane(3., 1., 4, 2);
+        let sum = vertices.iter().fold(p(0., 0., 0.), |sum, v| sum + v.pos);
+        assert!(sum.length() < 1e-5, "{:?}", sum);
+        let (first, last) = (vertices[0], vertices[vertices.len() - 1]);
+        assert_eq!((first.uv, last.uv), ([0., 0.], [1., 1.]));
+        assert_eq!((first.pos, last.pos), (p(-1.5, 0., 0.5), p(1.5, 0., -0.5)));
+    }
+
+    #[test]
+    fn cube_faces_point_outward() {
+        let (vertices, indices) = cube(2.);
+        assert_eq!(vertices.len(), 24);
+        assert_eq!(indices.len(), 36);
+        for v in &vertices {
+            // Every corner sits on the face its normal points out of
+            assert!((v.pos.dot(&v.normal) - 1.).abs() < 1e-6, "{:?}", v);
+        }
+        assert_unit_normals(&vertices);
+        assert_outward(&vertices, &indices);
+    }
+
+    #[test]
+    fn cube_faces_have_their_own_vertices() {
+        let (vertices, indices) = cube(1.);
+        let mut normals: Vec<_> = vertices.iter().map(|v| (v.normal.x as i32, v.normal.y as i32, v.normal.z as i32)).collect();
+        normals.sort();
+        normals.dedup();
+        assert_eq!(normals.len(), 6);
+        // Each face's two triangles only use that face's four vertices, which
+        // all share its normal
+        for (face, quad) in indices.chunks_exact(6).enumerate() {
+            let normal = vertices[quad[0] as usize].normal;
+            for &i in quad {
+                assert_eq!(i as usize / 4, face, "{:?}", quad);
+                assert_eq!(vertices[i as usize].normal, normal);
+            }
+        }
+    }
+
+    #[test]
+    fn sphere_vertices_on_surface() {
+        let (vertices, indices) = uv_sphere(1.5, 8, 16);
+        assert_eq!(vertices.len(), 9 * 17);
+        assert_eq!(indices.len(), 8 * 16 * 6);
+        assert!(vertices.iter().all(|v| (v.pos.length() - 1.5).abs() < 1e-5));
+        assert_unit_normals(&vertices);
+        assert_outward(&vertices, &indices);
+    }
+
+    #[test]
+    fn unit_sphere_normals_are_positions() {
+        let (vertices, indices) = sphere(6, 10);
+        assert_eq!(vertices.len(), 7 * 11);
+        assert!(vertices.iter().all(|v| v.normal == v.pos));
+        assert_unit_normals(&vertices);
+        assert_outward(&vertices, &indices);
+    }
+
+    #[test]
+    fn cylinder_sides_and_caps_point_outward() {
+        let (vertices, indices) = cylinder(0.5, 2., 12);
+        // Two side rows with a seam, then a center and ring per cap
+        assert_eq!(vertices.len(), 2 * 13 + 2 * 13);
+        assert_eq!(indices.len(), 12 * 6 + 2 * 12 * 3);
+        assert!(vertices.iter().all(|v| v.pos.y.abs() <= 1.));
+        assert_unit_normals(&vertices);
+        assert_outward(&vertices, &indices);
+    }
+
+    #[test]
+    fn uvs_stay_within_the_texture() {
+        let (sphere, _) = uv_sphere(1., 4, 6);
+        assert_eq!(sphere[0].uv, [0., 1.]);
+        let (torus, _) = torus(1., 0.25, 8, 4);
+        assert_eq!(torus[torus.len() - 1].uv, [1., 1.]);
+        for vertices in [sphere, torus, cylinder(1., 1., 5).0, cube(1.).0] {
+            assert!(vertices.iter().all(|v| v.uv.iter().all(|c| (0. ..=1.).contains(c))));
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Polled keyboard and mouse state, fed by DOM event listeners
+
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{Event, EventTarget, KeyboardEvent, MouseEvent, WheelEvent};
+
+/// Input accumulated between frames. Keys are identified by
+/// `KeyboardEvent.code` (e.g. `"KeyW"`), so they follow physical layout, and
+/// mouse buttons by `MouseEvent.button`.
+///
+/// Deltas accumulate until [`InputState::end_frame`], which the render loop
+/// callback should call once it has read them.
+#[derive(Default, Debug)]
+pub struct InputState {
+    keys_down: HashSet<String>,
+    keys_pressed: HashSet<String>,
+    buttons_down: HashSet<i16>,
+    mouse_position: (f32, f32),
+    mouse_delta: (f32, f32),
+    wheel_delta: f32,
+}
+
+impl InputState {
+    /// Registers listeners updating a new state, which stay registered until
+    /// the returned handle is dropped. Mouse events are taken from `target`
+    /// (usually the canvas), with positions relative to it; keys, button
+    /// releases and focus loss are taken from the window so they aren't
+    /// missed when the pointer leaves `target`.
+    pub fn attach(target: &EventTarget) -> Result<InputHandle, JsValue> {
+        let window: EventTarget = web_sys::window().unwrap().into();
+        let mut handle = InputHandle {
+            state: Rc::new(RefCell::new(InputState::default())),
+            listeners: Vec::new(),
+        };
+
+        handle.listen(&window, "keydown", |state, event: KeyboardEvent| {
+            state.press_key(&event.code())
+        })?;
+        handle.listen(&window, "keyup", |state, event: KeyboardEvent| {
+            state.release_key(&event.code())
+        })?;
+        handle.listen(&window, "blur", |state, _: Event| state.clear_held())?;
+        handle.listen(target, "mousemove", |state, event: MouseEvent| {
+            state.move_mouse(
+                (event.offset_x() as f32, event.offset_y() as f32),
+                (event.movement_x() as f32, event.movement_y() as f32),
+            )
+        })?;
+        handle.listen(target, "mousedown", |state, event: MouseEvent| {
+            state.buttons_down.insert(event.button());
+        })?;
+        handle.listen(&window, "mouseup", |state, event: MouseEvent| {
+            state.buttons_down.remove(&event.button());
+        })?;
+        handle.listen(target, "wheel", |state, event: WheelEvent| {
+            state.wheel_delta += event.delta_y() as f32
+        })?;
+        Ok(handle)
+    }
+
+    pub fn is_key_down(&self, code: &str) -> bool {
+        self.keys_down.contains(code)
+    }
+
+    /// Whether the key went down since the last [`InputState::end_frame`],
+    /// for toggles. Key repeat doesn't count as another press.
+    pub fn was_key_pressed(&self, code: &str) -> bool {
+        self.keys_pressed.contains(code)
+    }
+
+    /// 0 is the main (usually left) button, 1 the middle and 2 the secondary
+    pub fn is_button_down(&self, button: i16) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    /// Last pointer position over the target, in CSS pixels
+    pub fn mouse_position(&self) -> (f32, f32) {
+        self.mouse_position
+    }
+
+    /// Pointer movement since the last [`InputState::end_frame`]
+    pub fn mouse_delta(&self) -> (f32, f32) {
+        self.mouse_delta
+    }
+
+    /// Vertical scroll since the last [`InputState::end_frame`], positive
+    /// when scrolling down
+    pub fn wheel_delta(&self) -> f32 {
+        self.wheel_delta
+    }
+
+    /// Resets the per-frame deltas and presses. Held keys and buttons are
+    /// kept.
+    pub fn end_frame(&mut self) {
+        self.keys_pressed.clear();
+        self.mouse_delta = (0., 0.);
+        self.wheel_delta = 0.;
+    }
+
+    fn press_key(&mut self, code: &str) {
+        if self.keys_down.insert(String::from(code)) {
+            self.keys_pressed.insert(String::from(code));
+        }
+    }
+
+    fn release_key(&mut self, code: &str) {
+        self.keys_down.remove(code);
+    }
+
+    fn move_mouse(&mut self, position: (f32, f32), movement: (f32, f32)) {
+        self.mouse_position = position;
+        self.mouse_delta.0 += movement.0;
+        self.mouse_delta.1 += movement.1;
+    }
+
+    /// Key and button releases are never delivered once the page loses
+    /// focus, so forget everything held to avoid stuck input
+    fn clear_held(&mut self) {
+        self.keys_down.clear();
+        self.buttons_down.clear();
+    }
+}
+
+type Listener = (EventTarget, &'static str, Closure<dyn FnMut(Event)>);
+
+/// Owns the listeners feeding an [`InputState`], removing them when dropped
+pub struct InputHandle {
+    state: Rc<RefCell<InputState>>,
+    listeners: Vec<Listener>,
+}
+
+impl InputHandle {
+    pub fn borrow(&self) -> Ref<'_, InputState> {
+        self.state.borrow()
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, InputState> {
+        self.state.borrow_mut()
+    }
+
+    fn listen<E: JsCast + 'static>(
+        &mut self,
+        target: &EventTarget,
+        event: &'static str,
+        mut handler: impl FnMut(&mut InputState, E) + 'static,
+    ) -> Result<(), JsValue> {
+        let state = self.state.clone();
+        let cb = Closure::<dyn FnMut(Event)>::new(move |event: Event| {
+            handler(&mut state.borrow_mut(), event.unchecked_into());
+        });
+        target.add_event_listener_with_callback(event, cb.as_ref().unchecked_ref())?;
+        self.listeners.push((target.clone(), event, cb));
+        Ok(())
+    }
+}
+
+impl Drop for InputHandle {
+    fn drop(&mut self) {
+        for (target, event, cb) in &self.listeners {
+            // Only fails for targets that aren't event targets at all
+            let _ = target.remove_event_listener_with_callback(event, cb.as_ref().unchecked_ref());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deltas_accumulate_until_end_frame() {
+        let mut input = InputState::default();
+        input.move_mouse((10., 10.), (3., -1.));
+        input.move_mouse((12., 8.), (2., -2.));
+        input.wheel_delta += 100.;
+        assert_eq!(input.mouse_position(), (12., 8.));
+        assert_eq!(input.mouse_delta(), (5., -3.));
+        input.end_frame();
+        assert_eq!(input.mouse_delta(), (0., 0.));
+        assert_eq!(input.wheel_delta(), 0.);
+        assert_eq!(input.mouse_position(), (12., 8.));
+    }
+
+    #[test]
+    fn blur_releases_held_input() {
+        let mut input = InputState::default();
+        input.press_key("KeyW");
+        input.press_key("ShiftLeft");
+        input.buttons_down.insert(0);
+        input.release_key("ShiftLeft");
+        assert!(input.is_key_down("KeyW"));
+        assert!(!input.is_key_down("ShiftLeft"));
+        input.end_frame();
+        assert!(input.is_key_down("KeyW"));
+        input.clear_held();
+        assert!(!input.is_key_down("KeyW"));
+        assert!(!input.is_button_down(0));
+    }
+
+    #[test]
+    fn presses_last_one_frame_and_ignore_repeats() {
+        let mut input = InputState::default();
+        input.press_key("KeyV");
+        input.press_key("KeyV");
+        assert!(input.was_key_pressed("KeyV"));
+        input.end_frame();
+        assert!(!input.was_key_pressed("KeyV"));
+        // Still held, so a repeat isn't a new press
+        input.press_key("KeyV");
+        assert!(!input.was_key_pressed("KeyV"));
+        input.release_key("KeyV");
+        input.press_key("KeyV");
+        assert!(input.was_key_pressed("KeyV"));
+    }
+}
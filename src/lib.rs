@@ -1,25 +1,47 @@
 #[macro_use]
-mod renderer;
+pub mod renderer;
+pub mod error;
+pub mod geometry;
+pub mod input;
+pub mod orbit;
+pub mod polyline;
+pub mod postprocess;
+pub mod scene;
+pub mod skybox;
+pub mod stats;
 mod utils;
 
 use std::collections::HashMap;
-use std::iter::FromIterator;
-use std::{borrow::Borrow, cmp::min, ops::Div};
+use std::ops::{Add, Mul, Sub};
 
-use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+use nalgebra::{Matrix4, Vector2, Vector3};
 use wasm_bindgen::prelude::*;
 use web_sys::{
-    WebGl2RenderingContext, Window,
+    HtmlCanvasElement, WebGl2RenderingContext, Window,
 };
 
+use crate::error::WasmGlError;
+use crate::geometry::primitives::plane;
+use crate::geometry::{apply_axis_gradient, grass_field, Axis, GrassBladeParams};
+use crate::input::InputState;
+use crate::orbit::OrbitController;
+use crate::postprocess::{DebugQuad, PostProcessChain, PostProcessPass, ScreenCorner};
+use crate::scene::{Material, Mesh, Scene};
+use crate::skybox::Skybox;
+use crate::stats::FrameStats;
 use crate::utils::set_panic_hook;
-use crate::renderer::{render_loop, Shader};
+use crate::renderer::{
+    render_loop, resize_canvas, BindCache, Camera, CascadedShadowMap, CubeMap, CullMode, DirectionalLight, FogConfig, FogMode, Light,
+    LightSet, NamedBuffers, OrThrow, FrameUniforms, PointShadow, RenderLoopHandle, Shader, ShadowMap, ToonConfig,
+    UniformBuffer, UpdateThrottle, VaoBuilder, VertexLayout, WindConfig,
+};
 
-#[derive(Default, Clone, Copy)]
-struct Position {
-    x: f32,
-    y: f32,
-    z: f32,
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
 }
 
 impl Position {
@@ -38,13 +60,42 @@ impl Position {
     }
 
     pub fn average(&self, other: &Position) -> Position {
+        self.midpoint(other)
+    }
+
+    pub fn midpoint(&self, other: &Position) -> Position {
         Position {
-            x: self.x + other.x / 2.,
-            y: self.y + other.y / 2.,
-            z: self.z + other.z / 2.,
+            x: (self.x + other.x) / 2.,
+            y: (self.y + other.y) / 2.,
+            z: (self.z + other.z) / 2.,
         }
     }
 
+    /// Linear interpolation, returning `self` at `t = 0` and `other` at `t = 1`
+    pub fn lerp(&self, other: &Position, t: f32) -> Position {
+        Position {
+            x: self.x + (other.x - self.x) * t,
+            y: self.y + (other.y - self.y) * t,
+            z: self.z + (other.z - self.z) * t,
+        }
+    }
+
+    pub fn dot(&self, other: &Position) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(&self, other: &Position) -> Position {
+        Position {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    pub fn length(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
     pub fn normalize(mut self) -> Self {
         let mag = (self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
         self.x /= mag;
@@ -54,287 +105,708 @@ impl Position {
     }
 }
 
-#[derive(Default, Clone, Copy)]
-struct Color {
-    r: f32,
-    g: f32,
-    b: f32,
+impl Add for Position {
+    type Output = Position;
+
+    fn add(self, other: Position) -> Position {
+        Position { x: self.x + other.x, y: self.y + other.y, z: self.z + other.z }
+    }
+}
+
+impl Sub for Position {
+    type Output = Position;
+
+    fn sub(self, other: Position) -> Position {
+        Position { x: self.x - other.x, y: self.y - other.y, z: self.z - other.z }
+    }
+}
+
+impl Mul<f32> for Position {
+    type Output = Position;
+
+    fn mul(self, scale: f32) -> Position {
+        Position { x: self.x * scale, y: self.y * scale, z: self.z * scale }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Default for Color {
+    fn default() -> Self {
+        Color { r: 0., g: 0., b: 0., a: 1. }
+    }
+}
+
+impl Color {
+    /// Parses `#RGB`, `#RRGGBB` or `#RRGGBBAA` into normalized components
+    pub fn from_hex(s: &str) -> Result<Color, String> {
+        let digits = s
+            .strip_prefix('#')
+            .ok_or_else(|| format!("Color \"{s}\" must start with '#'"))?;
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Color \"{s}\" contains non-hex digits"));
+        }
+        let channel = |i: usize, width: usize| {
+            let value = u8::from_str_radix(&digits[i * width..(i + 1) * width], 16).unwrap();
+            // Single digit channels are repeated, so `f` becomes `ff`
+            let value = if width == 1 { value * 17 } else { value };
+            value as f32 / 255.
+        };
+        match digits.len() {
+            3 => Ok(Color { r: channel(0, 1), g: channel(1, 1), b: channel(2, 1), a: 1. }),
+            6 => Ok(Color { r: channel(0, 2), g: channel(1, 2), b: channel(2, 2), a: 1. }),
+            8 => Ok(Color { r: channel(0, 2), g: channel(1, 2), b: channel(2, 2), a: channel(3, 2) }),
+            n => Err(format!("Color \"{s}\" has {n} digits, expected 3, 6 or 8")),
+        }
+    }
+
+    /// Converts hue (degrees, wrapped into `0..360`), saturation and value
+    /// (both `0..=1`) into an opaque RGB color
+    pub fn from_hsv(h: f32, s: f32, v: f32) -> Color {
+        let h = h.rem_euclid(360.);
+        let chroma = v * s;
+        let x = chroma * (1. - ((h / 60.) % 2. - 1.).abs());
+        let m = v - chroma;
+        let (r, g, b) = match (h / 60.) as u32 {
+            0 => (chroma, x, 0.),
+            1 => (x, chroma, 0.),
+            2 => (0., chroma, x),
+            3 => (0., x, chroma),
+            4 => (x, 0., chroma),
+            _ => (chroma, 0., x),
+        };
+        Color { r: r + m, g: g + m, b: b + m, a: 1. }
+    }
+
+    /// Decodes sRGB-encoded components into linear light. Alpha is untouched.
+    pub fn to_linear(&self) -> Color {
+        fn decode(c: f32) -> f32 {
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        }
+        Color { r: decode(self.r), g: decode(self.g), b: decode(self.b), a: self.a }
+    }
+
+    /// Encodes linear components with the sRGB transfer function. Alpha is
+    /// untouched.
+    pub fn to_srgb(&self) -> Color {
+        fn encode(c: f32) -> f32 {
+            if c <= 0.0031308 {
+                c * 12.92
+            } else {
+                1.055 * c.powf(1. / 2.4) - 0.055
+            }
+        }
+        Color { r: encode(self.r), g: encode(self.g), b: encode(self.b), a: self.a }
+    }
+
+    /// Component-wise linear interpolation, including alpha
+    pub fn lerp(&self, other: &Color, t: f32) -> Color {
+        Color {
+            r: self.r + (other.r - self.r) * t,
+            g: self.g + (other.g - self.g) * t,
+            b: self.b + (other.b - self.b) * t,
+            a: self.a + (other.a - self.a) * t,
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct Vertex {
+    pub pos: Position,
+    pub normal: Position,
+    /// Texture coordinates, `[0, 0]` being the bottom left of the image
+    pub uv: [f32; 2],
+}
+
+/// A `Vertex` with a per-vertex color, for meshes shaded without a texture
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct ColoredVertex {
+    pub pos: Position,
+    pub normal: Position,
+    pub color: Color,
+}
+
+/// A `Vertex` with the direction of increasing U in model space, for
+/// normal mapping. See [`geometry::compute_tangents`].
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct TangentVertex {
+    pub pos: Position,
+    pub normal: Position,
+    pub uv: [f32; 2],
+    /// Perpendicular to `normal`
+    pub tangent: Position,
+    /// 1, or -1 where the UVs are mirrored. The bitangent (the direction of
+    /// increasing V) is `normal.cross(&tangent) * bitangent_sign`.
+    pub bitangent_sign: f32,
 }
 
-#[derive(Default, Clone, Copy)]
-struct Vertex {
-    pos: Position,
-    normal: Position,
+impl_vertex_layout!(Vertex { pos: vec3, normal: vec3, uv: vec2 });
+impl_vertex_layout!(ColoredVertex { pos: vec3, normal: vec3, color: vec4 });
+impl_vertex_layout!(TangentVertex { pos: vec3, normal: vec3, uv: vec2, tangent: vec3, bitangent_sign: float });
+
+/// Animate the mesh by rotating every vertex on the CPU and re-uploading the
+/// vertex buffer each frame, instead of through the `model` uniform. Only
+/// useful for experimenting with dynamic geometry.
+const ROTATE_ON_CPU: bool = false;
+
+/// Draw a skybox behind the grass, using the face-tinted debug cube map
+/// until there are sky images to load
+const SHOW_SKYBOX: bool = false;
+
+/// Render the scene at this fraction of the canvas resolution, upscaling it
+/// during post processing to trade sharpness for fill rate
+const RENDER_SCALE: f32 = 1.;
+
+/// Drag to orbit the camera around the field and scroll to zoom, instead
+/// of flying it with drag-to-look and WASD
+const ORBIT_CAMERA: bool = true;
+
+/// Upper bound on `devicePixelRatio` for the canvas backing store, to keep
+/// the fill rate reasonable on high density phone screens
+const MAX_PIXEL_RATIO: Option<f64> = Some(2.);
+
+/// Hang two overlapping tinted glass panes over the field, which only blend
+/// correctly from every side if transparent meshes are sorted
+const SHOW_GLASS: bool = true;
+
+/// Redraw the orange light's shadow cube map every this many frames, as its
+/// six passes over the scene cost more than the rest of the frame
+const POINT_SHADOW_INTERVAL: u32 = 2;
+
+/// Split the sun's shadow map into this many cascades along the view depth,
+/// for sharp shadows close to the camera. 0 uses a single shadow map over
+/// the whole field.
+const SHADOW_CASCADES: usize = 3;
+
+/// Tint the field by shadow cascade, to see where the splits fall
+const DEBUG_CASCADES: bool = false;
+
+/// Shows or hides the sun's shadow map, or each of its cascades, in the
+/// corners of the screen
+const SHADOW_DEBUG_KEY: &str = "KeyV";
+
+/// Log draw calls, primitives, uploads and binds to the console every this
+/// many frames
+const LOG_FRAME_STATS: Option<u32> = None;
+
+/// A running instance of the demo scene, returned to JS by [`init`]. Each
+/// instance owns its canvas, GL context and resources, so several can run on
+/// one page. Calling `free()` from JS ends rendering for good.
+#[wasm_bindgen]
+pub struct RendererHandle {
+    render_loop: RenderLoopHandle,
 }
 
-#[wasm_bindgen(start)]
-fn start() -> Result<(), JsValue> {
+#[wasm_bindgen]
+impl RendererHandle {
+    /// Resumes rendering after [`RendererHandle::stop`]
+    pub fn start(&self) {
+        self.render_loop.resume();
+    }
+
+    /// Pauses rendering, keeping every resource alive for `start`
+    pub fn stop(&self) {
+        self.render_loop.pause();
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.render_loop.is_paused()
+    }
+}
+
+/// Starts rendering the demo scene into `canvas`
+#[wasm_bindgen]
+pub fn init(canvas: HtmlCanvasElement) -> Result<RendererHandle, JsValue> {
     set_panic_hook();
-    let window: Window = web_sys::window().unwrap();
-    let document = window.document().unwrap();
-    let canvas = document.get_element_by_id("canvas").unwrap();
-    let canvas: web_sys::HtmlCanvasElement = canvas.dyn_into::<web_sys::HtmlCanvasElement>()?;
+    Ok(RendererHandle { render_loop: run(canvas)? })
+}
+
+/// Like [`init`], finding the canvas by its element id
+#[wasm_bindgen]
+pub fn init_with_id(id: &str) -> Result<RendererHandle, JsValue> {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let element = document
+        .get_element_by_id(id)
+        .ok_or_else(|| JsValue::from_str(&format!("No element with id \"{id}\"")))?;
+    let canvas = element
+        .dyn_into::<HtmlCanvasElement>()
+        .map_err(|element| JsValue::from_str(&format!(
+            "Element \"{id}\" is a <{}>, not a <canvas>",
+            element.tag_name().to_lowercase())))?;
+    init(canvas)
+}
+
+/// A static mesh drawn through the instanced main shader as a single
+/// instance, with the attribute pointers of `T` set up for `shader`
+fn single_instance_mesh<T: VertexLayout + 'static>(
+    context: &WebGl2RenderingContext,
+    shader: &Shader,
+    instance_model_location: u32,
+    vertices: Vec<T>,
+    indices: &[u32],
+) -> Result<Mesh, WasmGlError> {
+    let vao = VaoBuilder::new(context)?
+        .add_vertices("mesh", vertices, WebGl2RenderingContext::STATIC_DRAW)?
+        .add_instance_buffer("instances", vec![Matrix4::<f32>::identity()], WebGl2RenderingContext::STATIC_DRAW)?
+        .set_index_buffer(indices, WebGl2RenderingContext::STATIC_DRAW)?
+        .build();
+    vao.buffer::<T>("mesh").unwrap().bind_layout(context, shader);
+    let instances = vao.buffer::<Matrix4<f32>>("instances").unwrap();
+    instances.bind_mat4(context, instance_model_location, 0, 1);
+    Mesh::indexed(vao)
+}
 
+fn run(canvas: HtmlCanvasElement) -> Result<RenderLoopHandle, JsValue> {
+    let window: Window = web_sys::window().unwrap();
     let context = canvas
         .get_context("webgl2")?
-        .unwrap()
+        .ok_or("WebGL2 is not supported")?
         .dyn_into::<WebGl2RenderingContext>()?;
 
-    context.get_extension("WEBGL_depth_texture").expect_throw("need WEBGL_depth_texture");
-
-    context.clear_color(0., 0., 0., 1.);
-
-    let depth_tex = context.create_texture().expect_throw("texture failed to create");
-    const depth_tex_sz: usize = 512;
-
-    context.active_texture(WebGl2RenderingContext::TEXTURE0);
-    context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&depth_tex));
-    context.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
-        WebGl2RenderingContext::TEXTURE_2D,      // target
-        0,                  // mip level
-        WebGl2RenderingContext::DEPTH_COMPONENT32F as i32, // internal format
-        depth_tex_sz as i32,   // width
-        depth_tex_sz as i32,   // height
-        0,                  // border
-        WebGl2RenderingContext::DEPTH_COMPONENT, // format
-        WebGl2RenderingContext::FLOAT,    // type
-        None).expect_throw("error binding");              // data
-    context.tex_parameteri(
-        WebGl2RenderingContext::TEXTURE_2D,
-        WebGl2RenderingContext::TEXTURE_MAG_FILTER,
-        WebGl2RenderingContext::NEAREST as i32);
-    context.tex_parameteri(
-        WebGl2RenderingContext::TEXTURE_2D,
-        WebGl2RenderingContext::TEXTURE_MIN_FILTER,
-        WebGl2RenderingContext::NEAREST as i32);
-    context.tex_parameteri(
-        WebGl2RenderingContext::TEXTURE_2D,
-        WebGl2RenderingContext::TEXTURE_WRAP_S,
-        WebGl2RenderingContext::CLAMP_TO_EDGE as i32);
-    context.tex_parameteri(
-        WebGl2RenderingContext::TEXTURE_2D,
-        WebGl2RenderingContext::TEXTURE_WRAP_T,
-        WebGl2RenderingContext::CLAMP_TO_EDGE as i32);
-    
-    let depth_framebuf = context.create_framebuffer().expect_throw("creating framebuf");
-    context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&depth_framebuf));
-    context.framebuffer_texture_2d(
-        WebGl2RenderingContext::FRAMEBUFFER,       // target
-        WebGl2RenderingContext::DEPTH_ATTACHMENT,  // attachment point
-        WebGl2RenderingContext::TEXTURE_2D,        // texture target
-        Some(&depth_tex),         // texture
-        0);                   // mip level
+    context.get_extension("WEBGL_depth_texture")?;
+
+    // 512 is visibly blocky on large canvases
+    const SHADOW_MAP_SIZE: u32 = 2048;
+    // Each cascade covers less, so needs fewer texels
+    const CASCADE_SIZE: u32 = 1024;
+    let mut shadow_map = ShadowMap::new(&context, SHADOW_MAP_SIZE)?;
 
     let attribute_locations: HashMap<&str, u32> = HashMap::from([
         ("pos", 0),
+        // mat4 attributes take up 4 locations (2..=5)
+        ("instanceModel", 2),
     ]);
     
-    let shadow_pass = Shader::new(&context,
+    let includes = HashMap::from([
+        ("instancing", include_str!("./shaders/instancing.glsl")),
+        ("frame_data", include_str!("./shaders/frame_data.glsl")),
+    ]);
+
+    let shadow_pass = Shader::new_with_includes(&context,
         include_str!("./shaders/shadow_pass.vsh"),
         include_str!("./shaders/shadow_pass.fsh"),
-        &["projectionView"],
-        &["pos"],
-        Some(&attribute_locations));
+        &includes,
+        Some(&attribute_locations))?;
         
-    let shader = Shader::new(
+    let shader = Shader::new_with_includes(
         &context,
         include_str!("./shaders/main.vsh"),
         include_str!("./shaders/main.fsh"),
-        &["projection", "view", "reverseLightDir", "lightPos", "shadowView"],
-        &["pos", "normal"],
-        Some(&attribute_locations));
+        &includes,
+        Some(&attribute_locations))?;
     shader.enable(&context);
 
-    let mut vao = VAO_new!(
-        &context,
-        (Vec::<Vertex>::new()
-            // Vertex { pos: Position { x: -0.4, y: -0.4, z: -0.4 }, color: Color { r: 0.0, g: 0.0, b: 0.0 }, },
-            // Vertex { pos: Position { x: 0.4, y: -0.4, z: -0.4 }, color: Color { r: 1.0, g: 0.0, b: 0.0 }, },
-            // Vertex { pos: Position { x: -0.4, y: 0.4, z: -0.4 }, color: Color { r: 0.0, g: 1.0, b: 0.0 }, },
-            // Vertex { pos: Position { x: -0.4, y: -0.4, z: 0.4 }, color: Color { r: 0.0, g: 0.0, b: 1.0 }, },
-            // Vertex { pos: Position { x: 0.4, y: 0.4, z: -0.4 }, color: Color { r: 1.0, g: 1.0, b: 0.0 }, },
-            // Vertex { pos: Position { x: -0.4, y: 0.4, z: 0.4 }, color: Color { r: 0.0, g: 1.0, b: 1.0 }, },
-            // Vertex { pos: Position { x: 0.4, y: -0.4, z: 0.4 }, color: Color { r: 1.0, g: 0.0, b: 1.0 }, },
-            // Vertex { pos: Position { x: 0.4, y: 0.4, z: 0.4 }, color: Color { r: 1.0, g: 1.0, b: 1.0 }, },
-        , WebGl2RenderingContext::ARRAY_BUFFER, WebGl2RenderingContext::DYNAMIC_DRAW),
-        (Vec::<u8>::new()
-            // 0u8, 1, 2, 1, 2, 4, // Back
-            // 3, 6, 5, 6, 5, 7, // Front
-            // 0, 2, 3, 2, 3, 5, // Left
-            // 1, 4, 6, 4, 6, 7, // Right
-            // 0, 1, 3, 1, 3, 6, // Top
-            // 2, 4, 5, 4, 5, 7, // Bottom
-        , WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER, WebGl2RenderingContext::STATIC_DRAW)
-        // (vec![
-        //     Position { x: 0.0, y: 0.0, z: 0.0 },
-        //     Position { x: 1.0, y: 0.0, z: 0.0 },
-        //     Position { x: 2.0, y: 0.0, z: 0.0 },
-        //     Position { x: 0.0, y: 1.0, z: 0.0 },
-        //     Position { x: 1.0, y: 1.0, z: 0.0 },
-        // ], WebGl2RenderingContext::ARRAY_BUFFER, WebGl2RenderingContext::STATIC_DRAW)
-    );
-
-    let segments = 7;
-    let height = 0.7;
-    let mut current_height = 0.;
-    let mut width = 0.03;
-    let mut last_normal = Position { x: 0., y: 0., z: -1. };
-
-    for i in 0..segments {
-        let next_normal = (Position { x: 0., y: 0.1, z: -(height - current_height) * 0.3 }).normalize();
-        vao.vbos.0.buffer.push(
-            Vertex {
-                pos: Position { x: -width, y: current_height, z: 0.1 * i as f32 },
-                normal: last_normal.average(&next_normal),
-            });
-        vao.vbos.0.buffer.push(Vertex {
-            pos: Position { x: width, y: current_height, z: 0.1 * i as f32 },
-            normal: last_normal.average(&next_normal),
-        });
-        last_normal = next_normal;
-        let len = vao.vbos.0.len() as u8;
-        if i > 0 {
-            vao.vbos.1.buffer.append(&mut vec![
-                len - 4, len - 3, len - 2,
-                len - 3, len - 2, len - 1,
-            ]);
+    let frame_data = UniformBuffer::<FrameUniforms>::new(&context, 0)?;
+    shadow_pass.bind_uniform_block(&context, "FrameData", &frame_data)?;
+    shader.bind_uniform_block(&context, "FrameData", &frame_data)?;
+
+    let fog = FogConfig {
+        color: Color::default(),
+        mode: FogMode::Exponential { density: 0.15 },
+    };
+    fog.apply(&context, &shader);
+    ToonConfig::default().apply(&context, &shader);
+    // The shadow pass sways the same way, or shadows would detach from the
+    // blades
+    let wind = WindConfig { direction: Vector2::new(1., 0.3), strength: 0.15 };
+    wind.apply(&context, &shader);
+    shadow_pass.enable(&context);
+    wind.apply(&context, &shadow_pass);
+    context.clear_color(fog.color.r, fog.color.g, fog.color.b, 1.);
+
+    const GRASS_COUNT: usize = 10000;
+    let field = grass_field(GrassBladeParams::default(), GRASS_COUNT, (10., 10.), 1);
+    let mesh_usage = if ROTATE_ON_CPU {
+        WebGl2RenderingContext::DYNAMIC_DRAW
+    } else {
+        WebGl2RenderingContext::STATIC_DRAW
+    };
+    let vao = VaoBuilder::new(&context)?
+        .add_vertices("mesh", field.vertices, mesh_usage)?
+        .add_instance_buffer("instances", field.instances, WebGl2RenderingContext::STATIC_DRAW)?
+        .set_index_buffer(&field.indices, WebGl2RenderingContext::STATIC_DRAW)?
+        .build();
+    vao.buffer::<Vertex>("mesh").unwrap().bind_layout(&context, &shader);
+    let instances = vao.buffer::<Matrix4<f32>>("instances").unwrap();
+    instances.bind_mat4(&context, attribute_locations["instanceModel"], 0, 1);
+    let mut scene = Scene::new();
+    let mut grass = Mesh::indexed(vao)?;
+    grass.material = Material {
+        base_color: Vector3::new(0., 1., 0.),
+        specular_color: Vector3::new(0.3, 0.35, 0.2),
+        shininess: 24.,
+        // Blades are single sheets, seen from both sides
+        cull: CullMode::None,
+        ..Material::default()
+    };
+    let grass = scene.add(grass);
+
+    let (ground_vertices, ground_indices) = plane(10., 10., 1, 1);
+    // Vertex colors darken the dirt to moss towards the back of the field
+    let mut ground_vertices: Vec<ColoredVertex> = ground_vertices
+        .into_iter()
+        .map(|v| ColoredVertex { pos: v.pos, normal: v.normal, color: Color::default() })
+        .collect();
+    let (dirt, moss) = (Color { r: 1., g: 1., b: 1., a: 1. }, Color { r: 0.5, g: 0.9, b: 0.4, a: 1. });
+    apply_axis_gradient(&mut ground_vertices, Axis::Z, &[(-5., moss), (5., dirt)]);
+    let instance_model = attribute_locations["instanceModel"];
+    let mut ground = single_instance_mesh(&context, &shader, instance_model, ground_vertices, &ground_indices)?;
+    ground.material.base_color = Vector3::new(0.2, 0.12, 0.04);
+    scene.add(ground);
+
+    if SHOW_GLASS {
+        let (pane_vertices, pane_indices) = plane(1.5, 1.5, 1, 1);
+        let upright = Matrix4::from_euler_angles(std::f32::consts::FRAC_PI_2, 0., 0.);
+        let panes = [
+            (Vector3::new(-0.4, 1.2, 0.5), Vector3::new(1., 0.2, 0.2)),
+            (Vector3::new(0.4, 1.4, -0.5), Vector3::new(0.2, 0.3, 1.)),
+        ];
+        for (position, tint) in panes {
+            let mut pane = single_instance_mesh(&context, &shader, instance_model, pane_vertices.clone(), &pane_indices)?;
+            pane.model = Matrix4::new_translation(&position) * upright;
+            pane.material = Material {
+                base_color: tint,
+                specular_color: Vector3::new(0.8, 0.8, 0.8),
+                shininess: 64.,
+                opacity: 0.4,
+                transparent: true,
+                cull: CullMode::None,
+            };
+            scene.add(pane);
         }
-        width -= width * i as f32 * 2. / segments as f32 / segments as f32;
-        current_height += (height - current_height) * 0.3;
-    }
-    let next_normal = (Position { x: 0., y: 0.1, z: -(height - current_height) * 0.3 }).normalize();
-    vao.vbos.0.buffer.push(Vertex {
-        pos: Position { x: 0., y: current_height, z: 0.1 * segments as f32 },
-        normal: last_normal.average(&next_normal),
-    });
-    let len = vao.vbos.0.len() as u8;
-    vao.vbos.1.buffer.append(&mut vec![
-        len - 3, len - 2, len - 1,
-    ]);
-
-    vao.vbos.0.update(&context);
-    vao.vbos.1.update(&context);
-
-    VBO_bind!(vao.vbos.0, &context, attribute_locations["pos"], Vertex, 3, WebGl2RenderingContext::FLOAT);
-    VBO_bind!(vao.vbos.0, &context, shader, Vertex, normal, 3, WebGl2RenderingContext::FLOAT);
-    // VBO_bind!(vao.vbos.0, &context, shader, Vertex, color, 3, WebGl2RenderingContext::FLOAT);
-
-    // VBO_bind!(vao.vbos.2, &context, shader.find_attr("offset"), Position, 3, WebGl2RenderingContext::FLOAT);
-    // context.vertex_attrib_divisor(shader.find_attr("offset"), 1);
-
-    // let mut elements = VAO_new!(
-    //     &context
-    // );   
+    }
 
     context.enable(WebGl2RenderingContext::DEPTH_TEST);
     
-    let mut proj_matrix = Matrix4::new_perspective(
-        1.,
-        90.0f32.to_radians(),
-        0.1, 100.);
-    let shadow_proj_matrix =  Matrix4::new_perspective(
-        1.,
-        120.0f32.to_radians(),
-        0.1, 100.);
-    let light_pos = Vector3::new(1., 3., -1.);
-    let view_matrix = 
-        Matrix4::from_euler_angles(0., 0., 0.)
-            .prepend_translation(&-Vector3::new(0., 1., 0.));
-    let shadow_view_matrix = 
-        Matrix4::from_euler_angles(60.0f32.to_radians(), -10.0f32.to_radians(), 0.)
-            .prepend_translation(&-light_pos);
+    // The 10x10 field, with headroom for the blades and their sway
+    let (scene_min, scene_max) = (Vector3::new(-5., 0., -5.), Vector3::new(5., 1., 5.));
+    let sun = DirectionalLight::new(Vector3::new(-1., -3., 1.));
+    (shadow_map.view, shadow_map.projection) = sun.shadow_matrices(scene_min, scene_max);
+    let light_pos = sun.position(scene_min, scene_max);
+    // The sun comes first, as the light the shadow map belongs to
+    let mut lights = LightSet::new(Color { r: 0.15, g: 0.15, b: 0.2, a: 1. });
+    lights.add(Light::Directional { direction: sun.direction, color: Color { r: 1., g: 0.95, b: 0.85, a: 1. }, intensity: 1. })?;
+    let (lamp_position, lamp_range) = (Vector3::new(2., 1., 2.), 4.);
+    lights.add(Light::Point {
+        position: lamp_position,
+        color: Color { r: 1., g: 0.5, b: 0.1, a: 1. },
+        intensity: 0.8,
+        range: lamp_range,
+    })?;
+    const LAMP_INDEX: usize = 1;
+    let mut cascades = match SHADOW_CASCADES {
+        0 => None,
+        count => Some(CascadedShadowMap::new(&context, CASCADE_SIZE, count)?),
+    };
+    if let Some(cascades) = &mut cascades {
+        // Fog hides everything further away
+        cascades.max_distance = 25.;
+        cascades.debug_tint = DEBUG_CASCADES;
+    }
+    let mut point_shadow = PointShadow::new(&context, 512, lamp_position, lamp_range)?;
+    point_shadow.throttle = UpdateThrottle::new(POINT_SHADOW_INTERVAL);
     let (mut w, mut h) = (canvas.width() as i32, canvas.height() as i32);
-
-    render_loop(move |resize: bool| {
+    let mut camera = Camera::new(Vector3::new(0., 1., 6.), 90.0f32.to_radians(), 0.1, 100.);
+    let bind_cache = BindCache::new();
+    let skybox = SHOW_SKYBOX.then(|| Skybox::new(&context, CubeMap::debug(&context)?)).transpose()?;
+    let input = InputState::attach(&canvas)?;
+    let mut orbit = OrbitController::new(Vector3::zeros(), 6.);
+    orbit.set_angles(0., 0.2);
+    let mut elapsed = 0.;
+
+    let scaled = |size: i32| ((size as f32 * RENDER_SCALE) as u32).max(1);
+    let mut post = PostProcessChain::new(&context, scaled(w), scaled(h), vec![PostProcessPass::gamma(&context)?])?;
+    let debug_quad = DebugQuad::new(&context)?;
+    let mut show_shadow_maps = false;
+    let mut stats = FrameStats::new(&context);
+    stats.log_interval = LOG_FRAME_STATS;
+
+    render_loop(move |resize: bool, delta: f64| {
         if resize {
-            unsafe {
-                canvas.set_width(
-                    window
-                        .inner_width()
-                        .unwrap()
-                        .as_f64()
-                        .unwrap_or_default()
-                        .to_int_unchecked::<u32>(),
-                );
-                canvas.set_height(
-                    window
-                        .inner_height()
-                        .unwrap()
-                        .as_f64()
-                        .unwrap_or_default()
-                        .to_int_unchecked::<u32>(),
-                );
+            (w, h) = resize_canvas(&window, &canvas, MAX_PIXEL_RATIO);
+            camera.set_aspect(w, h);
+            post.resize(&context, scaled(w), scaled(h)).or_throw();
+        }
+
+        show_shadow_maps ^= input.borrow().was_key_pressed(SHADOW_DEBUG_KEY);
+        if ORBIT_CAMERA {
+            let mut input = input.borrow_mut();
+            orbit.update(&input, &mut camera);
+            input.end_frame();
+        } else {
+            // Drag to look around, WASD/Space/Shift to fly
+            const LOOK_SPEED: f32 = 0.005;
+            const MOVE_SPEED: f32 = 3.;
+            let mut input = input.borrow_mut();
+            if input.is_button_down(0) {
+                let (dx, dy) = input.mouse_delta();
+                camera.rotate(dx * LOOK_SPEED, -dy * LOOK_SPEED);
             }
-            (w, h) = (canvas.width() as i32, canvas.height() as i32);
-            proj_matrix = Matrix4::new_perspective(
-                1.,
-                90.0f32.to_radians(),
-                0.1, 1000.);
+            let axis = |positive: &str, negative: &str| {
+                input.is_key_down(positive) as i32 as f32 - input.is_key_down(negative) as i32 as f32
+            };
+            let movement = Vector3::new(axis("KeyD", "KeyA"), axis("Space", "ShiftLeft"), axis("KeyW", "KeyS"));
+            camera.translate_local(movement * MOVE_SPEED * delta as f32);
+            input.end_frame();
         }
-        
-        for ele in &mut vao.vbos.0.buffer {
-            ele.pos.rotate(&[0., 1., 0.], 1./30.);
-            ele.normal.rotate(&[0., 1., 0.], 1./30.);
+
+        // Radians per second
+        const ROTATION_SPEED: f32 = 2.;
+        elapsed += delta;
+        let grass = scene.get_mut(grass).unwrap();
+        if ROTATE_ON_CPU {
+            let angle = ROTATION_SPEED * delta as f32;
+            // Uploaded when the scene draws it
+            let mesh = grass.vao_mut::<NamedBuffers>().unwrap().buffer_mut::<Vertex>("mesh").unwrap();
+            for ele in mesh.as_mut_slice_tracked(0..mesh.len()) {
+                ele.pos.rotate(&[0., 1., 0.], angle);
+                ele.normal.rotate(&[0., 1., 0.], angle);
+            }
+        } else {
+            grass.model = Matrix4::from_euler_angles(0., elapsed as f32 * ROTATION_SPEED, 0.);
         }
-        vao.vbos.0.update(&context);
 
-        vao.activate(&context);
-        
-        shadow_pass.enable(&context);
-        context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&depth_framebuf));
-        context.viewport(0, 0, depth_tex_sz as i32, depth_tex_sz as i32);
-        context.clear(WebGl2RenderingContext::DEPTH_BUFFER_BIT);
-        context.uniform_matrix4fv_with_f32_array(
-            Some(shadow_pass.find_uniform("projectionView")), false,
-            &(shadow_proj_matrix * shadow_view_matrix).data.as_slice());
-            
-
-        context.draw_elements_instanced_with_i32(
-            WebGl2RenderingContext::TRIANGLES,
-            vao.vbos.1.len() as i32,
-            WebGl2RenderingContext::UNSIGNED_BYTE,
-            0,
-            10000
-        );
+        // The shadow pass renders from whichever light `lightProjectionView`
+        // is set to
+        let frame = |light_projection_view| FrameUniforms {
+            projection: camera.projection_matrix(),
+            view: camera.view_matrix(),
+            light_projection_view,
+            shadow_view: shadow_map.texture_matrix(),
+            light_pos,
+            _padding: 0.,
+            camera_pos: camera.position,
+            _camera_padding: 0.,
+        };
+
+        shadow_pass.enable_cached(&context, &bind_cache);
+        shadow_pass.set_time(&context, elapsed as f32);
+        point_shadow.render(&context, w, h, |_, projection_view| {
+            frame_data.update(&context, &frame(*projection_view));
+            scene.draw_opaque_culling(&context, &bind_cache, &shadow_pass, Some(CullMode::Front));
+        });
 
-        context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
-        context.viewport(0, 0, w, h);
+        // Only back faces cast shadows, pushing acne onto unlit sides
+        if let Some(cascades) = &mut cascades {
+            cascades.update(&camera, &sun, scene_min, scene_max);
+            cascades.render(&context, w, h, |_, projection_view| {
+                frame_data.update(&context, &frame(*projection_view));
+                scene.draw_opaque_culling(&context, &bind_cache, &shadow_pass, Some(CullMode::Front));
+            });
+        } else {
+            frame_data.update(&context, &frame(shadow_map.projection_view()));
+            shadow_map.begin(&context);
+            scene.draw_opaque_culling(&context, &bind_cache, &shadow_pass, Some(CullMode::Front));
+            shadow_map.end(&context, w, h);
+        }
+
+        post.scene().bind(&context);
         context.clear(
             WebGl2RenderingContext::COLOR_BUFFER_BIT | WebGl2RenderingContext::DEPTH_BUFFER_BIT,
         );
 
-        shader.enable(&context);
-
-        context.uniform_matrix4fv_with_f32_array(
-            Some(shader.find_uniform("projection")), false,
-            (proj_matrix).data.as_slice());
-
-        context.uniform_matrix4fv_with_f32_array(
-            Some(shader.find_uniform("view")), false,
-            (view_matrix).data.as_slice());
-
-        context.uniform3fv_with_f32_array(
-            Some(shader.find_uniform("lightPos")),
-            (light_pos).data.as_slice());
-            
-        context.uniform3fv_with_f32_array(
-            Some(shader.find_uniform("reverseLightDir")),
-            &(shadow_view_matrix).data.as_slice()[8..11]);
-            
-        context.uniform_matrix4fv_with_f32_array(
-            Some(shader.find_uniform("shadowView")), false,
-            &(Matrix4::new_scaling(0.5).append_translation(&Vector3::new(0.5, 0.5, 0.5))
-                 * shadow_proj_matrix * shadow_view_matrix)
-                .data.as_slice());
-
-        context.draw_elements_instanced_with_i32(
-            WebGl2RenderingContext::TRIANGLES,
-            vao.vbos.1.len() as i32,
-            WebGl2RenderingContext::UNSIGNED_BYTE,
-            0,
-            10000
-        );
-    })?;
+        shader.enable_cached(&context, &bind_cache);
+        shader.set_time(&context, elapsed as f32);
+        // Samplers of different types must be on different units, even
+        // those not sampled
+        shader.set_texture(&context, "shadowMap", 0);
+        shader.set_texture(&context, "cascadeShadowMap", 2);
+        // Bound even when cascades replace it, as unit 0 would otherwise
+        // keep the post process input, which is also the render target
+        shadow_map.bind_texture(&context, 0);
+        match &cascades {
+            Some(cascades) => {
+                cascades.bind_texture(&context, 2);
+                cascades.apply(&context, &shader);
+            }
+            None => {
+                shadow_map.apply(&context, &shader);
+                CascadedShadowMap::apply_none(&context, &shader);
+            }
+        }
+        point_shadow.bind_texture(&context, 1);
+        shader.set_texture(&context, "pointShadowMap", 1);
+        point_shadow.apply(&context, &shader, LAMP_INDEX);
+
+        lights.apply(&context, &shader);
+        scene.draw_opaque(&context, &bind_cache, &shader);
+        if let Some(skybox) = &skybox {
+            skybox.draw(&context, &bind_cache, &camera.projection_matrix(), &camera.view_matrix());
+        }
+        scene.draw_transparent(&context, &bind_cache, &shader, &camera.view_matrix());
+
+        post.run(&context, &bind_cache, w, h);
+
+        if show_shadow_maps {
+            const CORNERS: [ScreenCorner; 4] =
+                [ScreenCorner::BottomLeft, ScreenCorner::BottomRight, ScreenCorner::TopLeft, ScreenCorner::TopRight];
+            match &cascades {
+                Some(cascades) => {
+                    for (layer, &corner) in CORNERS.iter().enumerate().take(cascades.count()) {
+                        debug_quad.draw_cascade(&context, cascades, layer, corner, 0.25).or_throw();
+                    }
+                }
+                None => debug_quad.draw_texture(&context, shadow_map.texture(), CORNERS[0], 0.25).or_throw(),
+            }
+        }
+        stats.end_frame();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Position, b: Position) {
+        assert!(
+            (a.x - b.x).abs() < 1e-6 && (a.y - b.y).abs() < 1e-6 && (a.z - b.z).abs() < 1e-6,
+            "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn average_is_midpoint() {
+        let a = Position { x: 1., y: 2., z: -4. };
+        let b = Position { x: 3., y: -2., z: 0. };
+        assert_close(a.average(&b), Position { x: 2., y: 0., z: -2. });
+        assert_close(a.midpoint(&b), a.average(&b));
+    }
+
+    #[test]
+    fn lerp_endpoints_and_middle() {
+        let a = Position { x: 0., y: 1., z: 2. };
+        let b = Position { x: 4., y: 5., z: 6. };
+        assert_close(a.lerp(&b, 0.), a);
+        assert_close(a.lerp(&b, 1.), b);
+        assert_close(a.lerp(&b, 0.25), Position { x: 1., y: 2., z: 3. });
+    }
+
+    #[test]
+    fn normalize_gives_unit_length() {
+        let n = Position { x: 3., y: 0., z: 4. }.normalize();
+        assert_close(n, Position { x: 0.6, y: 0., z: 0.8 });
+        let a = Position { x: 1., y: 0., z: 0. };
+        let b = Position { x: 0., y: 1., z: 0. };
+        let avg = a.average(&b).normalize();
+        assert!(((avg.x * avg.x + avg.y * avg.y + avg.z * avg.z).sqrt() - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn cross_follows_right_hand_rule() {
+        let x = Position { x: 1., y: 0., z: 0. };
+        let y = Position { x: 0., y: 1., z: 0. };
+        assert_close(x.cross(&y), Position { x: 0., y: 0., z: 1. });
+        assert_eq!(x.dot(&y), 0.);
+    }
+
+    #[test]
+    fn vertex_layouts_follow_the_fields() {
+        use crate::renderer::VertexLayout;
+        let names: Vec<_> = Vertex::ATTRIBUTES.iter().map(|a| a.name).collect();
+        assert_eq!(names, ["pos", "normal", "uv"]);
+        let uv = Vertex::ATTRIBUTES[2];
+        assert_eq!((uv.offset, uv.size), (std::mem::offset_of!(Vertex, uv), 2));
+        // Packed straight after the two positions, as `repr(C)` lays out the
+        // fields in order
+        assert_eq!(uv.offset, std::mem::size_of::<Position>() * 2);
+        assert_eq!(std::mem::size_of::<Vertex>(), std::mem::size_of::<Position>() * 2 + 8);
+        assert_eq!(ColoredVertex::ATTRIBUTES[2].size, 4);
+        assert!(Vertex::ATTRIBUTES.iter().all(|a| a.gl_type == WebGl2RenderingContext::FLOAT && !a.normalized));
+        assert!(Vertex::ATTRIBUTES.iter().all(|a| !a.integer));
+    }
+
+    #[test]
+    fn position_bounds_cover_every_vertex() {
+        use crate::renderer::position_bounds;
+        let vertex = |x, y, z| Vertex { pos: Position { x, y, z }, ..Vertex::default() };
+        let (min, max) = position_bounds(&[vertex(1., -2., 0.), vertex(-1., 4., 3.), vertex(0., 0., -5.)]).unwrap();
+        assert_eq!((min, max), (Vector3::new(-1., -2., -5.), Vector3::new(1., 4., 3.)));
+        assert_eq!(position_bounds::<Vertex>(&[]), None);
+    }
 
-    Ok(())
+    #[test]
+    fn integer_fields_are_integer_attributes() {
+        use crate::renderer::VertexLayout;
+        #[repr(C)]
+        struct Skinned {
+            pos: Position,
+            bones: [u32; 4],
+            material: i32,
+        }
+        impl_vertex_layout!(Skinned { pos: vec3, bones: uvec4, material: int });
+        let [pos, bones, material] = Skinned::ATTRIBUTES else { panic!("expected 3 attributes") };
+        assert!(!pos.integer);
+        assert_eq!((bones.size, bones.gl_type, bones.integer), (4, WebGl2RenderingContext::UNSIGNED_INT, true));
+        assert_eq!((material.size, material.gl_type, material.integer), (1, WebGl2RenderingContext::INT, true));
+    }
+
+    #[test]
+    fn hex_short_form_expands() {
+        assert_eq!(Color::from_hex("#fff"), Ok(Color { r: 1., g: 1., b: 1., a: 1. }));
+    }
+
+    #[test]
+    fn hex_long_forms() {
+        assert_eq!(Color::from_hex("#000000"), Ok(Color { r: 0., g: 0., b: 0., a: 1. }));
+        let c = Color::from_hex("#ff880080").unwrap();
+        assert_eq!((c.r, c.b), (1., 0.));
+        assert!((c.g - 136. / 255.).abs() < 1e-6);
+        assert!((c.a - 128. / 255.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn hsv_primaries() {
+        assert_eq!(Color::from_hsv(0., 1., 1.), Color { r: 1., g: 0., b: 0., a: 1. });
+        assert_eq!(Color::from_hsv(120., 1., 1.), Color { r: 0., g: 1., b: 0., a: 1. });
+        assert_eq!(Color::from_hsv(240., 1., 1.), Color { r: 0., g: 0., b: 1., a: 1. });
+        // Hue wraps around
+        assert_eq!(Color::from_hsv(480., 1., 1.), Color::from_hsv(120., 1., 1.));
+        assert_eq!(Color::from_hsv(-240., 1., 1.), Color::from_hsv(120., 1., 1.));
+    }
+
+    #[test]
+    fn hsv_desaturated_is_gray() {
+        let c = Color::from_hsv(200., 0., 0.5);
+        assert_eq!((c.r, c.g, c.b), (0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn srgb_mid_gray_to_linear() {
+        let c = Color { r: 0.5, g: 0.5, b: 0.5, a: 0.5 }.to_linear();
+        assert!((c.r - 0.214).abs() < 1e-3);
+        assert_eq!(c.a, 0.5);
+    }
+
+    #[test]
+    fn srgb_round_trip() {
+        for i in 0..=20 {
+            let v = i as f32 / 20.;
+            let c = Color { r: v, g: v * v, b: 1. - v, a: 1. };
+            let back = c.to_linear().to_srgb();
+            assert!((back.r - c.r).abs() < 1e-5);
+            assert!((back.g - c.g).abs() < 1e-5);
+            assert!((back.b - c.b).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn hex_rejects_malformed() {
+        assert!(Color::from_hex("fff").is_err());
+        assert!(Color::from_hex("#ggg").is_err());
+        assert!(Color::from_hex("#ff88").is_err());
+        assert!(Color::from_hex("#").is_err());
+    }
 }
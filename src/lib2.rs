@@ -2,7 +2,7 @@ use std::{
     any::Any, borrow::{self, BorrowMut}, cell::RefCell, collections::HashMap, iter::FromIterator, marker::Tuple, mem::{offset_of, size_of}, ops::{DerefMut, Div}, rc::Rc
 };
 
-use js_sys::{ArrayBuffer, Float32Array, SharedArrayBuffer, Uint8Array};
+use js_sys::{ArrayBuffer, SharedArrayBuffer, Uint8Array};
 use wasm_bindgen::{convert::VectorIntoWasmAbi, prelude::*};
 use web_sys::{
     Event, HtmlCanvasElement, WebGl2RenderingContext, WebGlBuffer, WebGlProgram, WebGlShader,
@@ -109,13 +109,16 @@ trait IVBO {
 struct VBO<T> {
     source: Vec<T>,
     buffer: WebGlBuffer,
+    /// Usage hint passed to every `bufferData`, e.g. `DYNAMIC_DRAW`
+    access_type: u32,
 }
 
 impl<T> VBO<T> {
-    pub fn new(ctx: &WebGl2RenderingContext, default_content: Option<Vec<T>>) -> VBO<T> {
+    pub fn new(ctx: &WebGl2RenderingContext, default_content: Option<Vec<T>>, access_type: u32) -> VBO<T> {
         VBO {
             buffer: ctx.create_buffer().expect_throw("Failed to create buffer"),
             source: default_content.unwrap_or_default(),
+            access_type,
         }
     }
 
@@ -144,24 +147,13 @@ impl<T> IVBO for VBO<T> {
 
     fn update(&mut self, ctx: &WebGl2RenderingContext) {
         ctx.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&self.buffer));
-        // Note that `Float32Array::view` is somewhat dangerous (hence the
-        // `unsafe`!). This is creating a raw view into our module's
-        // `WebAssembly.Memory` buffer, but if we allocate more pages for ourself
-        // (aka do a memory allocation in Rust) it'll cause the buffer to change,
-        // causing the `Float32Array` to be invalid.
-        //
-        // As a result, after `Float32Array::view` we have to be very careful not to
-        // do any memory allocations before it's dropped.
-        unsafe {
-            // let array_buf_view =
-            //     js_sys::Float32Array::view(self.source.as_slice().align_to::<f32>().1);
-
-            ctx.buffer_data_with_array_buffer_view(
-                WebGl2RenderingContext::ARRAY_BUFFER,
-                &Float32Array::view(self.source.as_slice().align_to::<f32>().1),
-                WebGl2RenderingContext::STATIC_DRAW,
-            );
-        }
+        // Copied rather than viewed, as a `Float32Array::view` into wasm
+        // memory is invalidated by any allocation before the GL call
+        ctx.buffer_data_with_u8_array(
+            WebGl2RenderingContext::ARRAY_BUFFER,
+            unsafe { self.source.as_slice().align_to::<u8>().1 },
+            self.access_type,
+        );
     }
 }
 
@@ -173,6 +165,8 @@ struct Renderer {
     vbos: Vec<Box<dyn IVBO>>,
     attr_locations: HashMap<String, u32>,
     timer: u32,
+    /// Caps `devicePixelRatio` when sizing the backing store
+    max_pixel_ratio: Option<f64>,
 }
 
 impl Renderer {
@@ -221,6 +215,7 @@ impl Renderer {
             context,
             main_shader: shader_prog,
             timer: 0,
+            max_pixel_ratio: None,
         })
     }
 
@@ -239,7 +234,9 @@ impl Renderer {
                 Vertex { pos: Position { x: -0.7, y: -0.7, z: 0.0 }, color: Color { r: 1.0, g: 0.0, b: 0.0 } },
                 Vertex { pos: Position { x: 0.7, y: -0.7, z: 0.0 }, color: Color { r: 0.0, g: 1.0, b: 0.0 } },
                 Vertex { pos: Position { x: 0.0, y: 0.7, z: 0.0 }, color: Color { r: 0.0, g: 0.0, b: 1.0 } },
-            ]));
+            ]),
+            // Animated in `render`
+            WebGl2RenderingContext::DYNAMIC_DRAW);
         // vbo.update(&self.context);
         vbo.bind(&self.context, self.attr_locations["position"],
             3, WebGl2RenderingContext::FLOAT, false, offset_of!(Vertex, pos));
@@ -278,30 +275,10 @@ impl Renderer {
     }
 
     fn resize_canvas(&self) {
-        unsafe {
-            self.canvas.set_width(
-                self.window
-                    .inner_width()
-                    .unwrap()
-                    .as_f64()
-                    .unwrap_or_default()
-                    .to_int_unchecked::<u32>(),
-            );
-            self.canvas.set_height(
-                self.window
-                    .inner_height()
-                    .unwrap()
-                    .as_f64()
-                    .unwrap_or_default()
-                    .to_int_unchecked::<u32>(),
-            );
-        }
-        self.context.viewport(
-            0,
-            0,
-            self.canvas.width() as i32,
-            self.canvas.height() as i32,
-        );
+        // Lay the canvas out at the window's logical size, but render at the
+        // physical resolution so HiDPI displays aren't blurry
+        let (width, height) = crate::renderer::resize_canvas(&self.window, &self.canvas, self.max_pixel_ratio);
+        self.context.viewport(0, 0, width, height);
     }
 
     fn update(&mut self) {
@@ -0,0 +1,146 @@
+//! Model viewer style camera control, circling a target point
+
+use nalgebra::Vector3;
+
+use crate::input::InputState;
+use crate::renderer::Camera;
+
+/// Keeps a [`Camera`] looking at `target` from a point on a sphere around
+/// it. Dragging with the main mouse button changes the azimuth (about +Y)
+/// and elevation, and the wheel changes the distance.
+#[derive(Clone, Debug)]
+pub struct OrbitController {
+    pub target: Vector3<f32>,
+    azimuth: f32,
+    elevation: f32,
+    distance: f32,
+    /// Radians per CSS pixel dragged
+    pub rotate_speed: f32,
+    /// The distance is scaled by `exp(zoom_speed * wheel_delta)`
+    pub zoom_speed: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+}
+
+impl OrbitController {
+    /// Elevation stays short of the poles, where the view would flip
+    pub const MAX_ELEVATION: f32 = Camera::MAX_PITCH;
+
+    /// Starts at zero azimuth and elevation, i.e. on the +Z side of `target`
+    pub fn new(target: Vector3<f32>, distance: f32) -> OrbitController {
+        OrbitController {
+            target,
+            azimuth: 0.,
+            elevation: 0.,
+            distance,
+            rotate_speed: 0.005,
+            zoom_speed: 0.001,
+            min_distance: 0.5,
+            max_distance: 50.,
+        }
+    }
+
+    pub fn azimuth(&self) -> f32 {
+        self.azimuth
+    }
+
+    pub fn elevation(&self) -> f32 {
+        self.elevation
+    }
+
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    /// Sets the azimuth and elevation, in radians, clamping the elevation
+    pub fn set_angles(&mut self, azimuth: f32, elevation: f32) {
+        self.azimuth = azimuth % std::f32::consts::TAU;
+        self.elevation = elevation.clamp(-Self::MAX_ELEVATION, Self::MAX_ELEVATION);
+    }
+
+    /// Orbits by a drag of `dx`, `dy` CSS pixels. Dragging right moves the
+    /// camera left around the target, and dragging down moves it up.
+    pub fn drag(&mut self, dx: f32, dy: f32) {
+        self.set_angles(self.azimuth + dx * self.rotate_speed, self.elevation + dy * self.rotate_speed);
+    }
+
+    /// Moves towards the target for negative (scroll up) `wheel_delta`,
+    /// within `min_distance..=max_distance`
+    pub fn zoom(&mut self, wheel_delta: f32) {
+        self.distance = (self.distance * (wheel_delta * self.zoom_speed).exp())
+            .clamp(self.min_distance, self.max_distance);
+    }
+
+    /// Applies this frame's drag and scroll, then updates `camera`. Call
+    /// before [`InputState::end_frame`].
+    pub fn update(&mut self, input: &InputState, camera: &mut Camera) {
+        if input.is_button_down(0) {
+            let (dx, dy) = input.mouse_delta();
+            self.drag(dx, dy);
+        }
+        self.zoom(input.wheel_delta());
+        self.apply(camera);
+    }
+
+    /// Moves and turns `camera` to look at the target from the current
+    /// azimuth, elevation and distance
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.set_rotation(self.azimuth, -self.elevation);
+        camera.position = self.target - camera.forward() * self.distance;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).norm() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn horizontal_drag_changes_azimuth_only() {
+        let mut orbit = OrbitController::new(Vector3::zeros(), 5.);
+        orbit.drag(120., 0.);
+        assert!((orbit.azimuth() - 120. * orbit.rotate_speed).abs() < 1e-6);
+        assert_eq!(orbit.elevation(), 0.);
+        assert_eq!(orbit.distance(), 5.);
+    }
+
+    #[test]
+    fn elevation_is_clamped_short_of_the_poles() {
+        let mut orbit = OrbitController::new(Vector3::zeros(), 5.);
+        orbit.drag(0., 1e6);
+        assert_eq!(orbit.elevation(), OrbitController::MAX_ELEVATION);
+        orbit.drag(0., -1e6);
+        assert_eq!(orbit.elevation(), -OrbitController::MAX_ELEVATION);
+    }
+
+    #[test]
+    fn zoom_stays_within_limits() {
+        let mut orbit = OrbitController::new(Vector3::zeros(), 5.);
+        orbit.zoom(-100.);
+        assert!(orbit.distance() < 5.);
+        orbit.zoom(-1e6);
+        assert_eq!(orbit.distance(), orbit.min_distance);
+        orbit.zoom(1e6);
+        assert_eq!(orbit.distance(), orbit.max_distance);
+    }
+
+    #[test]
+    fn camera_looks_at_target_from_distance() {
+        let target = Vector3::new(1., 0.5, -2.);
+        let mut orbit = OrbitController::new(target, 4.);
+        let mut camera = Camera::new(Vector3::zeros(), 1., 0.1, 100.);
+        orbit.apply(&mut camera);
+        assert_close(camera.position, target + Vector3::new(0., 0., 4.));
+
+        orbit.drag(std::f32::consts::FRAC_PI_2 / orbit.rotate_speed, 0.3 / orbit.rotate_speed);
+        orbit.apply(&mut camera);
+        assert!(((camera.position - target).norm() - 4.).abs() < 1e-5);
+        assert!(camera.position.y > target.y);
+        // A quarter turn right moves the camera to the -X side
+        assert!(camera.position.x < target.x);
+        assert_close(camera.forward(), (target - camera.position).normalize());
+    }
+}
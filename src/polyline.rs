@@ -0,0 +1,216 @@
+//! Thick lines, expanded into triangles on the CPU
+
+use std::f32::consts::PI;
+
+use nalgebra::Matrix4;
+use web_sys::WebGl2RenderingContext;
+
+use crate::error::WasmGlError;
+use crate::renderer::{check_gl, Shader, VAO, VBO};
+use crate::stats::record_draw;
+use crate::{Color, Position};
+
+/// How consecutive segments are connected
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JoinStyle {
+    /// A single triangle across the outside of the corner
+    Bevel,
+    /// Segment edges extended until they meet, falling back to a bevel when
+    /// the tip would be further than `limit` half-widths from the corner
+    Miter { limit: f32 },
+    /// An arc around the outside of the corner
+    Round,
+}
+
+/// Maximum angle covered by one triangle of a round join
+const ROUND_STEP: f32 = PI / 8.;
+
+fn offset(p: Position, dir: (f32, f32), distance: f32) -> Position {
+    Position { x: p.x + dir.0 * distance, y: p.y + dir.1 * distance, z: p.z }
+}
+
+/// Appends a triangle, flipping it if needed so it faces +Z
+fn push_triangle(out: &mut Vec<Position>, a: Position, b: Position, c: Position) {
+    let facing = (b - a).cross(&(c - a)).z;
+    if facing < 0. {
+        out.extend_from_slice(&[a, c, b]);
+    } else {
+        out.extend_from_slice(&[a, b, c]);
+    }
+}
+
+/// Expands `points` into a ribbon `width` wide in the XY plane, returning a
+/// non-indexed triangle list. Zero length segments are skipped.
+pub fn polyline_triangles(points: &[Position], width: f32, join: JoinStyle) -> Vec<Position> {
+    let half = width / 2.;
+    let mut points = points.to_vec();
+    points.dedup_by(|b, a| (b.x - a.x).hypot(b.y - a.y) <= f32::EPSILON);
+    // Unit normal pointing to the left of each segment
+    let normals: Vec<(f32, f32)> = points
+        .windows(2)
+        .map(|seg| {
+            let (dx, dy) = (seg[1].x - seg[0].x, seg[1].y - seg[0].y);
+            let len = dx.hypot(dy);
+            (-dy / len, dx / len)
+        })
+        .collect();
+
+    let mut out = Vec::new();
+    for (seg, &n) in points.windows(2).zip(&normals) {
+        let (a, b) = (seg[0], seg[1]);
+        let corners = [offset(a, n, half), offset(a, n, -half), offset(b, n, half), offset(b, n, -half)];
+        push_triangle(&mut out, corners[0], corners[1], corners[2]);
+        push_triangle(&mut out, corners[2], corners[1], corners[3]);
+    }
+
+    for (i, n) in normals.windows(2).enumerate() {
+        let (prev, next) = (n[0], n[1]);
+        let center = points[i + 1];
+        // The gap opens on the right of a left turn and vice versa
+        let turn = prev.0 * next.1 - prev.1 * next.0;
+        if turn.abs() <= f32::EPSILON {
+            continue;
+        }
+        let side = if turn > 0. { -1. } else { 1. };
+        let from = (prev.0 * side, prev.1 * side);
+        let to = (next.0 * side, next.1 * side);
+        match join {
+            JoinStyle::Bevel => {
+                push_triangle(&mut out, center, offset(center, from, half), offset(center, to, half));
+            }
+            JoinStyle::Miter { limit } => {
+                let (mx, my) = (from.0 + to.0, from.1 + to.1);
+                let len = mx.hypot(my);
+                // Distance to the tip, in half-widths
+                let tip = 1. / ((mx * from.0 + my * from.1) / len);
+                if len <= f32::EPSILON || tip > limit {
+                    push_triangle(&mut out, center, offset(center, from, half), offset(center, to, half));
+                } else {
+                    let tip_pos = offset(center, (mx / len, my / len), tip * half);
+                    push_triangle(&mut out, center, offset(center, from, half), tip_pos);
+                    push_triangle(&mut out, center, tip_pos, offset(center, to, half));
+                }
+            }
+            JoinStyle::Round => {
+                let start = from.1.atan2(from.0);
+                let mut sweep = to.1.atan2(to.0) - start;
+                if sweep > PI {
+                    sweep -= 2. * PI;
+                } else if sweep < -PI {
+                    sweep += 2. * PI;
+                }
+                let steps = (sweep.abs() / ROUND_STEP).ceil().max(1.) as u32;
+                let arc = |k: u32| {
+                    let (s, c) = (start + sweep * k as f32 / steps as f32).sin_cos();
+                    offset(center, (c, s), half)
+                };
+                for k in 0..steps {
+                    push_triangle(&mut out, center, arc(k), arc(k + 1));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Draws solid colored polylines, reusing one vertex buffer between calls
+pub struct PolylineRenderer {
+    shader: Shader,
+    vao: VAO<VBO<Position>>,
+    /// Transform applied to the line points, identity by default so points
+    /// are in clip space
+    pub projection_view: Matrix4<f32>,
+}
+
+impl PolylineRenderer {
+    pub fn new(context: &WebGl2RenderingContext) -> Result<PolylineRenderer, WasmGlError> {
+        let shader = Shader::new(
+            context,
+            include_str!("./shaders/polyline.vsh"),
+            include_str!("./shaders/polyline.fsh"),
+            &["projectionView", "color"],
+            &["pos"],
+            None)?;
+        let vao = VAO_new!(
+            context,
+            (Vec::new(), WebGl2RenderingContext::ARRAY_BUFFER, WebGl2RenderingContext::STREAM_DRAW)
+        )?;
+        VBO_bind!(vao.vbos, context, shader.find_attr("pos"), Position, 3, WebGl2RenderingContext::FLOAT);
+        Ok(PolylineRenderer { shader, vao, projection_view: Matrix4::identity() })
+    }
+
+    pub fn draw_polyline(
+        &mut self,
+        context: &WebGl2RenderingContext,
+        points: &[Position],
+        width: f32,
+        join_style: JoinStyle,
+        color: Color,
+    ) {
+        *self.vao.vbos.buffer_mut() = polyline_triangles(points, width, join_style);
+        self.vao.vbos.update(context);
+        self.shader.enable(context);
+        self.shader.set_mat4(context, "projectionView", &self.projection_view);
+        context.uniform4f(Some(self.shader.find_uniform("color")), color.r, color.g, color.b, color.a);
+        self.vao.activate(context);
+        context.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, self.vao.vbos.len() as i32);
+        check_gl(context, "draw_polyline");
+        record_draw(context, WebGl2RenderingContext::TRIANGLES, self.vao.vbos.len() as i32, 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(x: f32, y: f32) -> Position {
+        Position { x, y, z: 0. }
+    }
+
+    fn right_angle() -> Vec<Position> {
+        vec![p(0., 0.), p(1., 0.), p(1., 1.)]
+    }
+
+    #[test]
+    fn straight_line_is_one_quad() {
+        let tris = polyline_triangles(&[p(0., 0.), p(1., 0.), p(2., 0.)], 0.2, JoinStyle::Round);
+        // Two quads, and no join since the line doesn't turn
+        assert_eq!(tris.len(), 2 * 6);
+        assert!(tris.iter().all(|v| (v.y.abs() - 0.1).abs() < 1e-6));
+    }
+
+    #[test]
+    fn miter_reaches_the_corner_tip() {
+        let tris = polyline_triangles(&right_angle(), 0.2, JoinStyle::Miter { limit: 4. });
+        assert_eq!(tris.len(), 2 * 6 + 2 * 3);
+        assert!(tris.iter().any(|v| (v.x - 1.1).abs() < 1e-5 && (v.y + 0.1).abs() < 1e-5));
+    }
+
+    #[test]
+    fn sharp_miter_falls_back_to_bevel() {
+        let hairpin = [p(0., 0.), p(1., 0.), p(0., 0.1)];
+        let tris = polyline_triangles(&hairpin, 0.2, JoinStyle::Miter { limit: 4. });
+        assert_eq!(tris.len(), 2 * 6 + 3);
+    }
+
+    #[test]
+    fn round_join_stays_on_the_arc() {
+        let tris = polyline_triangles(&right_angle(), 0.2, JoinStyle::Round);
+        // A quarter turn needs 4 steps of PI / 8
+        assert_eq!(tris.len(), 2 * 6 + 4 * 3);
+        for v in &tris[12..] {
+            let r = (v.x - 1.).hypot(v.y);
+            assert!(r < 1e-6 || (r - 0.1).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn triangles_face_forward_and_skip_duplicates() {
+        let tris = polyline_triangles(&[p(0., 0.), p(0., 0.), p(1., 0.), p(1., 1.)], 0.2, JoinStyle::Bevel);
+        assert_eq!(tris.len(), 2 * 6 + 3);
+        for tri in tris.chunks_exact(3) {
+            assert!((tri[1] - tri[0]).cross(&(tri[2] - tri[0])).z >= 0.);
+            assert!(tri.iter().all(|v| v.x.is_finite() && v.y.is_finite()));
+        }
+    }
+}
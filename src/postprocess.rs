@@ -0,0 +1,332 @@
+//! Screen-space passes run over a rendered frame
+
+use js_sys::Int32Array;
+use nalgebra::Vector2;
+use wasm_bindgen::JsCast;
+use web_sys::{WebGl2RenderingContext, WebGlProgram, WebGlVertexArrayObject};
+
+use crate::error::WasmGlError;
+use crate::renderer::{check_gl, BindCache, CascadedShadowMap, Framebuffer, Shader, Texture2D, TextureFormat};
+use crate::stats::record_draw;
+
+/// A fragment shader run once per pixel of its target, over a single
+/// triangle covering the screen. The shader receives `in vec2 uv` in
+/// `0..1`, and its inputs are bound to texture units in the order given.
+pub struct PostProcessPass {
+    shader: Shader,
+    inputs: Vec<String>,
+}
+
+impl PostProcessPass {
+    /// `inputs` names the fragment shader's sampler uniforms
+    pub fn new(context: &WebGl2RenderingContext, fragment_src: &str, inputs: &[&str]) -> Result<PostProcessPass, WasmGlError> {
+        let shader = Shader::new_auto(context, include_str!("./shaders/fullscreen.vsh"), fragment_src, None)?;
+        if let Some(missing) = inputs.iter().find(|input| shader.get_uniform(input).is_none()) {
+            return Err(WasmGlError::MissingUniform { name: String::from(*missing) });
+        }
+        Ok(PostProcessPass { shader, inputs: inputs.iter().map(|input| String::from(*input)).collect() })
+    }
+
+    /// Encodes linear color as sRGB, for the last pass before the canvas
+    pub fn gamma(context: &WebGl2RenderingContext) -> Result<PostProcessPass, WasmGlError> {
+        PostProcessPass::new(context, include_str!("./shaders/gamma.fsh"), &["source"])
+    }
+
+    /// For setting any extra uniforms, after enabling it
+    pub fn shader(&self) -> &Shader {
+        &self.shader
+    }
+
+    /// Runs the pass into `target`, or the canvas if `None`, reading
+    /// `inputs[i]` through the `i`th named sampler. Depth testing is off
+    /// while it draws.
+    pub fn draw(
+        &self,
+        context: &WebGl2RenderingContext,
+        cache: &BindCache,
+        inputs: &[&Texture2D],
+        target: Option<&Framebuffer>,
+        screen_w: i32,
+        screen_h: i32,
+    ) {
+        assert_eq!(inputs.len(), self.inputs.len(), "Post process pass expects {} inputs", self.inputs.len());
+        match target {
+            Some(target) => target.bind(context),
+            None => {
+                context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+                context.viewport(0, 0, screen_w, screen_h);
+            }
+        }
+        self.shader.enable_cached(context, cache);
+        for (unit, (name, texture)) in self.inputs.iter().zip(inputs).enumerate() {
+            texture.bind(context, unit as u32);
+            self.shader.set_texture(context, name, unit as u32);
+        }
+        let depth_test = context.is_enabled(WebGl2RenderingContext::DEPTH_TEST);
+        context.disable(WebGl2RenderingContext::DEPTH_TEST);
+        // The vertex shader builds the triangle from `gl_VertexID`, so no
+        // attributes are needed
+        cache.bind_vertex_array(context, None);
+        context.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 3);
+        check_gl(context, "PostProcessPass draw");
+        record_draw(context, WebGl2RenderingContext::TRIANGLES, 3, 1);
+        if depth_test {
+            context.enable(WebGl2RenderingContext::DEPTH_TEST);
+        }
+    }
+}
+
+/// Where pass `i` of `count` writes: ping-ponging between the two
+/// intermediate targets, with the last pass going to the canvas (`None`)
+fn pass_target(i: usize, count: usize) -> Option<usize> {
+    if i + 1 == count {
+        None
+    } else {
+        Some(i % 2)
+    }
+}
+
+/// Renders a scene offscreen and runs it through a list of single-input
+/// passes on its way to the canvas. Each pass reads the previous one's
+/// output, starting with the scene's color.
+pub struct PostProcessChain {
+    scene: Framebuffer,
+    intermediate: Vec<Framebuffer>,
+    passes: Vec<PostProcessPass>,
+}
+
+impl PostProcessChain {
+    /// Creates the offscreen targets at `width` x `height`, which should
+    /// follow the canvas via [`PostProcessChain::resize`]
+    pub fn new(
+        context: &WebGl2RenderingContext,
+        width: u32,
+        height: u32,
+        passes: Vec<PostProcessPass>,
+    ) -> Result<PostProcessChain, WasmGlError> {
+        if passes.is_empty() || passes.iter().any(|pass| pass.inputs.len() != 1) {
+            return Err(WasmGlError::InvalidArgument(String::from(
+                "A post process chain needs one or more single-input passes")));
+        }
+        let scene = Framebuffer::new(context, width, height)
+            .with_color(TextureFormat::Rgba8)
+            .with_depth_renderbuffer()
+            .build()?;
+        let intermediate = (0..(passes.len() - 1).min(2))
+            .map(|_| Framebuffer::new(context, width, height).with_color(TextureFormat::Rgba8).build())
+            .collect::<Result<_, _>>()?;
+        Ok(PostProcessChain { scene, intermediate, passes })
+    }
+
+    pub fn resize(&mut self, context: &WebGl2RenderingContext, width: u32, height: u32) -> Result<(), WasmGlError> {
+        for target in std::iter::once(&mut self.scene).chain(&mut self.intermediate) {
+            target.resize(context, width, height)?;
+        }
+        Ok(())
+    }
+
+    /// The framebuffer to render the scene into
+    pub fn scene(&self) -> &Framebuffer {
+        &self.scene
+    }
+
+    /// Runs every pass, leaving the result on the canvas
+    pub fn run(&self, context: &WebGl2RenderingContext, cache: &BindCache, screen_w: i32, screen_h: i32) {
+        let mut source = self.scene.color_texture(0).unwrap();
+        for (i, pass) in self.passes.iter().enumerate() {
+            let target = pass_target(i, self.passes.len()).map(|t| &self.intermediate[t]);
+            pass.draw(context, cache, &[source], target, screen_w, screen_h);
+            if let Some(target) = target {
+                source = target.color_texture(0).unwrap();
+            }
+        }
+    }
+}
+
+/// Where [`DebugQuad`] draws, within the current viewport
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScreenCorner {
+    BottomLeft,
+    BottomRight,
+    TopLeft,
+    TopRight,
+}
+
+/// `(x, y, width, height)` of a rectangle `size_fraction` of the viewport's
+/// height tall, with the given aspect ratio, in `corner` of `viewport`
+fn corner_rect(viewport: [i32; 4], corner: ScreenCorner, size_fraction: f32, aspect: f32) -> [i32; 4] {
+    let [x, y, w, h] = viewport;
+    let height = ((h as f32 * size_fraction) as i32).clamp(1, h.max(1));
+    let width = ((height as f32 * aspect) as i32).clamp(1, w.max(1));
+    let (left, bottom) = match corner {
+        ScreenCorner::BottomLeft => (x, y),
+        ScreenCorner::BottomRight => (x + w - width, y),
+        ScreenCorner::TopLeft => (x, y + h - height),
+        ScreenCorner::TopRight => (x + w - width, y + h - height),
+    };
+    [left, bottom, width, height]
+}
+
+/// GL state [`DebugQuad`] changes, restored once it has drawn
+struct SavedState {
+    viewport: [i32; 4],
+    scissor: [i32; 4],
+    scissor_test: bool,
+    depth_test: bool,
+    program: Option<WebGlProgram>,
+    vertex_array: Option<WebGlVertexArrayObject>,
+}
+
+impl SavedState {
+    fn save(context: &WebGl2RenderingContext) -> Result<SavedState, WasmGlError> {
+        let rect = |parameter: u32| -> Result<[i32; 4], WasmGlError> {
+            let mut rect = [0; 4];
+            Int32Array::from(context.get_parameter(parameter)?).copy_to(&mut rect);
+            Ok(rect)
+        };
+        Ok(SavedState {
+            viewport: rect(WebGl2RenderingContext::VIEWPORT)?,
+            scissor: rect(WebGl2RenderingContext::SCISSOR_BOX)?,
+            scissor_test: context.is_enabled(WebGl2RenderingContext::SCISSOR_TEST),
+            depth_test: context.is_enabled(WebGl2RenderingContext::DEPTH_TEST),
+            program: context.get_parameter(WebGl2RenderingContext::CURRENT_PROGRAM)?.dyn_into().ok(),
+            vertex_array: context.get_parameter(WebGl2RenderingContext::VERTEX_ARRAY_BINDING)?.dyn_into().ok(),
+        })
+    }
+
+    fn restore(self, context: &WebGl2RenderingContext) {
+        let [x, y, w, h] = self.viewport;
+        context.viewport(x, y, w, h);
+        let [x, y, w, h] = self.scissor;
+        context.scissor(x, y, w, h);
+        let set = |capability: u32, enabled: bool| if enabled {
+            context.enable(capability)
+        } else {
+            context.disable(capability)
+        };
+        set(WebGl2RenderingContext::SCISSOR_TEST, self.scissor_test);
+        set(WebGl2RenderingContext::DEPTH_TEST, self.depth_test);
+        context.use_program(self.program.as_ref());
+        context.bind_vertex_array(self.vertex_array.as_ref());
+    }
+}
+
+/// Shows a texture in a corner of whatever is being rendered to, for
+/// inspecting shadow maps and other intermediate targets. It draws with its
+/// own program and restores the viewport, scissor, depth test, program and
+/// vertex array it changes, so it can be dropped in anywhere in a frame.
+/// Texture units 0 and 1 are left bound to what it showed.
+pub struct DebugQuad {
+    shader: Shader,
+    /// Near and far planes of the perspective projection that rendered a
+    /// depth texture, to show depth linearly. `None` shows depth as stored,
+    /// which is already linear for orthographic projections like
+    /// [`ShadowMap`](crate::renderer::ShadowMap)'s.
+    pub depth_range: Option<(f32, f32)>,
+}
+
+impl DebugQuad {
+    pub fn new(context: &WebGl2RenderingContext) -> Result<DebugQuad, WasmGlError> {
+        let shader = Shader::new_auto(
+            context,
+            include_str!("./shaders/fullscreen.vsh"),
+            include_str!("./shaders/debug_quad.fsh"),
+            None,
+        )?;
+        Ok(DebugQuad { shader, depth_range: None })
+    }
+
+    /// Draws `texture` into `corner` of the current viewport,
+    /// `size_fraction` of its height tall, keeping the texture's aspect
+    /// ratio. Depth formats are shown in greyscale.
+    pub fn draw_texture(
+        &self,
+        context: &WebGl2RenderingContext,
+        texture: &Texture2D,
+        corner: ScreenCorner,
+        size_fraction: f32,
+    ) -> Result<(), WasmGlError> {
+        texture.bind(context, 0);
+        let depth = texture.format() == TextureFormat::Depth32F;
+        let aspect = texture.width() as f32 / texture.height() as f32;
+        self.draw(context, -1, depth, corner, size_fraction, aspect)
+    }
+
+    /// Draws cascade `layer` of `cascades` like [`DebugQuad::draw_texture`]
+    pub fn draw_cascade(
+        &self,
+        context: &WebGl2RenderingContext,
+        cascades: &CascadedShadowMap,
+        layer: usize,
+        corner: ScreenCorner,
+        size_fraction: f32,
+    ) -> Result<(), WasmGlError> {
+        if layer >= cascades.count() {
+            return Err(WasmGlError::InvalidArgument(format!(
+                "Cascade {layer} is out of range for {} cascades", cascades.count())));
+        }
+        cascades.bind_texture(context, 1);
+        self.draw(context, layer as i32, true, corner, size_fraction, 1.)
+    }
+
+    fn draw(
+        &self,
+        context: &WebGl2RenderingContext,
+        layer: i32,
+        depth: bool,
+        corner: ScreenCorner,
+        size_fraction: f32,
+        aspect: f32,
+    ) -> Result<(), WasmGlError> {
+        let saved = SavedState::save(context)?;
+        let [x, y, w, h] = corner_rect(saved.viewport, corner, size_fraction, aspect);
+        context.viewport(x, y, w, h);
+        context.scissor(x, y, w, h);
+        context.enable(WebGl2RenderingContext::SCISSOR_TEST);
+        context.disable(WebGl2RenderingContext::DEPTH_TEST);
+
+        self.shader.enable(context);
+        // Samplers of different types must be on different units
+        self.shader.set_texture(context, "source", 0);
+        self.shader.set_texture(context, "sourceLayers", 1);
+        self.shader.set_i32(context, "layer", layer);
+        let (depth_mode, (near, far)) = match (depth, self.depth_range) {
+            (false, _) => (0, (0., 1.)),
+            (true, None) => (1, (0., 1.)),
+            (true, Some(range)) => (2, range),
+        };
+        self.shader.set_i32(context, "depthMode", depth_mode);
+        self.shader.set_vec2(context, "depthRange", &Vector2::new(near, far));
+        // Like `PostProcessPass`, the triangle comes from `gl_VertexID`
+        context.bind_vertex_array(None);
+        context.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 3);
+        check_gl(context, "DebugQuad draw");
+        record_draw(context, WebGl2RenderingContext::TRIANGLES, 3, 1);
+
+        saved.restore(context);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_ping_pong_and_end_on_the_canvas() {
+        let targets: Vec<_> = (0..4).map(|i| pass_target(i, 4)).collect();
+        assert_eq!(targets, [Some(0), Some(1), Some(0), None]);
+        assert_eq!(pass_target(0, 1), None);
+    }
+
+    #[test]
+    fn corner_rects_keep_aspect_inside_the_viewport() {
+        let viewport = [10, 20, 400, 200];
+        assert_eq!(corner_rect(viewport, ScreenCorner::BottomLeft, 0.25, 1.), [10, 20, 50, 50]);
+        assert_eq!(corner_rect(viewport, ScreenCorner::TopRight, 0.25, 2.), [310, 170, 100, 50]);
+        assert_eq!(corner_rect(viewport, ScreenCorner::TopLeft, 0.5, 1.), [10, 120, 100, 100]);
+        // Clamped to the viewport, and never empty
+        assert_eq!(corner_rect(viewport, ScreenCorner::BottomRight, 1., 4.), [10, 20, 400, 200]);
+        assert_eq!(corner_rect(viewport, ScreenCorner::BottomLeft, 0., 1.), [10, 20, 1, 1]);
+    }
+}
@@ -1,29 +1,180 @@
 use std::{
-    cell::RefCell, collections::HashMap, iter::{zip, FromIterator}, rc::Rc
+    any::Any, cell::{Cell, RefCell}, collections::HashMap, iter::FromIterator, ops::Range, rc::{Rc, Weak}
 };
 
-use js_sys::{Array, Uint8Array};
-use nalgebra::{Matrix, Matrix4};
-use wasm_bindgen::{prelude::*, throw_str};
+use js_sys::Uint8Array;
+use nalgebra::{Matrix3, Matrix4, Vector2, Vector3};
+use wasm_bindgen::{prelude::*, throw_str, throw_val, Clamped, JsCast};
+use wasm_bindgen_futures::JsFuture;
 use web_sys::{
-    window, WebGl2RenderingContext, WebGlBuffer, WebGlProgram, WebGlShader, WebGlUniformLocation, WebGlVertexArrayObject
+    window, AddEventListenerOptions, CanvasRenderingContext2d, HtmlCanvasElement, HtmlImageElement, ImageData, WebGl2RenderingContext, WebGlActiveInfo, WebGlBuffer, WebGlFramebuffer, WebGlProgram, WebGlRenderbuffer, WebGlShader, WebGlTexture, WebGlUniformLocation, WebGlVertexArrayObject, Window
 };
 
-fn request_animation_frame(f: &Closure<dyn FnMut()>) {
+use crate::error::WasmGlError;
+use crate::stats::{record_draw, record_shader_switch, record_texture_bind, record_upload};
+use crate::Color;
+
+thread_local! {
+    static LIVE_GL_OBJECTS: Cell<usize> = const { Cell::new(0) };
+    static BUFFER_UPLOADS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// How many GL objects (buffers, vertex arrays, programs, textures,
+/// framebuffers and renderbuffers) are owned by this crate's wrappers and
+/// not yet deleted, for spotting leaks
+pub fn live_gl_objects() -> usize {
+    LIVE_GL_OBJECTS.with(Cell::get)
+}
+
+/// Called by each wrapper once it owns a new GL object
+pub(crate) fn gl_object_created() {
+    LIVE_GL_OBJECTS.with(|live| live.set(live.get() + 1));
+}
+
+/// Called by each wrapper's `Drop` for every GL object it deletes
+pub(crate) fn gl_object_deleted() {
+    LIVE_GL_OBJECTS.with(|live| live.set(live.get() - 1));
+}
+
+/// How many times a [`VBO`] has uploaded data to its GL buffer, for checking
+/// that unchanged buffers aren't re-uploaded
+pub fn buffer_uploads() -> usize {
+    BUFFER_UPLOADS.with(Cell::get)
+}
+
+fn buffer_uploaded() {
+    BUFFER_UPLOADS.with(|uploads| uploads.set(uploads.get() + 1));
+}
+
+/// Panics if GL has recorded any errors, naming them and `label`, the
+/// operation just issued. Only checks with the `gl-debug` feature;
+/// otherwise this does nothing.
+#[cfg(feature = "gl-debug")]
+pub fn check_gl(ctx: &WebGl2RenderingContext, label: &str) {
+    // Each kind of error is recorded separately until read, and context
+    // loss is reported once, so this always ends
+    let errors: Vec<_> = std::iter::from_fn(|| match ctx.get_error() {
+        WebGl2RenderingContext::NO_ERROR => None,
+        error => Some(gl_error_name(error)),
+    }).take(8).collect();
+    if !errors.is_empty() {
+        panic!("GL error {} after {}", errors.join(", "), label);
+    }
+}
+
+#[cfg(not(feature = "gl-debug"))]
+#[inline(always)]
+pub fn check_gl(_ctx: &WebGl2RenderingContext, _label: &str) {}
+
+#[cfg(feature = "gl-debug")]
+fn gl_error_name(error: u32) -> &'static str {
+    match error {
+        WebGl2RenderingContext::INVALID_ENUM => "INVALID_ENUM",
+        WebGl2RenderingContext::INVALID_VALUE => "INVALID_VALUE",
+        WebGl2RenderingContext::INVALID_OPERATION => "INVALID_OPERATION",
+        WebGl2RenderingContext::INVALID_FRAMEBUFFER_OPERATION => "INVALID_FRAMEBUFFER_OPERATION",
+        WebGl2RenderingContext::OUT_OF_MEMORY => "OUT_OF_MEMORY",
+        WebGl2RenderingContext::CONTEXT_LOST_WEBGL => "CONTEXT_LOST_WEBGL",
+        _ => "unknown error",
+    }
+}
+
+fn request_animation_frame(f: &FrameClosure) -> i32 {
     web_sys::window()
         .unwrap()
         .request_animation_frame(f.as_ref().unchecked_ref())
-        .expect("should register `requestAnimationFrame` OK");
+        .expect("should register `requestAnimationFrame` OK")
+}
+
+fn shader_stage(shader_type: u32) -> &'static str {
+    match shader_type {
+        WebGl2RenderingContext::VERTEX_SHADER => "vertex",
+        _ => "fragment",
+    }
+}
+
+/// Splits a leading decimal number off `text`
+fn split_number(text: &str) -> Option<(usize, &str)> {
+    let end = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    Some((text[..end].parse().ok()?, &text[end..]))
+}
+
+/// Finds the source location in an info log message: `0:27: ...` as
+/// reported by ANGLE (Chrome, Firefox and Safari), or `0:27(5): ...` as
+/// reported by Mesa. Returns the 1-based line and, if given, column.
+fn log_location(message: &str) -> Option<(usize, Option<usize>)> {
+    let bytes = message.as_bytes();
+    (0..bytes.len())
+        .filter(|&i| message.is_char_boundary(i) && (i == 0 || !bytes[i - 1].is_ascii_digit()))
+        .find_map(|start| {
+            let (_source_string, rest) = split_number(&message[start..])?;
+            let (line, rest) = split_number(rest.strip_prefix(':')?)?;
+            match rest.strip_prefix('(').and_then(split_number) {
+                Some((column, rest)) if rest.starts_with(')') => Some((line, Some(column))),
+                _ if rest.starts_with(':') => Some((line, None)),
+                _ => None,
+            }
+        })
+}
+
+/// Quotes the source line each info log message points at, with
+/// `CONTEXT_LINES` either side and a caret under the column or the quoted
+/// token if the message has one. Returns the log unchanged if no message
+/// points into `source`.
+fn annotate_compile_log(source: &str, log: &str) -> String {
+    const CONTEXT_LINES: usize = 2;
+    let lines: Vec<&str> = source.lines().collect();
+    let width = lines.len().to_string().len();
+    let mut output = String::new();
+    let mut annotated = false;
+    for message in log.lines().map(|message| message.trim_end_matches('\0').trim_end()) {
+        if message.is_empty() {
+            continue;
+        }
+        output.push_str(message);
+        output.push('\n');
+        let Some((line, column)) = log_location(message).filter(|&(line, _)| (1..=lines.len()).contains(&line)) else {
+            continue;
+        };
+        annotated = true;
+        let text = lines[line - 1];
+        let caret = match column {
+            Some(column) => Some((column.saturating_sub(1).min(text.len()), 1)),
+            None => message
+                .split('\'')
+                .nth(1)
+                .filter(|token| !token.is_empty())
+                .and_then(|token| text.find(token).map(|start| (start, token.len()))),
+        };
+        for i in line.saturating_sub(CONTEXT_LINES).max(1)..=(line + CONTEXT_LINES).min(lines.len()) {
+            let marker = if i == line { '>' } else { ' ' };
+            output.push_str(&format!("{marker} {i:>width$} | {}\n", lines[i - 1]));
+            if let Some((start, len)) = caret.filter(|_| i == line) {
+                // Tabs are kept so the caret lines up however wide they show
+                let padding: String = text
+                    .char_indices()
+                    .take_while(|&(index, _)| index < start)
+                    .map(|(_, c)| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                output.push_str(&format!("  {:width$} | {}{}\n", "", padding, "^".repeat(len)));
+            }
+        }
+    }
+    if annotated {
+        output
+    } else {
+        String::from(log)
+    }
 }
 
 fn compile_shader(
     context: &WebGl2RenderingContext,
     shader_type: u32,
     source: &str,
-) -> Result<WebGlShader, String> {
+) -> Result<WebGlShader, WasmGlError> {
     let shader = context
         .create_shader(shader_type)
-        .ok_or_else(|| String::from("Unable to create shader object"))?;
+        .ok_or(WasmGlError::ResourceCreation { what: "shader" })?;
     context.shader_source(&shader, source);
     context.compile_shader(&shader);
 
@@ -34,9 +185,12 @@ fn compile_shader(
     {
         Ok(shader)
     } else {
-        Err(context
+        let log = context
             .get_shader_info_log(&shader)
-            .unwrap_or_else(|| String::from("Unknown error creating shader")))
+            .map(|log| annotate_compile_log(source, &log))
+            .unwrap_or_else(|| String::from("Unknown error creating shader"));
+        context.delete_shader(Some(&shader));
+        Err(WasmGlError::ShaderCompile { stage: shader_stage(shader_type), log })
     }
 }
 
@@ -45,16 +199,16 @@ fn link_program(
     vert_shader: &WebGlShader,
     frag_shader: &WebGlShader,
     bound_attribute_locations: Option<&HashMap<&str, u32>>,
-) -> Result<WebGlProgram, String> {
+) -> Result<WebGlProgram, WasmGlError> {
     let program = context
         .create_program()
-        .ok_or_else(|| String::from("Unable to create shader object"))?;
+        .ok_or(WasmGlError::ResourceCreation { what: "program" })?;
 
     context.attach_shader(&program, vert_shader);
     context.attach_shader(&program, frag_shader);
-    if bound_attribute_locations.is_some() {
-        for (k, v) in bound_attribute_locations.unwrap() {
-            context.bind_attrib_location(&program, *v, *k);
+    if let Some(locations) = bound_attribute_locations {
+        for (k, v) in locations {
+            context.bind_attrib_location(&program, *v, k);
         }
     }
     context.link_program(&program);
@@ -66,33 +220,186 @@ fn link_program(
     {
         Ok(program)
     } else {
-        Err(context
+        let log = context
             .get_program_info_log(&program)
-            .unwrap_or_else(|| String::from("Unknown error creating program object")))
+            .unwrap_or_else(|| String::from("Unknown error creating program object"));
+        context.delete_program(Some(&program));
+        Err(WasmGlError::ProgramLink { log })
     }
 }
 
-trait OrThrow<T> {
+/// Throws the error as a JS exception instead of returning it, for callers
+/// such as render loop callbacks with nowhere to propagate it to
+pub trait OrThrow<T> {
     fn or_throw(self) -> T;
 }
 
 impl<T> OrThrow<T> for Result<T, String> {
     fn or_throw(self) -> T {
-        if self.is_err() {
-            throw_str(&self.err().unwrap())
-        } else {
-            self.unwrap()
+        match self {
+            Ok(value) => value,
+            Err(err) => throw_str(&err),
+        }
+    }
+}
+
+impl<T> OrThrow<T> for Result<T, WasmGlError> {
+    fn or_throw(self) -> T {
+        match self {
+            Ok(value) => value,
+            Err(err) => throw_val(err.into()),
+        }
+    }
+}
+
+/// Replaces every `#include "name"` line in `source` with the snippet
+/// `includes[name]`, itself preprocessed, since GLSL ES has no includes of
+/// its own. Fails on unknown names, malformed directives and include cycles.
+pub fn preprocess_includes(source: &str, includes: &HashMap<&str, &str>) -> Result<String, String> {
+    let mut output = String::with_capacity(source.len());
+    expand_includes(source, includes, &mut Vec::new(), &mut output)?;
+    Ok(output)
+}
+
+fn expand_includes<'a>(
+    source: &str,
+    includes: &HashMap<&'a str, &'a str>,
+    stack: &mut Vec<&'a str>,
+    output: &mut String,
+) -> Result<(), String> {
+    for line in source.lines() {
+        let directive = match line.trim().strip_prefix("#include") {
+            Some(rest) => rest.trim(),
+            None => {
+                output.push_str(line);
+                output.push('\n');
+                continue;
+            }
+        };
+        let name = directive
+            .strip_prefix('"')
+            .and_then(|rest| rest.strip_suffix('"'))
+            .ok_or_else(|| format!("Malformed include `{}`, expected #include \"name\"", line.trim()))?;
+        let (&name, &snippet) = includes
+            .get_key_value(name)
+            .ok_or_else(|| format!("Include \"{name}\" was not found"))?;
+        if stack.contains(&name) {
+            let cycle = stack.iter().chain(Some(&name)).copied().collect::<Vec<_>>().join(" -> ");
+            return Err(format!("Include cycle: {cycle}"));
         }
+        stack.push(name);
+        expand_includes(snippet, includes, stack, output)?;
+        stack.pop();
     }
+    Ok(())
 }
 
+/// A linked program with its registered uniform and attribute locations.
+/// The program is deleted when the `Shader` is dropped.
 pub struct Shader {
+    // Only a handle to the JS object, which never references the Shader back
+    context: WebGl2RenderingContext,
     program: WebGlProgram,
     attribute_locations: HashMap<String, u32>,
     uniform_locations: HashMap<String, WebGlUniformLocation>,
+    uniform_values: UniformCache,
+}
+
+impl Drop for Shader {
+    fn drop(&mut self) {
+        self.context.delete_program(Some(&self.program));
+        gl_object_deleted();
+    }
+}
+
+/// Last uploaded value of each uniform, stored as raw bit patterns so that
+/// floats and ints share one map and NaNs still compare equal
+#[derive(Default)]
+struct UniformCache(HashMap<String, Vec<u32>>);
+
+impl UniformCache {
+    /// Records `bits` for `name`, returning false if it was already there.
+    /// Only allocates the first time `name` is set.
+    fn replace(&mut self, name: &str, bits: impl Iterator<Item = u32> + Clone) -> bool {
+        match self.0.get_mut(name) {
+            Some(stored) if stored.iter().copied().eq(bits.clone()) => false,
+            Some(stored) => {
+                stored.clear();
+                stored.extend(bits);
+                true
+            }
+            None => {
+                self.0.insert(String::from(name), bits.collect());
+                true
+            }
+        }
+    }
+}
+
+/// Compiles both stages and links them, cleaning up the intermediate shader
+/// objects whether or not it succeeds
+fn build_program(
+    context: &WebGl2RenderingContext,
+    vertex_src: &str,
+    fragment_src: &str,
+    bound_attribute_locations: Option<&HashMap<&str, u32>>,
+) -> Result<WebGlProgram, WasmGlError> {
+    let vert_shader = compile_shader(
+        context,
+        WebGl2RenderingContext::VERTEX_SHADER,
+        vertex_src
+    )?;
+    let frag_shader = compile_shader(
+        context,
+        WebGl2RenderingContext::FRAGMENT_SHADER,
+        fragment_src,
+    ).inspect_err(|_| context.delete_shader(Some(&vert_shader)))?;
+    let program = link_program(context, &vert_shader, &frag_shader, bound_attribute_locations);
+    context.delete_shader(Some(&vert_shader));
+    context.delete_shader(Some(&frag_shader));
+    program
+}
+
+/// The names an active uniform or attribute is registered under: arrays by
+/// their base name, as well as each element (`name[i]`) so they can be set
+/// individually. Elements of arrays of structs are already reported one
+/// member at a time, as `name[i].member`.
+fn element_names(name: String, size: i32) -> Vec<String> {
+    match name.strip_suffix("[0]") {
+        Some(base) => std::iter::once(String::from(base))
+            .chain((0..size).map(|i| format!("{base}[{i}]")))
+            .collect(),
+        None => vec![name],
+    }
+}
+
+/// Names of the program's active uniforms or attributes (per `count_param`),
+/// as registered by [`element_names`], with built-ins skipped
+fn active_names(
+    context: &WebGl2RenderingContext,
+    program: &WebGlProgram,
+    count_param: u32,
+    get_active: impl Fn(&WebGlProgram, u32) -> Option<WebGlActiveInfo>,
+) -> Vec<String> {
+    let count = context
+        .get_program_parameter(program, count_param)
+        .as_f64()
+        .unwrap_or(0.) as u32;
+    (0..count)
+        .filter_map(|i| get_active(program, i))
+        .flat_map(|info| element_names(info.name(), info.size()))
+        .filter(|name| !name.starts_with("gl_"))
+        .collect()
 }
 
+/// Seconds since the render loop started, registered by every shader that
+/// declares it and uploaded with [`Shader::set_time`]
+pub const TIME_UNIFORM: &str = "uTime";
+
 impl Shader {
+    /// Compiles and links a shader program, registering the locations of
+    /// the given uniforms (all of which must be active) and attributes, and
+    /// of [`TIME_UNIFORM`] if the program uses it
     pub fn new(
         context: &WebGl2RenderingContext,
         vertex_src: &str,
@@ -100,56 +407,392 @@ impl Shader {
         uniforms: &[&str],
         attributes: &[&str],
         bound_attribute_locations: Option<&HashMap<&str, u32>>,
-    ) -> Shader {
-        let vert_shader = compile_shader(
-            context,
-            WebGl2RenderingContext::VERTEX_SHADER,
-            vertex_src
-        ).or_throw();
-        let frag_shader = compile_shader(
-            context,
-            WebGl2RenderingContext::FRAGMENT_SHADER,
-            fragment_src,
-        ).or_throw();
-        let program = link_program(context, &vert_shader, &frag_shader, bound_attribute_locations)
-            .or_throw();
-        context.delete_shader(Some(&vert_shader));
-        context.delete_shader(Some(&frag_shader));
-        Shader {
+    ) -> Result<Shader, WasmGlError> {
+        let program = build_program(context, vertex_src, fragment_src, bound_attribute_locations)?;
+        let uniform_locations = uniforms.iter().map(|attr| {
+            context.get_uniform_location(&program, attr)
+                .map(|location| (String::from(*attr), location))
+                .ok_or_else(|| WasmGlError::MissingUniform { name: String::from(*attr) })
+        }).collect::<Result<HashMap<_, _>, _>>();
+        let mut uniform_locations = match uniform_locations {
+            Ok(locations) => locations,
+            Err(err) => {
+                context.delete_program(Some(&program));
+                return Err(err);
+            }
+        };
+        if let Some(location) = context.get_uniform_location(&program, TIME_UNIFORM) {
+            uniform_locations.insert(String::from(TIME_UNIFORM), location);
+        }
+        gl_object_created();
+        Ok(Shader {
             attribute_locations: HashMap::from_iter(attributes.iter().map(|attr| {
                 (
                     String::from(*attr),
                     context.get_attrib_location(&program, attr) as u32,
                 )
             })),
-            uniform_locations: HashMap::from_iter(uniforms.iter().map(|attr| {
-                (
-                    String::from(*attr),
-                    context.get_uniform_location(&program, attr).expect_throw(format!("Uniform {attr} was not found").as_str()),
-                )
+            uniform_locations,
+            uniform_values: UniformCache::default(),
+            context: context.clone(),
+            program,
+        })
+    }
+
+    /// Like [`Shader::new`], but registers every active uniform and
+    /// attribute reported by the linked program instead of taking name lists.
+    /// Uniforms inside uniform blocks have no location and are skipped.
+    pub fn new_auto(
+        context: &WebGl2RenderingContext,
+        vertex_src: &str,
+        fragment_src: &str,
+        bound_attribute_locations: Option<&HashMap<&str, u32>>,
+    ) -> Result<Shader, WasmGlError> {
+        let program = build_program(context, vertex_src, fragment_src, bound_attribute_locations)?;
+        let uniform_names = active_names(
+            context, &program, WebGl2RenderingContext::ACTIVE_UNIFORMS,
+            |program, i| context.get_active_uniform(program, i));
+        let attribute_names = active_names(
+            context, &program, WebGl2RenderingContext::ACTIVE_ATTRIBUTES,
+            |program, i| context.get_active_attrib(program, i));
+        gl_object_created();
+        Ok(Shader {
+            attribute_locations: HashMap::from_iter(attribute_names.into_iter().map(|attr| {
+                let location = context.get_attrib_location(&program, &attr) as u32;
+                (attr, location)
             })),
+            uniform_locations: HashMap::from_iter(uniform_names.into_iter().filter_map(|name| {
+                context.get_uniform_location(&program, &name).map(|location| (name, location))
+            })),
+            uniform_values: UniformCache::default(),
+            context: context.clone(),
             program,
-        }
+        })
+    }
+
+    /// Like [`Shader::new_auto`], but first expands `#include "name"` lines
+    /// in both sources from `includes` (see [`preprocess_includes`])
+    pub fn new_with_includes(
+        context: &WebGl2RenderingContext,
+        vertex_src: &str,
+        fragment_src: &str,
+        includes: &HashMap<&str, &str>,
+        bound_attribute_locations: Option<&HashMap<&str, u32>>,
+    ) -> Result<Shader, WasmGlError> {
+        let preprocess = |source, stage| preprocess_includes(source, includes)
+            .map_err(|log| WasmGlError::ShaderCompile { stage, log });
+        let vertex_src = preprocess(vertex_src, "vertex")?;
+        let fragment_src = preprocess(fragment_src, "fragment")?;
+        Shader::new_auto(context, &vertex_src, &fragment_src, bound_attribute_locations)
+    }
+
+    /// Recompiles from new sources in place, e.g. for live editing. On
+    /// failure the current program is kept, untouched and still usable. On
+    /// success every active uniform and attribute is registered, as in
+    /// [`Shader::new_auto`], with attributes kept at their current
+    /// locations so existing VAOs stay valid. Cached uniform values are
+    /// forgotten, and the new program must be enabled again.
+    pub fn reload(&mut self, context: &WebGl2RenderingContext, vertex_src: &str, fragment_src: &str) -> Result<(), WasmGlError> {
+        let locations: HashMap<&str, u32> = self.attribute_locations
+            .iter()
+            .map(|(name, &location)| (name.as_str(), location))
+            .collect();
+        let reloaded = Shader::new_auto(context, vertex_src, fragment_src, Some(&locations))?;
+        // Dropping the old shader deletes its program
+        *self = reloaded;
+        Ok(())
+    }
+
+    pub fn uniform_names(&self) -> impl Iterator<Item = &str> {
+        self.uniform_locations.keys().map(String::as_str)
     }
 
+    pub fn attribute_names(&self) -> impl Iterator<Item = &str> {
+        self.attribute_locations.keys().map(String::as_str)
+    }
+
+    pub fn get_attr(&self, name: &str) -> Option<u32> {
+        self.attribute_locations.get(name).copied()
+    }
+
+    /// The GLSL type of the active attribute `name`, e.g. `FLOAT_VEC3` or
+    /// `INT`, or `None` if it isn't active
+    pub fn attr_type(&self, name: &str) -> Option<u32> {
+        let count = self
+            .context
+            .get_program_parameter(&self.program, WebGl2RenderingContext::ACTIVE_ATTRIBUTES)
+            .as_f64()
+            .unwrap_or(0.) as u32;
+        (0..count)
+            .filter_map(|i| self.context.get_active_attrib(&self.program, i))
+            .find(|info| info.name() == name)
+            .map(|info| info.type_())
+    }
+
+    pub fn get_uniform(&self, name: &str) -> Option<&WebGlUniformLocation> {
+        self.uniform_locations.get(name)
+    }
+
+    /// Like [`Shader::get_attr`], but panics if the attribute wasn't
+    /// registered (or was optimized out of the shader)
     pub fn find_attr(&self, name: &str) -> u32 {
-        return self.attribute_locations[name];
+        self.get_attr(name)
+            .unwrap_or_else(|| panic!("Attribute `{}` was not found in shader", name))
     }
 
+    /// Like [`Shader::get_uniform`], but panics if the uniform wasn't
+    /// registered (or was optimized out of the shader)
     pub fn find_uniform(&self, name: &str) -> &WebGlUniformLocation {
-        return &self.uniform_locations[name];
+        self.get_uniform(name)
+            .unwrap_or_else(|| panic!("Uniform `{}` was not found in shader", name))
+    }
+
+    pub fn program(&self) -> &WebGlProgram {
+        &self.program
     }
 
     pub fn enable(&self, context: &WebGl2RenderingContext) {
         context.use_program(Some(&self.program));
+        record_shader_switch(context);
+    }
+
+    /// Makes the uniform block `name` read from `buffer`, failing if the
+    /// block isn't active or its size doesn't match `T`, which usually means
+    /// the std140 padding of `T` is wrong
+    pub fn bind_uniform_block<T: Std140>(
+        &self,
+        context: &WebGl2RenderingContext,
+        name: &str,
+        buffer: &UniformBuffer<T>,
+    ) -> Result<(), WasmGlError> {
+        let index = context.get_uniform_block_index(&self.program, name);
+        if index == WebGl2RenderingContext::INVALID_INDEX {
+            return Err(WasmGlError::MissingUniform { name: String::from(name) });
+        }
+        let size = context
+            .get_active_uniform_block_parameter(&self.program, index, WebGl2RenderingContext::UNIFORM_BLOCK_DATA_SIZE)?
+            .as_f64()
+            .unwrap_or_default() as usize;
+        if size != std::mem::size_of::<T>() {
+            return Err(WasmGlError::InvalidArgument(format!(
+                "Uniform block {name} is {size} bytes, but {} is {} bytes",
+                std::any::type_name::<T>(), std::mem::size_of::<T>())));
+        }
+        context.uniform_block_binding(&self.program, index, buffer.binding_point());
+        Ok(())
+    }
+
+    // Typed uniform setters. The shader must be enabled, and like
+    // `find_uniform` these panic if the uniform isn't registered.
+
+    pub fn set_mat4(&self, context: &WebGl2RenderingContext, name: &str, value: &Matrix4<f32>) {
+        context.uniform_matrix4fv_with_f32_array(Some(self.find_uniform(name)), false, value.as_slice());
+    }
+
+    pub fn set_mat3(&self, context: &WebGl2RenderingContext, name: &str, value: &Matrix3<f32>) {
+        context.uniform_matrix3fv_with_f32_array(Some(self.find_uniform(name)), false, value.as_slice());
+    }
+
+    pub fn set_vec2(&self, context: &WebGl2RenderingContext, name: &str, value: &Vector2<f32>) {
+        context.uniform2fv_with_f32_array(Some(self.find_uniform(name)), value.as_slice());
+    }
+
+    pub fn set_vec3(&self, context: &WebGl2RenderingContext, name: &str, value: &Vector3<f32>) {
+        context.uniform3fv_with_f32_array(Some(self.find_uniform(name)), value.as_slice());
+    }
+
+    pub fn set_f32(&self, context: &WebGl2RenderingContext, name: &str, value: f32) {
+        context.uniform1f(Some(self.find_uniform(name)), value);
+    }
+
+    pub fn set_i32(&self, context: &WebGl2RenderingContext, name: &str, value: i32) {
+        context.uniform1i(Some(self.find_uniform(name)), value);
+    }
+
+    /// Uploads [`TIME_UNIFORM`] if this shader uses it, doing nothing
+    /// otherwise. The shader must be enabled.
+    pub fn set_time(&self, context: &WebGl2RenderingContext, seconds: f32) {
+        if let Some(location) = self.get_uniform(TIME_UNIFORM) {
+            context.uniform1f(Some(location), seconds);
+        }
+    }
+
+    /// Points the sampler uniform `name` at texture unit `unit`, i.e. the
+    /// unit a texture was bound to with [`bind_texture_unit`]
+    pub fn set_texture(&self, context: &WebGl2RenderingContext, name: &str, unit: u32) {
+        self.set_i32(context, name, unit as i32);
+    }
+
+    // Cached variants of the setters above. These take `&mut self` to
+    // remember the last uploaded value of each uniform, and skip the GL call
+    // when it hasn't changed, returning whether it was issued. Uniform state
+    // belongs to the program, so the cache survives switching shaders, but
+    // mixing in the uncached setters for the same uniform makes it stale
+    // until [`Shader::invalidate_uniforms`] is called.
+
+    pub fn set_mat4_cached(&mut self, context: &WebGl2RenderingContext, name: &str, value: &Matrix4<f32>) -> bool {
+        let changed = self.uniform_values.replace(name, value.iter().map(|x| x.to_bits()));
+        if changed {
+            self.set_mat4(context, name, value);
+        }
+        changed
+    }
+
+    pub fn set_vec3_cached(&mut self, context: &WebGl2RenderingContext, name: &str, value: &Vector3<f32>) -> bool {
+        let changed = self.uniform_values.replace(name, value.iter().map(|x| x.to_bits()));
+        if changed {
+            self.set_vec3(context, name, value);
+        }
+        changed
+    }
+
+    pub fn set_f32_cached(&mut self, context: &WebGl2RenderingContext, name: &str, value: f32) -> bool {
+        let changed = self.uniform_values.replace(name, std::iter::once(value.to_bits()));
+        if changed {
+            self.set_f32(context, name, value);
+        }
+        changed
+    }
+
+    pub fn set_i32_cached(&mut self, context: &WebGl2RenderingContext, name: &str, value: i32) -> bool {
+        let changed = self.uniform_values.replace(name, std::iter::once(value as u32));
+        if changed {
+            self.set_i32(context, name, value);
+        }
+        changed
+    }
+
+    /// Forgets every cached uniform value, so the next cached set uploads
+    pub fn invalidate_uniforms(&mut self) {
+        self.uniform_values.0.clear();
+    }
+
+    /// Like [`Shader::enable`], but skips the GL call if `cache` says this
+    /// program is already in use
+    pub fn enable_cached(&self, context: &WebGl2RenderingContext, cache: &BindCache) {
+        cache.use_program(context, Some(&self.program));
+    }
+
+    /// Camera-facing particle shader which fades particles out as they
+    /// approach the scene geometry behind them, using the scene's depth
+    /// texture bound to `sceneDepth`. `softness` is the view-space distance
+    /// over which the fade happens. Particles should be drawn after opaque
+    /// geometry with blending enabled and depth writes disabled.
+    pub fn soft_particles(
+        context: &WebGl2RenderingContext,
+        bound_attribute_locations: Option<&HashMap<&str, u32>>,
+    ) -> Result<Shader, WasmGlError> {
+        Shader::new(
+            context,
+            include_str!("./shaders/soft_particle.vsh"),
+            include_str!("./shaders/soft_particle.fsh"),
+            &["projection", "view", "sceneDepth", "viewportSize", "near", "far", "softness"],
+            &["corner", "center", "size", "color"],
+            bound_attribute_locations)
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct VBO<T> {
-    pub buffer: Vec<T>,
+    buffer: Vec<T>,
+    context: WebGl2RenderingContext,
     handle: WebGlBuffer,
     buffer_type: u32,
     access_type: u32,
+    /// Elements of `buffer` changed since the last upload, `None` if there
+    /// are none. May extend past the end of `buffer`.
+    dirty: Cell<Option<Range<usize>>>,
+    /// Length in elements of the GL buffer's storage
+    uploaded_len: Cell<usize>,
+}
+
+/// One attribute of a [`VertexLayout`], as passed to `vertexAttribPointer`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexAttribute {
+    /// The shader attribute, named after the struct field
+    pub name: &'static str,
+    /// Byte offset of the field in the vertex
+    pub offset: usize,
+    /// Number of components, 1 to 4
+    pub size: i32,
+    /// Component type, e.g. `FLOAT`
+    pub gl_type: u32,
+    pub normalized: bool,
+    /// Whether the shader input is an `int`/`uint` type, read without
+    /// conversion to float through `vertexAttribIPointer`
+    pub integer: bool,
+}
+
+/// Vertex types whose fields map to shader attributes of the same name.
+/// Implement with `impl_vertex_layout!`, so the layout can't drift from the
+/// struct definition.
+pub trait VertexLayout {
+    const ATTRIBUTES: &'static [VertexAttribute];
+}
+
+/// The smallest and largest coordinates of the `pos` attribute over
+/// `vertices`, `None` if there are none or `T` has no 3 component float
+/// `pos`
+pub fn position_bounds<T: VertexLayout>(vertices: &[T]) -> Option<(Vector3<f32>, Vector3<f32>)> {
+    let pos = T::ATTRIBUTES
+        .iter()
+        .find(|a| a.name == "pos" && a.size == 3 && a.gl_type == WebGl2RenderingContext::FLOAT)?;
+    vertices
+        .iter()
+        .map(|vertex| {
+            // SAFETY: `impl_vertex_layout!` takes the offset of a field of
+            // `T`, which holds 3 `f32`s for a `vec3`
+            let position = unsafe { ((vertex as *const T as *const u8).add(pos.offset) as *const [f32; 3]).read_unaligned() };
+            Vector3::from(position)
+        })
+        .fold(None, |bounds, p| match bounds {
+            None => Some((p, p)),
+            Some((min, max)) => Some((min.inf(&p), max.sup(&p))),
+        })
+}
+
+/// Implements [`VertexLayout`] from the GLSL type of each field, e.g.
+/// `impl_vertex_layout!(Vertex { pos: vec3, normal: vec3 })`. Supports
+/// `float`, `int` and `uint` scalars and vectors, with 32-bit components.
+macro_rules! impl_vertex_layout {
+    ($Vertex:ty { $($field:ident: $glsl:ident),* $(,)? }) => {
+        impl $crate::renderer::VertexLayout for $Vertex {
+            const ATTRIBUTES: &'static [$crate::renderer::VertexAttribute] = &[
+                $($crate::renderer::VertexAttribute {
+                    name: stringify!($field),
+                    offset: std::mem::offset_of!($Vertex, $field),
+                    size: impl_vertex_layout!(@size $glsl),
+                    gl_type: impl_vertex_layout!(@type $glsl),
+                    normalized: false,
+                    integer: impl_vertex_layout!(@type $glsl) != web_sys::WebGl2RenderingContext::FLOAT,
+                }),*
+            ];
+        }
+    };
+    (@size float) => { 1 };
+    (@size vec2) => { 2 };
+    (@size vec3) => { 3 };
+    (@size vec4) => { 4 };
+    (@size int) => { 1 };
+    (@size ivec2) => { 2 };
+    (@size ivec3) => { 3 };
+    (@size ivec4) => { 4 };
+    (@size uint) => { 1 };
+    (@size uvec2) => { 2 };
+    (@size uvec3) => { 3 };
+    (@size uvec4) => { 4 };
+    (@type float) => { web_sys::WebGl2RenderingContext::FLOAT };
+    (@type vec2) => { web_sys::WebGl2RenderingContext::FLOAT };
+    (@type vec3) => { web_sys::WebGl2RenderingContext::FLOAT };
+    (@type vec4) => { web_sys::WebGl2RenderingContext::FLOAT };
+    (@type int) => { web_sys::WebGl2RenderingContext::INT };
+    (@type ivec2) => { web_sys::WebGl2RenderingContext::INT };
+    (@type ivec3) => { web_sys::WebGl2RenderingContext::INT };
+    (@type ivec4) => { web_sys::WebGl2RenderingContext::INT };
+    (@type uint) => { web_sys::WebGl2RenderingContext::UNSIGNED_INT };
+    (@type uvec2) => { web_sys::WebGl2RenderingContext::UNSIGNED_INT };
+    (@type uvec3) => { web_sys::WebGl2RenderingContext::UNSIGNED_INT };
+    (@type uvec4) => { web_sys::WebGl2RenderingContext::UNSIGNED_INT };
 }
 
 macro_rules! VBO_bind {
@@ -195,34 +838,213 @@ macro_rules! VBO_bind {
     };
 }
 
+/// [`VBO_bind!`] for integer shader inputs, through [`VBO::bind_integer`]
+// Nothing in the demo has integer attributes yet
+#[allow(unused_macros)]
+macro_rules! VBO_bind_int {
+    ($vbo:expr, $ctx:expr, $shader:expr, $DataClass:ty, $member:ident, $sz:expr, $type:expr) => {
+        $vbo.bind_integer(
+            $ctx,
+            $shader.find_attr(stringify!($member)),
+            $sz,
+            $type,
+            std::mem::offset_of!($DataClass, $member)
+        )
+    };
+    ($vbo:expr, $ctx:expr, $addr:expr, $DataClass:ty, $sz:expr, $type:expr) => {
+        $vbo.bind_integer(
+            $ctx,
+            $addr,
+            $sz,
+            $type,
+            0
+        )
+    };
+}
+
 impl<T> VBO<T> {
-    pub fn new(ctx: &WebGl2RenderingContext, data: Option<Vec<T>>, buffer_type: u32, access_type: u32) -> VBO<T> {
-        let result = VBO {
+    pub fn new(ctx: &WebGl2RenderingContext, data: Option<Vec<T>>, buffer_type: u32, access_type: u32) -> Result<VBO<T>, WasmGlError> {
+        let handle = ctx.create_buffer().ok_or(WasmGlError::ResourceCreation { what: "buffer" })?;
+        gl_object_created();
+        Ok(VBO {
             buffer: data.unwrap_or_default(),
-            handle: ctx.create_buffer().expect_throw("Failed to create buffer"),
+            context: ctx.clone(),
+            handle,
             buffer_type,
             access_type,
+            dirty: Cell::new(Some(0..usize::MAX)),
+            uploaded_len: Cell::new(0),
+        })
+    }
+
+    pub fn buffer(&self) -> &[T] {
+        &self.buffer
+    }
+
+    /// Mutable access to the CPU-side data, marking all of it for re-upload.
+    /// Prefer the tracked methods below, which only re-upload what changed.
+    pub fn buffer_mut(&mut self) -> &mut Vec<T> {
+        self.mark_dirty();
+        &mut self.buffer
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.buffer.push(value);
+        self.mark_range(self.buffer.len() - 1..self.buffer.len());
+    }
+
+    pub fn extend(&mut self, values: impl IntoIterator<Item = T>) {
+        let start = self.buffer.len();
+        self.buffer.extend(values);
+        self.mark_range(start..self.buffer.len());
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.mark_range(0..0);
+    }
+
+    /// Replaces the elements in `range` with `values`, which may be a
+    /// different number of elements
+    pub fn splice_range(&mut self, range: Range<usize>, values: impl IntoIterator<Item = T>) {
+        let (start, old_len, removed) = (range.start, self.buffer.len(), range.len());
+        self.buffer.splice(range, values);
+        let inserted = self.buffer.len() + removed - old_len;
+        // Elements after the replacement only move if the length changed
+        let end = if inserted == removed { start + inserted } else { self.buffer.len() };
+        self.mark_range(start..end);
+    }
+
+    /// Mutable access to the elements in `range`, marking only those for
+    /// re-upload
+    pub fn as_mut_slice_tracked(&mut self, range: Range<usize>) -> &mut [T] {
+        self.mark_range(range.clone());
+        &mut self.buffer[range]
+    }
+
+    /// Forces the next [`VBO::update`] to upload everything, e.g. after the
+    /// GL buffer was written some other way
+    pub fn mark_dirty(&self) {
+        self.mark_range(0..usize::MAX);
+    }
+
+    fn mark_range(&self, range: Range<usize>) {
+        let merged = match self.dirty.take() {
+            Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+            None => range,
+        };
+        self.dirty.set(Some(merged));
+    }
+
+    /// Uploads what changed since the last upload, returning whether
+    /// anything did. Only the changed elements are re-uploaded unless the
+    /// length changed. [`VBO::update_unchecked`] is the opt-in view path,
+    /// and the ignored `bench_vbo_update` web test compares the two.
+    pub fn update(&self, ctx: &WebGl2RenderingContext) -> bool {
+        let Some(range) = self.dirty.take() else {
+            return false;
         };
-        result
+        ctx.bind_buffer(self.buffer_type, Some(&self.handle));
+        if self.uploaded_len.get() == self.len() {
+            let (offset, len) = element_byte_range::<T>(range.start, range.end.min(self.len()).saturating_sub(range.start));
+            ctx.buffer_sub_data_with_i32_and_u8_array(self.buffer_type, offset as i32, &self.bytes()[offset..offset + len]);
+            record_upload(ctx, len);
+        } else {
+            ctx.buffer_data_with_u8_array(self.buffer_type, self.bytes(), self.access_type);
+            record_upload(ctx, self.bytes().len());
+            self.uploaded_len.set(self.len());
+        }
+        check_gl(ctx, "VBO::update");
+        buffer_uploaded();
+        true
+    }
+
+    /// [`VBO::update`] through a `Uint8Array::view` straight into wasm
+    /// memory, always uploading the whole buffer.
+    ///
+    /// wasm-bindgen already hands `&[u8]` arguments to JS as a subarray of
+    /// wasm memory rather than a copy, so [`VBO::update`] should cost about
+    /// the same; keep using it unless `bench_vbo_update` shows otherwise on
+    /// your target. The benchmark needs a browser (`wasm-pack test --chrome
+    /// --headless -- --include-ignored`), so no figures are recorded here yet.
+    ///
+    /// # Safety
+    ///
+    /// `Uint8Array::view` is a raw view into our module's `WebAssembly.Memory`
+    /// buffer. If we allocate more pages for ourself (aka do a memory
+    /// allocation in Rust) the buffer changes, invalidating the view, so
+    /// nothing may allocate between creating the view and the GL call. That
+    /// includes allocating in a `Debug` impl or log line added in between.
+    pub unsafe fn update_unchecked(&self, ctx: &WebGl2RenderingContext) -> bool {
+        if self.dirty.take().is_none() {
+            return false;
+        }
+        ctx.bind_buffer(self.buffer_type, Some(&self.handle));
+        ctx.buffer_data_with_array_buffer_view(self.buffer_type, &Uint8Array::view(self.bytes()), self.access_type);
+        check_gl(ctx, "VBO::update_unchecked");
+        record_upload(ctx, self.bytes().len());
+        self.uploaded_len.set(self.len());
+        buffer_uploaded();
+        true
     }
 
-    pub fn update(&self, ctx: &WebGl2RenderingContext) {
+    fn bytes(&self) -> &[u8] {
+        // Reinterpreting as bytes is only sound for plain data types without
+        // padding, which is what a VBO should hold anyway
+        unsafe { self.buffer.as_slice().align_to::<u8>().1 }
+    }
+
+    /// Allocates GL storage for `capacity` elements without uploading any
+    /// data, for filling in later with [`VBO::update_range`]
+    pub fn reserve(&self, ctx: &WebGl2RenderingContext, capacity: usize) {
+        let (_, len) = element_byte_range::<T>(0, capacity);
+        ctx.bind_buffer(self.buffer_type, Some(&self.handle));
+        ctx.buffer_data_with_i32(self.buffer_type, len as i32, self.access_type);
+        check_gl(ctx, "VBO::reserve");
+        self.uploaded_len.set(capacity);
+    }
+
+    /// Re-specifies the GL storage at the current length with undefined
+    /// contents, so streaming uploads get fresh memory instead of stalling on
+    /// draws still reading the old data. Follow with [`VBO::update_range`].
+    pub fn orphan(&self, ctx: &WebGl2RenderingContext) {
+        self.reserve(ctx, self.len());
+    }
+
+    /// Re-uploads only elements `start..start + count` with `bufferSubData`,
+    /// clamped to [`VBO::len`]. The GL buffer must already be allocated at
+    /// least that large, e.g. by an earlier [`VBO::update`].
+    pub fn update_range(&self, ctx: &WebGl2RenderingContext, start: usize, count: usize) {
+        let range = clamp_range(start, count, self.len());
+        if range.is_empty() {
+            return;
+        }
+        let (offset, len) = element_byte_range::<T>(range.start, range.len());
         ctx.bind_buffer(self.buffer_type, Some(&self.handle));
-        // Note that `Float32Array::view` is somewhat dangerous (hence the
-        // `unsafe`!). This is creating a raw view into our module's
-        // `WebAssembly.Memory` buffer, but if we allocate more pages for ourself
-        // (aka do a memory allocation in Rust) it'll cause the buffer to change,
-        // causing the `Float32Array` to be invalid.
-        //
-        // As a result, after `Float32Array::view` we have to be very careful not to
-        // do any memory allocations before it's dropped.
+        ctx.buffer_sub_data_with_i32_and_u8_array(self.buffer_type, offset as i32, &self.bytes()[offset..offset + len]);
+        check_gl(ctx, "VBO::update_range");
+        record_upload(ctx, len);
+        buffer_uploaded();
+    }
+
+    /// Reads the first [`VBO::len`] elements of the GL buffer back, e.g. to
+    /// check transform feedback output. This stalls until the GPU has
+    /// finished writing the buffer, so keep it to debugging and tests.
+    pub fn read_back(&self, ctx: &WebGl2RenderingContext) -> Vec<T> where T: Copy {
+        let (_, len) = element_byte_range::<T>(0, self.len());
+        let mut data: Vec<T> = Vec::with_capacity(self.len());
+        ctx.bind_buffer(self.buffer_type, Some(&self.handle));
+        // The view is over `data`'s allocation, which is aligned for `T`, so
+        // this mirrors `bytes` in reverse. Growing wasm memory invalidates the
+        // view, so no allocation may happen while it is alive. Reinterpreting
+        // the bytes is only sound for plain data types without padding or
+        // invalid bit patterns, which is what a VBO should hold anyway.
         unsafe {
-            ctx.buffer_data_with_array_buffer_view(
-                self.buffer_type,
-                &Uint8Array::view(self.buffer.as_slice().align_to::<u8>().1),
-                self.access_type,
-            );
+            let view = Uint8Array::view_mut_raw(data.as_mut_ptr() as *mut u8, len);
+            ctx.get_buffer_sub_data_with_i32_and_array_buffer_view(self.buffer_type, 0, &view);
+            data.set_len(self.len());
         }
+        data
     }
 
     pub fn bind(&self, ctx: &WebGl2RenderingContext, 
@@ -242,56 +1064,3477 @@ impl<T> VBO<T> {
         ctx.enable_vertex_attrib_array(addr);
     }
 
-    pub fn len(&self) -> usize {
-        self.buffer.len()
+    /// Like [`VBO::bind`], but for `int`/`uint` shader inputs: `type_` must
+    /// be an integer type such as `INT` or `UNSIGNED_BYTE`, and the values
+    /// reach the shader unconverted. Binding a float input this way (or an
+    /// integer input with [`VBO::bind`]) reads garbage without a GL error.
+    pub fn bind_integer(&self, ctx: &WebGl2RenderingContext, addr: u32, size: i32, type_: u32, offset: usize) {
+        ctx.bind_buffer(self.buffer_type, Some(&self.handle));
+        ctx.vertex_attrib_i_pointer_with_i32(addr, size, type_, std::mem::size_of::<T>() as i32, offset as i32);
+        ctx.enable_vertex_attrib_array(addr);
     }
-}
 
-pub struct VAO<T> {
-    pub handle: WebGlVertexArrayObject,
-    pub vbos: Box<T>,
-}
+    /// Sets and enables the attribute pointers for every attribute of `T`
+    /// that `shader` has, so the VAO to record them in should be bound.
+    /// Attributes the shader doesn't use (or that were optimized out) are
+    /// skipped with a console warning, as are (in debug builds) integer
+    /// attributes bound to float inputs and vice versa.
+    pub fn bind_layout(&self, ctx: &WebGl2RenderingContext, shader: &Shader) where T: VertexLayout {
+        for attribute in T::ATTRIBUTES {
+            let Some(location) = shader.get_attr(attribute.name) else {
+                web_sys::console::warn_1(
+                    &format!("Attribute `{}` is not used by the shader, skipping it", attribute.name).into());
+                continue;
+            };
+            if cfg!(debug_assertions) {
+                let shader_integer = shader.attr_type(attribute.name).map(is_integer_type);
+                if shader_integer.is_some_and(|integer| integer != attribute.integer) {
+                    web_sys::console::warn_1(&format!(
+                        "Attribute `{}` is bound as {} but declared as {} in the shader",
+                        attribute.name,
+                        if attribute.integer { "an integer" } else { "a float" },
+                        if attribute.integer { "a float" } else { "an integer" },
+                    ).into());
+                }
+            }
+            if attribute.integer {
+                self.bind_integer(ctx, location, attribute.size, attribute.gl_type, attribute.offset);
+            } else {
+                self.bind(ctx, location, attribute.size, attribute.gl_type, attribute.normalized, attribute.offset);
+            }
+        }
+    }
 
-macro_rules! VAO_new {
-    ($ctx:expr, $(($vbo:expr, $buffer_type:expr, $access_type:expr)),*) => {{
-        let ctx: &WebGl2RenderingContext = $ctx;
-        let handle = ctx.create_vertex_array()
-            .expect_throw("Could not create vertex array object");
-        ctx.bind_vertex_array(Some(&handle));
-        crate::renderer::VAO {
-            handle,
-            vbos: Box::new((
-                $(
-                    crate::renderer::VBO::new(ctx, Some($vbo), $buffer_type, $access_type)
-                ),*
-            ))
+    /// Binds a column-major `mat4` attribute, which occupies the four
+    /// consecutive locations starting at `base_location` (one per column).
+    pub fn bind_mat4(&self, ctx: &WebGl2RenderingContext,
+            base_location: u32, offset: usize, divisor: u32) {
+        ctx.bind_buffer(
+            self.buffer_type,
+            Some(&self.handle),
+        );
+        for i in 0..4 {
+            let addr = base_location + i;
+            ctx.vertex_attrib_pointer_with_i32(
+                addr,
+                4,
+                WebGl2RenderingContext::FLOAT,
+                false,
+                std::mem::size_of::<T>() as i32,
+                (offset + i as usize * 16) as i32,
+            );
+            ctx.enable_vertex_attrib_array(addr);
+            ctx.vertex_attrib_divisor(addr, divisor);
         }
-    }};
-}
+    }
 
-impl<T> VAO<T> {
-    pub fn activate(&self, ctx: &WebGl2RenderingContext) {
-        ctx.bind_vertex_array(Some(&self.handle));
+    pub fn len(&self) -> usize {
+        self.buffer.len()
     }
-}
 
+    /// Length in elements of the GL buffer's storage, as of the last upload
+    /// or [`VBO::reserve`]
+    pub fn uploaded_len(&self) -> usize {
+        self.uploaded_len.get()
+    }
 
-pub fn render_loop(mut callback: impl FnMut(bool) + 'static) -> Result<(), JsValue> {
-    callback(true);
-    let ref1 = Rc::new(RefCell::new(callback));
-    let ref2 = ref1.clone();
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
 
-    let init_cb = Rc::new(RefCell::new(None::<Closure<dyn FnMut()>>));
-    let loop_cb = init_cb.clone();
-    *init_cb.borrow_mut() = Some(Closure::new(move || {
-        ref1.borrow_mut()(false);
-        request_animation_frame(&loop_cb.borrow_mut().as_ref().unwrap());
-    }));
-    request_animation_frame(&init_cb.borrow_mut().as_ref().unwrap());
-    let cb = Closure::<dyn FnMut()>::new(move || {
-        ref2.borrow_mut()(true);
-    });
-    window().unwrap().add_event_listener_with_callback("resize", cb.as_ref().unchecked_ref())?;
-    cb.forget();
-    Ok(())
+    pub fn handle(&self) -> &WebGlBuffer {
+        &self.handle
+    }
+
+    /// Usage hint passed to `bufferData` on every [`VBO::update`]
+    pub fn access_type(&self) -> u32 {
+        self.access_type
+    }
+}
+
+/// Whether a GLSL attribute type from `getActiveAttrib` is an `int` or
+/// `uint` scalar or vector
+fn is_integer_type(gl_type: u32) -> bool {
+    matches!(
+        gl_type,
+        WebGl2RenderingContext::INT
+            | WebGl2RenderingContext::INT_VEC2
+            | WebGl2RenderingContext::INT_VEC3
+            | WebGl2RenderingContext::INT_VEC4
+            | WebGl2RenderingContext::UNSIGNED_INT
+            | WebGl2RenderingContext::UNSIGNED_INT_VEC2
+            | WebGl2RenderingContext::UNSIGNED_INT_VEC3
+            | WebGl2RenderingContext::UNSIGNED_INT_VEC4
+    )
+}
+
+/// `start..start + count`, cut short at `len`
+fn clamp_range(start: usize, count: usize, len: usize) -> Range<usize> {
+    let start = start.min(len);
+    start..start.saturating_add(count).min(len)
+}
+
+/// Byte offset and length of elements `start..start + count` of a `T` buffer
+fn element_byte_range<T>(start: usize, count: usize) -> (usize, usize) {
+    let size = std::mem::size_of::<T>();
+    (start * size, count * size)
+}
+
+impl<T> Drop for VBO<T> {
+    fn drop(&mut self) {
+        self.context.delete_buffer(Some(&self.handle));
+        gl_object_deleted();
+    }
+}
+
+/// A vertex array object and the buffers it reads from. `T` is either a
+/// tuple of VBOs from `VAO_new!`, or [`NamedBuffers`] from a [`VaoBuilder`].
+/// Dropping it deletes the vertex array, then each buffer.
+#[allow(clippy::upper_case_acronyms)]
+pub struct VAO<T = NamedBuffers> {
+    pub handle: WebGlVertexArrayObject,
+    pub vbos: Box<T>,
+    pub(crate) context: WebGl2RenderingContext,
+}
+
+impl<T> VAO<T> {
+    /// Points attribute `addr` at the first `size` components of `type_` in
+    /// each element of `vbo`, advancing once every `divisor` instances
+    /// instead of per vertex.
+    /// The vertex array is left bound.
+    pub fn add_instanced_attribute<U>(
+        &self,
+        ctx: &WebGl2RenderingContext,
+        vbo: &VBO<U>,
+        addr: u32,
+        size: i32,
+        type_: u32,
+        divisor: u32,
+    ) {
+        ctx.bind_vertex_array(Some(&self.handle));
+        vbo.bind(ctx, addr, size, type_, false, 0);
+        ctx.vertex_attrib_divisor(addr, divisor);
+    }
+}
+
+impl<T> Drop for VAO<T> {
+    fn drop(&mut self) {
+        self.context.delete_vertex_array(Some(&self.handle));
+        gl_object_deleted();
+    }
+}
+
+/// Evaluates to a `Result<VAO<_>, WasmGlError>`, with the new VAO left bound
+macro_rules! VAO_new {
+    ($ctx:expr, $(($vbo:expr, $buffer_type:expr, $access_type:expr)),*) => {{
+        let ctx: &WebGl2RenderingContext = $ctx;
+        // A labelled block rather than a closure with `?`, so that `$vbo`
+        // can move out of a field without capturing the whole struct
+        'vao: {
+            let vbos = (
+                $(
+                    match crate::renderer::VBO::new(ctx, Some($vbo), $buffer_type, $access_type) {
+                        Ok(vbo) => vbo,
+                        Err(err) => break 'vao Err(err),
+                    }
+                ),*
+            );
+            crate::renderer::VaoBuilder::new(ctx).map(|builder| builder.build_with(vbos))
+        }
+    }};
+}
+
+/// The parts of a [`VBO`] that don't depend on its element type, so buffers
+/// of different types can be stored and updated together
+pub trait AnyVbo: Any {
+    fn len(&self) -> usize;
+    fn uploaded_len(&self) -> usize;
+    fn is_empty(&self) -> bool;
+    fn handle(&self) -> &WebGlBuffer;
+    fn update(&self, ctx: &WebGl2RenderingContext) -> bool;
+    /// For downcasting to the `VBO<T>`
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: 'static> AnyVbo for VBO<T> {
+    fn len(&self) -> usize {
+        VBO::len(self)
+    }
+
+    fn uploaded_len(&self) -> usize {
+        VBO::uploaded_len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        VBO::is_empty(self)
+    }
+
+    fn handle(&self) -> &WebGlBuffer {
+        VBO::handle(self)
+    }
+
+    fn update(&self, ctx: &WebGl2RenderingContext) -> bool {
+        VBO::update(self, ctx)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Buffers looked up by name and type, so they can be added after the VAO
+/// is created
+#[derive(Default)]
+pub struct NamedBuffers {
+    buffers: Vec<Box<dyn AnyVbo>>,
+    // Index into `buffers` of each name
+    names: HashMap<String, usize>,
+    indices: Option<IndexBuffer>,
+    /// Index into `buffers` of the per-instance buffer, if any
+    instances: Option<usize>,
+    /// Bounds of the positions added with [`VaoBuilder::add_vertices`]
+    bounds: Option<(Vector3<f32>, Vector3<f32>)>,
+}
+
+impl NamedBuffers {
+    /// Uploads `data` to a new `ARRAY_BUFFER`. The VAO must be bound.
+    fn add<T: 'static>(
+        &mut self,
+        ctx: &WebGl2RenderingContext,
+        name: &str,
+        data: Vec<T>,
+        usage: u32,
+    ) -> Result<&mut VBO<T>, WasmGlError> {
+        if self.names.contains_key(name) {
+            return Err(WasmGlError::InvalidArgument(format!("The VAO already has a buffer named {name}")));
+        }
+        let vbo = VBO::new(ctx, Some(data), WebGl2RenderingContext::ARRAY_BUFFER, usage)?;
+        vbo.update(ctx);
+        self.names.insert(String::from(name), self.buffers.len());
+        self.buffers.push(Box::new(vbo));
+        Ok(self.last_mut())
+    }
+
+    fn last_mut<T: 'static>(&mut self) -> &mut VBO<T> {
+        self.buffers.last_mut().unwrap().as_any_mut().downcast_mut().unwrap()
+    }
+
+    fn get(&self, name: &str) -> Option<&dyn AnyVbo> {
+        Some(self.buffers[*self.names.get(name)?].as_ref())
+    }
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut dyn AnyVbo> {
+        Some(self.buffers[*self.names.get(name)?].as_mut())
+    }
+
+    /// Like `add`, drawing one instance per element of the buffer
+    fn add_instanced<T: 'static>(
+        &mut self,
+        ctx: &WebGl2RenderingContext,
+        name: &str,
+        data: Vec<T>,
+        usage: u32,
+    ) -> Result<&mut VBO<T>, WasmGlError> {
+        if self.instances.is_some() {
+            return Err(WasmGlError::InvalidArgument(format!("The VAO already has an instance buffer, so can't add {name}")));
+        }
+        self.add(ctx, name, data, usage)?;
+        self.instances = Some(self.buffers.len() - 1);
+        Ok(self.last_mut())
+    }
+
+    fn instance_count(&self) -> Option<usize> {
+        // Elements not uploaded yet would be read past the end of the GL
+        // buffer, and ones past the length are stale
+        self.instances.map(|i| self.buffers[i].len().min(self.buffers[i].uploaded_len()))
+    }
+}
+
+/// Creates a [`VAO`] and binds it, so that buffers added to it (and
+/// attribute pointers set before binding another VAO) are recorded in it, e.g.
+/// `VaoBuilder::new(ctx)?.add_vertex_buffer("mesh", vertices, STATIC_DRAW)?.set_index_buffer(&indices, STATIC_DRAW)?.build()`
+pub struct VaoBuilder<'a> {
+    ctx: &'a WebGl2RenderingContext,
+    // Taken by `build`, otherwise deleted when the builder is dropped
+    handle: Option<WebGlVertexArrayObject>,
+    buffers: NamedBuffers,
+}
+
+impl Drop for VaoBuilder<'_> {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.ctx.delete_vertex_array(Some(&handle));
+            gl_object_deleted();
+        }
+    }
+}
+
+impl<'a> VaoBuilder<'a> {
+    pub fn new(ctx: &'a WebGl2RenderingContext) -> Result<VaoBuilder<'a>, WasmGlError> {
+        let handle = ctx.create_vertex_array().ok_or(WasmGlError::ResourceCreation { what: "vertex array" })?;
+        ctx.bind_vertex_array(Some(&handle));
+        gl_object_created();
+        Ok(VaoBuilder { ctx, handle: Some(handle), buffers: NamedBuffers::default() })
+    }
+
+    /// Uploads `data` to a new `ARRAY_BUFFER` named `name`, e.g. with
+    /// `usage` `STATIC_DRAW`. Names must be unique.
+    pub fn add_vertex_buffer<T: 'static>(mut self, name: &str, data: Vec<T>, usage: u32) -> Result<Self, WasmGlError> {
+        self.buffers.add(self.ctx, name, data, usage)?;
+        Ok(self)
+    }
+
+    /// Like [`VaoBuilder::add_vertex_buffer`], also recording the bounds of
+    /// the vertices' `pos` attribute (see [`position_bounds`]), which a
+    /// [`Mesh`](crate::scene::Mesh) built from the VAO is centered on
+    pub fn add_vertices<T: VertexLayout + 'static>(
+        mut self,
+        name: &str,
+        data: Vec<T>,
+        usage: u32,
+    ) -> Result<Self, WasmGlError> {
+        if let Some((min, max)) = position_bounds(&data) {
+            self.buffers.bounds = Some(match self.buffers.bounds {
+                Some((old_min, old_max)) => (old_min.inf(&min), old_max.sup(&max)),
+                None => (min, max),
+            });
+        }
+        self.add_vertex_buffer(name, data, usage)
+    }
+
+    /// Like [`VaoBuilder::add_vertex_buffer`], for per-instance data. Draws
+    /// through a [`Scene`](crate::scene::Scene) then draw one instance per
+    /// element, however the buffer's length changes. Attribute pointers into
+    /// it still need a divisor of 1. A VAO has at most one instance buffer.
+    pub fn add_instance_buffer<T: 'static>(mut self, name: &str, data: Vec<T>, usage: u32) -> Result<Self, WasmGlError> {
+        self.buffers.add_instanced(self.ctx, name, data, usage)?;
+        Ok(self)
+    }
+
+    /// Uploads `indices` to the VAO's element buffer, replacing any earlier
+    /// one. See [`IndexBuffer::new`] for the index width.
+    pub fn set_index_buffer(mut self, indices: &[u32], usage: u32) -> Result<Self, WasmGlError> {
+        self.buffers.indices = Some(IndexBuffer::new(self.ctx, indices, usage)?);
+        Ok(self)
+    }
+
+    /// Returns the VAO, still bound
+    pub fn build(mut self) -> VAO {
+        let buffers = std::mem::take(&mut self.buffers);
+        self.build_with(buffers)
+    }
+
+    /// Returns a VAO holding `vbos` instead of named buffers, as `VAO_new!`
+    /// does. Any buffers added to the builder are deleted.
+    pub fn build_with<T>(mut self, vbos: T) -> VAO<T> {
+        let handle = self.handle.take().unwrap();
+        VAO { handle, vbos: Box::new(vbos), context: self.ctx.clone() }
+    }
+}
+
+impl VAO {
+    /// The buffer added as `name`, if there is one holding `T`s
+    pub fn buffer<T: 'static>(&self, name: &str) -> Option<&VBO<T>> {
+        self.vbos.get(name)?.as_any().downcast_ref()
+    }
+
+    pub fn buffer_mut<T: 'static>(&mut self, name: &str) -> Option<&mut VBO<T>> {
+        self.vbos.get_mut(name)?.as_any_mut().downcast_mut()
+    }
+
+    /// The buffer added as `name`, whatever its element type
+    pub fn vbo(&self, name: &str) -> Option<&dyn AnyVbo> {
+        self.vbos.get(name)
+    }
+
+    /// Uploads every buffer that changed since its last upload, as
+    /// [`VBO::update`], returning whether any did
+    pub fn update_buffers(&self, ctx: &WebGl2RenderingContext) -> bool {
+        let mut updated = false;
+        for vbo in &self.vbos.buffers {
+            updated |= vbo.update(ctx);
+        }
+        updated
+    }
+
+    /// Adds a buffer after creation, as [`VaoBuilder::add_vertex_buffer`],
+    /// leaving this VAO bound to set its attribute pointers
+    pub fn add_vertex_buffer<T: 'static>(
+        &mut self,
+        ctx: &WebGl2RenderingContext,
+        name: &str,
+        data: Vec<T>,
+        usage: u32,
+    ) -> Result<&mut VBO<T>, WasmGlError> {
+        self.activate(ctx);
+        self.vbos.add(ctx, name, data, usage)
+    }
+
+    pub fn index_buffer(&self) -> Option<&IndexBuffer> {
+        self.vbos.indices.as_ref()
+    }
+
+    /// The smallest and largest positions of the vertices added with
+    /// [`VaoBuilder::add_vertices`], as they were added
+    pub fn bounds(&self) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        self.vbos.bounds
+    }
+
+    /// The length of the buffer added with
+    /// [`VaoBuilder::add_instance_buffer`], if there is one, leaving out
+    /// elements added since it was last uploaded
+    pub fn instance_count(&self) -> Option<usize> {
+        self.vbos.instance_count()
+    }
+
+    /// Describes drawing the whole index buffer, with the count and index
+    /// type it was uploaded with, once per instance (or just once without an
+    /// instance buffer). `None` if there is no index buffer.
+    pub fn draw_spec(&self, mode: u32) -> Option<DrawSpec> {
+        let indices = self.index_buffer()?;
+        let instances = self.instance_count().map_or(1, |count| count as i32);
+        Some(DrawSpec { instances, ..indices.draw_spec(mode) })
+    }
+
+    /// Uploads any changed buffers, as [`VAO::update_buffers`], then binds
+    /// this VAO and issues an instanced indexed draw described by `spec`
+    pub fn draw(&self, ctx: &WebGl2RenderingContext, spec: &DrawSpec) {
+        self.update_buffers(ctx);
+        self.activate(ctx);
+        spec.draw_bound(ctx);
+    }
+
+    /// Like [`VAO::draw`], binding through `cache` so consecutive draws of
+    /// the same VAO only bind it once
+    pub fn draw_cached(&self, ctx: &WebGl2RenderingContext, cache: &BindCache, spec: &DrawSpec) {
+        self.update_buffers(ctx);
+        self.activate_cached(ctx, cache);
+        spec.draw_bound(ctx);
+    }
+
+    /// Uploads any changed buffers, then draws the whole index buffer as
+    /// [`VAO::draw_spec`] describes, which is an error if it has none. The
+    /// upload comes first so the spec counts instances added since the last
+    /// one.
+    pub fn draw_elements(&self, ctx: &WebGl2RenderingContext, mode: u32) -> Result<(), WasmGlError> {
+        self.update_buffers(ctx);
+        let spec = self.draw_spec(mode).ok_or_else(no_index_buffer)?;
+        self.draw(ctx, &spec);
+        Ok(())
+    }
+
+    /// Like [`VAO::draw_elements`], binding through `cache`
+    pub fn draw_elements_cached(
+        &self,
+        ctx: &WebGl2RenderingContext,
+        cache: &BindCache,
+        mode: u32,
+    ) -> Result<(), WasmGlError> {
+        self.update_buffers(ctx);
+        let spec = self.draw_spec(mode).ok_or_else(no_index_buffer)?;
+        self.draw_cached(ctx, cache, &spec);
+        Ok(())
+    }
+}
+
+pub(crate) fn no_index_buffer() -> WasmGlError {
+    WasmGlError::InvalidArgument(String::from("The VAO has no index buffer"))
+}
+
+impl<T> VAO<T> {
+    /// Binds this VAO without uploading anything. Draw a VAO built with
+    /// named buffers through [`VAO::draw`] or [`VAO::draw_elements`], which
+    /// upload changed buffers first.
+    pub fn activate(&self, ctx: &WebGl2RenderingContext) {
+        ctx.bind_vertex_array(Some(&self.handle));
+    }
+
+    pub fn activate_cached(&self, ctx: &WebGl2RenderingContext, cache: &BindCache) {
+        cache.bind_vertex_array(ctx, Some(&self.handle));
+    }
+}
+
+/// Fixed-function state for a group of draws, set with
+/// [`RenderState::apply`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RenderState {
+    /// Straight alpha blending over what was drawn before
+    pub blend: bool,
+    pub depth_test: bool,
+    pub depth_write: bool,
+}
+
+impl RenderState {
+    /// Depth tested and written, without blending
+    pub fn opaque() -> RenderState {
+        RenderState { blend: false, depth_test: true, depth_write: true }
+    }
+
+    /// Blended and depth tested, but without writing depth, so transparent
+    /// surfaces don't hide those drawn after them. Draw transparent geometry
+    /// after opaque geometry, back to front.
+    pub fn transparent() -> RenderState {
+        RenderState { blend: true, depth_test: true, depth_write: false }
+    }
+
+    pub fn apply(&self, ctx: &WebGl2RenderingContext) {
+        let set = |capability, enabled| {
+            if enabled {
+                ctx.enable(capability);
+            } else {
+                ctx.disable(capability);
+            }
+        };
+        set(WebGl2RenderingContext::BLEND, self.blend);
+        if self.blend {
+            // Alpha accumulates as coverage, so the result stays usable as
+            // the source of a later blend
+            ctx.blend_func_separate(
+                WebGl2RenderingContext::SRC_ALPHA,
+                WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+                WebGl2RenderingContext::ONE,
+                WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+            );
+        }
+        set(WebGl2RenderingContext::DEPTH_TEST, self.depth_test);
+        ctx.depth_mask(self.depth_write);
+    }
+}
+
+/// Which faces to skip rasterizing, judged by counter-clockwise winding
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CullMode {
+    /// Draws both sides, for open or two-sided geometry
+    None,
+    /// Skips faces pointing away from the camera, for closed geometry
+    #[default]
+    Back,
+    Front,
+}
+
+impl CullMode {
+    pub fn apply(self, ctx: &WebGl2RenderingContext) {
+        let face = match self {
+            CullMode::None => return ctx.disable(WebGl2RenderingContext::CULL_FACE),
+            CullMode::Back => WebGl2RenderingContext::BACK,
+            CullMode::Front => WebGl2RenderingContext::FRONT,
+        };
+        ctx.enable(WebGl2RenderingContext::CULL_FACE);
+        ctx.cull_face(face);
+    }
+}
+
+/// Remembers the currently bound program, VAO and framebuffer so that
+/// redundant binds can be skipped. Every bind of a given kind must go through
+/// the cache (or be followed by [`BindCache::invalidate`]), otherwise it will
+/// wrongly skip binds.
+///
+/// Each bind method returns whether the GL call was actually issued.
+#[derive(Default)]
+pub struct BindCache {
+    // The outer `None` means the bound object is unknown
+    program: RefCell<Option<Option<WebGlProgram>>>,
+    vertex_array: RefCell<Option<Option<WebGlVertexArrayObject>>>,
+    framebuffer: RefCell<Option<Option<WebGlFramebuffer>>>,
+}
+
+/// Stores `new` in `slot`, returning false if it was already there
+fn replace_cached<T: Clone + PartialEq>(slot: &RefCell<Option<Option<T>>>, new: Option<&T>) -> bool {
+    let mut slot = slot.borrow_mut();
+    if matches!(&*slot, Some(current) if current.as_ref() == new) {
+        false
+    } else {
+        *slot = Some(new.cloned());
+        true
+    }
+}
+
+impl BindCache {
+    pub fn new() -> BindCache {
+        BindCache::default()
+    }
+
+    pub fn use_program(&self, ctx: &WebGl2RenderingContext, program: Option<&WebGlProgram>) -> bool {
+        let changed = replace_cached(&self.program, program);
+        if changed {
+            ctx.use_program(program);
+            record_shader_switch(ctx);
+        }
+        changed
+    }
+
+    pub fn bind_vertex_array(&self, ctx: &WebGl2RenderingContext, vao: Option<&WebGlVertexArrayObject>) -> bool {
+        let changed = replace_cached(&self.vertex_array, vao);
+        if changed {
+            ctx.bind_vertex_array(vao);
+        }
+        changed
+    }
+
+    pub fn bind_framebuffer(&self, ctx: &WebGl2RenderingContext, framebuffer: Option<&WebGlFramebuffer>) -> bool {
+        let changed = replace_cached(&self.framebuffer, framebuffer);
+        if changed {
+            ctx.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, framebuffer);
+        }
+        changed
+    }
+
+    /// Forgets all cached state, for after binds made outside the cache
+    pub fn invalidate(&self) {
+        self.program.replace(None);
+        self.vertex_array.replace(None);
+        self.framebuffer.replace(None);
+    }
+}
+
+/// Integer types which can be used as element indices
+pub trait IndexType {
+    const GL_TYPE: u32;
+}
+
+impl IndexType for u8 {
+    const GL_TYPE: u32 = WebGl2RenderingContext::UNSIGNED_BYTE;
+}
+
+impl IndexType for u16 {
+    const GL_TYPE: u32 = WebGl2RenderingContext::UNSIGNED_SHORT;
+}
+
+impl IndexType for u32 {
+    const GL_TYPE: u32 = WebGl2RenderingContext::UNSIGNED_INT;
+}
+
+/// Smallest index component type able to address `vertex_count` vertices
+pub fn index_type_for(vertex_count: usize) -> u32 {
+    if vertex_count <= u8::MAX as usize + 1 {
+        u8::GL_TYPE
+    } else if vertex_count <= u16::MAX as usize + 1 {
+        u16::GL_TYPE
+    } else {
+        u32::GL_TYPE
+    }
+}
+
+/// An element buffer whose index width is picked at runtime, so meshes only
+/// pay for wide indices once they have enough vertices to need them
+pub enum IndexBuffer {
+    U8(VBO<u8>),
+    U16(VBO<u16>),
+    U32(VBO<u32>),
+}
+
+impl IndexBuffer {
+    /// Narrows `indices` to the smallest type fitting the largest of them and
+    /// uploads them to a new `ELEMENT_ARRAY_BUFFER`. That binding is part of
+    /// VAO state, so the target VAO should be bound first.
+    pub fn new(ctx: &WebGl2RenderingContext, indices: &[u32], access_type: u32) -> Result<IndexBuffer, WasmGlError> {
+        let vertex_count = indices.iter().max().map_or(0, |&max| max as usize + 1);
+        let buffer = match index_type_for(vertex_count) {
+            WebGl2RenderingContext::UNSIGNED_BYTE => IndexBuffer::U8(VBO::new(
+                ctx, Some(indices.iter().map(|&i| i as u8).collect()),
+                WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER, access_type)?),
+            WebGl2RenderingContext::UNSIGNED_SHORT => IndexBuffer::U16(VBO::new(
+                ctx, Some(indices.iter().map(|&i| i as u16).collect()),
+                WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER, access_type)?),
+            _ => IndexBuffer::U32(VBO::new(
+                ctx, Some(indices.to_vec()), WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER, access_type)?),
+        };
+        buffer.update(ctx);
+        Ok(buffer)
+    }
+
+    /// Index type for `drawElements`, e.g. `UNSIGNED_SHORT`
+    pub fn component_type(&self) -> u32 {
+        match self {
+            IndexBuffer::U8(_) => u8::GL_TYPE,
+            IndexBuffer::U16(_) => u16::GL_TYPE,
+            IndexBuffer::U32(_) => u32::GL_TYPE,
+        }
+    }
+
+    pub fn update(&self, ctx: &WebGl2RenderingContext) -> bool {
+        match self {
+            IndexBuffer::U8(vbo) => vbo.update(ctx),
+            IndexBuffer::U16(vbo) => vbo.update(ctx),
+            IndexBuffer::U32(vbo) => vbo.update(ctx),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            IndexBuffer::U8(vbo) => vbo.len(),
+            IndexBuffer::U16(vbo) => vbo.len(),
+            IndexBuffer::U32(vbo) => vbo.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Describes drawing the whole buffer once
+    pub fn draw_spec(&self, mode: u32) -> DrawSpec {
+        DrawSpec {
+            mode,
+            index_type: self.component_type(),
+            count: self.len() as i32,
+            offset: 0,
+            instances: 1,
+        }
+    }
+}
+
+/// Everything needed for a `drawElementsInstanced` call
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DrawSpec {
+    /// Primitive mode, e.g. `TRIANGLES`
+    pub mode: u32,
+    /// Index component type, e.g. `UNSIGNED_SHORT`
+    pub index_type: u32,
+    /// Number of indices to draw
+    pub count: i32,
+    /// Byte offset into the element buffer
+    pub offset: i32,
+    pub instances: i32,
+}
+
+impl DrawSpec {
+    /// Issues the draw with whichever VAO is bound
+    pub(crate) fn draw_bound(&self, ctx: &WebGl2RenderingContext) {
+        self.validate().or_throw();
+        ctx.draw_elements_instanced_with_i32(
+            self.mode,
+            self.count,
+            self.index_type,
+            self.offset,
+            self.instances,
+        );
+        check_gl(ctx, "drawElementsInstanced");
+        record_draw(ctx, self.mode, self.count, self.instances);
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self.mode {
+            WebGl2RenderingContext::POINTS
+            | WebGl2RenderingContext::LINES
+            | WebGl2RenderingContext::LINE_LOOP
+            | WebGl2RenderingContext::LINE_STRIP
+            | WebGl2RenderingContext::TRIANGLES
+            | WebGl2RenderingContext::TRIANGLE_STRIP
+            | WebGl2RenderingContext::TRIANGLE_FAN => {}
+            mode => return Err(format!("Unknown primitive mode {mode:#x}")),
+        }
+        let index_size = match self.index_type {
+            WebGl2RenderingContext::UNSIGNED_BYTE => 1,
+            WebGl2RenderingContext::UNSIGNED_SHORT => 2,
+            WebGl2RenderingContext::UNSIGNED_INT => 4,
+            ty => return Err(format!("Unknown index type {ty:#x}")),
+        };
+        if self.count < 0 || self.instances < 0 || self.offset < 0 {
+            return Err(format!("Negative count, offset or instances in {self:?}"));
+        }
+        if self.offset % index_size != 0 {
+            return Err(format!(
+                "Offset {} is not a multiple of the index size {index_size}", self.offset));
+        }
+        Ok(())
+    }
+}
+
+impl<I: IndexType> VBO<I> {
+    /// Describes drawing the whole of this element buffer once
+    pub fn draw_spec(&self, mode: u32) -> DrawSpec {
+        DrawSpec {
+            mode,
+            index_type: I::GL_TYPE,
+            count: self.len() as i32,
+            offset: 0,
+            instances: 1,
+        }
+    }
+}
+
+/// Types that can be copied byte for byte into a `std140` uniform block.
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C)]` with no implicit padding, laid out to
+/// match the block: scalars are 4 byte aligned, `vec2` 8, and `vec3`, `vec4`
+/// and each column of a matrix 16, so a `vec3` needs a following `f32` (or
+/// scalar member) to fill it out, and a `mat3` needs padded columns.
+pub unsafe trait Std140: Copy + 'static {}
+
+/// A `UNIFORM_BUFFER` holding one `T`, bound to a uniform buffer binding
+/// point so that every shader block bound to the same point reads it
+pub struct UniformBuffer<T: Std140> {
+    context: WebGl2RenderingContext,
+    handle: WebGlBuffer,
+    binding_point: u32,
+    _value: std::marker::PhantomData<T>,
+}
+
+impl<T: Std140> UniformBuffer<T> {
+    /// Allocates the buffer and binds it to `binding_point`
+    pub fn new(ctx: &WebGl2RenderingContext, binding_point: u32) -> Result<UniformBuffer<T>, WasmGlError> {
+        let handle = ctx.create_buffer().ok_or(WasmGlError::ResourceCreation { what: "uniform buffer" })?;
+        ctx.bind_buffer(WebGl2RenderingContext::UNIFORM_BUFFER, Some(&handle));
+        ctx.buffer_data_with_i32(
+            WebGl2RenderingContext::UNIFORM_BUFFER,
+            std::mem::size_of::<T>() as i32,
+            WebGl2RenderingContext::DYNAMIC_DRAW);
+        ctx.bind_buffer_base(WebGl2RenderingContext::UNIFORM_BUFFER, binding_point, Some(&handle));
+        gl_object_created();
+        Ok(UniformBuffer { context: ctx.clone(), handle, binding_point, _value: std::marker::PhantomData })
+    }
+
+    pub fn binding_point(&self) -> u32 {
+        self.binding_point
+    }
+
+    pub fn update(&self, ctx: &WebGl2RenderingContext, value: &T) {
+        // Safe as `Std140` types have no padding to read
+        let bytes = unsafe {
+            std::slice::from_raw_parts(value as *const T as *const u8, std::mem::size_of::<T>())
+        };
+        ctx.bind_buffer(WebGl2RenderingContext::UNIFORM_BUFFER, Some(&self.handle));
+        ctx.buffer_sub_data_with_i32_and_u8_array(WebGl2RenderingContext::UNIFORM_BUFFER, 0, bytes);
+    }
+}
+
+impl<T: Std140> Drop for UniformBuffer<T> {
+    fn drop(&mut self) {
+        self.context.delete_buffer(Some(&self.handle));
+        gl_object_deleted();
+    }
+}
+
+/// Per-frame camera and light data, matching the `FrameData` block in
+/// `shaders/frame_data.glsl`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameUniforms {
+    pub projection: Matrix4<f32>,
+    pub view: Matrix4<f32>,
+    /// The light's projection * view, for rendering the shadow pass
+    pub light_projection_view: Matrix4<f32>,
+    /// Maps world space into shadow map texture space
+    pub shadow_view: Matrix4<f32>,
+    pub light_pos: Vector3<f32>,
+    // Fills out the vec3 to 16 bytes
+    pub _padding: f32,
+    /// World space eye position, for specular highlights
+    pub camera_pos: Vector3<f32>,
+    pub _camera_padding: f32,
+}
+
+unsafe impl Std140 for FrameUniforms {}
+
+/// Pixel formats supported by [`Texture2D`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureFormat {
+    Rgba8,
+    Rgb8,
+    R8,
+    /// 32 bit float depth, for depth attachments and shadow maps
+    Depth32F,
+}
+
+impl TextureFormat {
+    /// `(internal format, format, type)` for `texImage2D`
+    fn gl_formats(self) -> (u32, u32, u32) {
+        match self {
+            TextureFormat::Rgba8 => (
+                WebGl2RenderingContext::RGBA8,
+                WebGl2RenderingContext::RGBA,
+                WebGl2RenderingContext::UNSIGNED_BYTE),
+            TextureFormat::Rgb8 => (
+                WebGl2RenderingContext::RGB8,
+                WebGl2RenderingContext::RGB,
+                WebGl2RenderingContext::UNSIGNED_BYTE),
+            TextureFormat::R8 => (
+                WebGl2RenderingContext::R8,
+                WebGl2RenderingContext::RED,
+                WebGl2RenderingContext::UNSIGNED_BYTE),
+            TextureFormat::Depth32F => (
+                WebGl2RenderingContext::DEPTH_COMPONENT32F,
+                WebGl2RenderingContext::DEPTH_COMPONENT,
+                WebGl2RenderingContext::FLOAT),
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            TextureFormat::Rgba8 | TextureFormat::Depth32F => 4,
+            TextureFormat::Rgb8 => 3,
+            TextureFormat::R8 => 1,
+        }
+    }
+}
+
+/// Checks a texture size against the context's `MAX_TEXTURE_SIZE`, and the
+/// pixel data (if any) against the size
+fn validate_texture(width: u32, height: u32, max_size: u32, format: TextureFormat, pixels: Option<&[u8]>) -> Result<(), WasmGlError> {
+    if width == 0 || height == 0 || width > max_size || height > max_size {
+        return Err(WasmGlError::InvalidArgument(format!("Texture size {width}x{height} must be within 1..={max_size}")));
+    }
+    let expected = width as usize * height as usize * format.bytes_per_pixel();
+    match pixels {
+        Some(pixels) if pixels.len() != expected => Err(WasmGlError::InvalidArgument(format!(
+            "Expected {expected} bytes of {format:?} pixels for {width}x{height}, got {}",
+            pixels.len()))),
+        _ => Ok(()),
+    }
+}
+
+fn integer_parameter(ctx: &WebGl2RenderingContext, parameter: u32, name: &str) -> Result<u32, WasmGlError> {
+    let value = ctx.get_parameter(parameter)?;
+    value.as_f64()
+        .map(|value| value as u32)
+        .ok_or_else(|| WasmGlError::Js(JsValue::from_str(&format!("Failed to query {name}"))))
+}
+
+fn max_texture_size(ctx: &WebGl2RenderingContext) -> Result<u32, WasmGlError> {
+    integer_parameter(ctx, WebGl2RenderingContext::MAX_TEXTURE_SIZE, "MAX_TEXTURE_SIZE")
+}
+
+/// Fetches and decodes an image, with an anonymous CORS request
+async fn load_image(url: &str) -> Result<HtmlImageElement, JsValue> {
+    let image = HtmlImageElement::new()?;
+    image.set_cross_origin(Some("anonymous"));
+    image.set_src(url);
+    JsFuture::from(image.decode())
+        .await
+        .map_err(|_| JsValue::from_str(&format!("Failed to load image {url}")))?;
+    Ok(image)
+}
+
+/// Filtering and wrapping of a [`Texture2D`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SamplerParams {
+    pub min_filter: u32,
+    pub mag_filter: u32,
+    pub wrap_s: u32,
+    pub wrap_t: u32,
+}
+
+impl Default for SamplerParams {
+    /// `NEAREST` filtering and `CLAMP_TO_EDGE` wrapping, which is complete
+    /// without mipmaps and valid for every format
+    fn default() -> SamplerParams {
+        SamplerParams {
+            min_filter: WebGl2RenderingContext::NEAREST,
+            mag_filter: WebGl2RenderingContext::NEAREST,
+            wrap_s: WebGl2RenderingContext::CLAMP_TO_EDGE,
+            wrap_t: WebGl2RenderingContext::CLAMP_TO_EDGE,
+        }
+    }
+}
+
+/// Describes an uninitialized [`Texture2D`], see [`Texture2D::new`]
+pub struct TextureBuilder<'a> {
+    ctx: &'a WebGl2RenderingContext,
+    format: TextureFormat,
+    width: u32,
+    height: u32,
+    params: SamplerParams,
+}
+
+impl TextureBuilder<'_> {
+    /// Defaults to [`TextureFormat::Rgba8`]
+    pub fn format(mut self, format: TextureFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// Sets the minification and magnification filters, `NEAREST` by default
+    pub fn filter(mut self, min: u32, mag: u32) -> Self {
+        self.params.min_filter = min;
+        self.params.mag_filter = mag;
+        self
+    }
+
+    /// Sets the wrap modes, `CLAMP_TO_EDGE` by default
+    pub fn wrap(mut self, s: u32, t: u32) -> Self {
+        self.params.wrap_s = s;
+        self.params.wrap_t = t;
+        self
+    }
+
+    /// Allocates the storage, failing if the size is zero or too large
+    pub fn build(self) -> Result<Texture2D, WasmGlError> {
+        let result = Texture2D::create(self.ctx, self.width, self.height, self.format, None)?;
+        result.set_params(self.ctx, &self.params);
+        Ok(result)
+    }
+}
+
+/// A `TEXTURE_2D` with immutable format but resizable storage. Sampling
+/// parameters are set with the builder-style methods after construction.
+/// The texture is deleted when it is dropped.
+pub struct Texture2D {
+    // Only a handle to the JS object, which never references the texture back
+    context: WebGl2RenderingContext,
+    texture: WebGlTexture,
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+}
+
+impl Drop for Texture2D {
+    fn drop(&mut self) {
+        self.context.delete_texture(Some(&self.texture));
+        gl_object_deleted();
+    }
+}
+
+impl Texture2D {
+    /// Starts describing an uninitialized texture, e.g.
+    /// `Texture2D::new(ctx).format(TextureFormat::R8).size(w, h).filter(min, mag).build()`.
+    /// The size must be set; sampling defaults to [`SamplerParams::default`].
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(ctx: &WebGl2RenderingContext) -> TextureBuilder<'_> {
+        TextureBuilder { ctx, format: TextureFormat::Rgba8, width: 0, height: 0, params: SamplerParams::default() }
+    }
+
+    /// Creates a texture from tightly packed rows of `pixels`, bottom row
+    /// first, leaving it bound to `TEXTURE_2D`
+    pub fn from_pixels(
+        ctx: &WebGl2RenderingContext,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        pixels: &[u8],
+    ) -> Result<Texture2D, WasmGlError> {
+        Texture2D::create(ctx, width, height, format, Some(pixels))
+    }
+
+    /// Loads an image over the network (CORS requests are anonymous) into an
+    /// RGBA8 texture, flipped so the image's bottom row is at `v = 0`. With
+    /// `mipmaps`, the mip chain is generated (WebGL2 allows this at any size)
+    /// and used for minification. Network and decode failures are errors.
+    pub async fn from_url(ctx: &WebGl2RenderingContext, url: &str, mipmaps: bool) -> Result<Texture2D, WasmGlError> {
+        let image = load_image(url).await?;
+        Texture2D::from_image(ctx, &image, mipmaps)
+    }
+
+    /// Uploads an already decoded image, like [`Texture2D::from_url`]. An
+    /// image that hasn't finished loading (await `decode()` or `onload`
+    /// first) or failed to is an error.
+    pub fn from_image(ctx: &WebGl2RenderingContext, image: &HtmlImageElement, mipmaps: bool) -> Result<Texture2D, WasmGlError> {
+        let (width, height) = (image.natural_width(), image.natural_height());
+        if !image.complete() || width == 0 {
+            return Err(WasmGlError::InvalidArgument(format!("Image {} has not loaded", image.src())));
+        }
+        let mut result = Texture2D::create(ctx, 1, 1, TextureFormat::Rgba8, None)?;
+        validate_texture(width, height, max_texture_size(ctx)?, result.format, None)?;
+        let (internal_format, format, type_) = result.format.gl_formats();
+        ctx.pixel_storei(WebGl2RenderingContext::UNPACK_FLIP_Y_WEBGL, 1);
+        let uploaded = ctx.tex_image_2d_with_u32_and_u32_and_html_image_element(
+            WebGl2RenderingContext::TEXTURE_2D,
+            0,
+            internal_format as i32,
+            format,
+            type_,
+            image,
+        );
+        ctx.pixel_storei(WebGl2RenderingContext::UNPACK_FLIP_Y_WEBGL, 0);
+        uploaded.map_err(|_| WasmGlError::Js(JsValue::from_str(&format!("Failed to upload {width}x{height} image"))))?;
+        result.width = width;
+        result.height = height;
+        if mipmaps {
+            result = result
+                .mipmaps(ctx)
+                .filter(ctx, WebGl2RenderingContext::LINEAR_MIPMAP_LINEAR, WebGl2RenderingContext::LINEAR);
+        }
+        Ok(result)
+    }
+
+    /// An uninitialized `size` x `size` depth texture with nearest filtering
+    /// and edge clamping, as used for shadow maps
+    pub fn depth(ctx: &WebGl2RenderingContext, size: u32) -> Result<Texture2D, WasmGlError> {
+        Texture2D::new(ctx).format(TextureFormat::Depth32F).size(size, size).build()
+    }
+
+    fn create(
+        ctx: &WebGl2RenderingContext,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        pixels: Option<&[u8]>,
+    ) -> Result<Texture2D, WasmGlError> {
+        validate_texture(width, height, max_texture_size(ctx)?, format, pixels)?;
+        let texture = ctx.create_texture().ok_or(WasmGlError::ResourceCreation { what: "texture" })?;
+        gl_object_created();
+        let mut result = Texture2D { context: ctx.clone(), texture, width: 0, height: 0, format };
+        result.upload(ctx, width, height, pixels)?;
+        // No mipmaps yet, so the default mipmapped min filter would leave the
+        // texture incomplete
+        Ok(result.filter(ctx, WebGl2RenderingContext::LINEAR, WebGl2RenderingContext::LINEAR))
+    }
+
+    /// (Re)specifies the storage, leaving the texture bound to `TEXTURE_2D`
+    fn upload(&mut self, ctx: &WebGl2RenderingContext, width: u32, height: u32, pixels: Option<&[u8]>) -> Result<(), WasmGlError> {
+        let (internal_format, format, type_) = self.format.gl_formats();
+        ctx.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&self.texture));
+        // Rows of RGB8 and R8 pixels aren't necessarily 4 byte aligned
+        ctx.pixel_storei(WebGl2RenderingContext::UNPACK_ALIGNMENT, 1);
+        ctx.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+            WebGl2RenderingContext::TEXTURE_2D,
+            0,
+            internal_format as i32,
+            width as i32,
+            height as i32,
+            0,
+            format,
+            type_,
+            pixels,
+        ).map_err(|_| WasmGlError::Js(JsValue::from_str(&format!("Failed to allocate {width}x{height} texture"))))?;
+        check_gl(ctx, "Texture2D texImage2D");
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+
+    /// Reallocates the storage at a new size, discarding the contents
+    pub fn resize(&mut self, ctx: &WebGl2RenderingContext, width: u32, height: u32) -> Result<(), WasmGlError> {
+        self.upload(ctx, width, height, None)
+    }
+
+    /// Sets the minification and magnification filters, e.g. `NEAREST`,
+    /// `LINEAR` or (for min) `LINEAR_MIPMAP_LINEAR`
+    pub fn filter(self, ctx: &WebGl2RenderingContext, min: u32, mag: u32) -> Self {
+        self.set_parameter(ctx, WebGl2RenderingContext::TEXTURE_MIN_FILTER, min);
+        self.set_parameter(ctx, WebGl2RenderingContext::TEXTURE_MAG_FILTER, mag);
+        self
+    }
+
+    /// Sets the wrap modes, e.g. `CLAMP_TO_EDGE`, `REPEAT` or `MIRRORED_REPEAT`
+    pub fn wrap(self, ctx: &WebGl2RenderingContext, s: u32, t: u32) -> Self {
+        self.set_parameter(ctx, WebGl2RenderingContext::TEXTURE_WRAP_S, s);
+        self.set_parameter(ctx, WebGl2RenderingContext::TEXTURE_WRAP_T, t);
+        self
+    }
+
+    /// Generates the mip chain from the current contents. Pair with a
+    /// mipmapped min filter for it to be used.
+    pub fn mipmaps(self, ctx: &WebGl2RenderingContext) -> Self {
+        ctx.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&self.texture));
+        ctx.generate_mipmap(WebGl2RenderingContext::TEXTURE_2D);
+        self
+    }
+
+    fn set_params(&self, ctx: &WebGl2RenderingContext, params: &SamplerParams) {
+        self.set_parameter(ctx, WebGl2RenderingContext::TEXTURE_MIN_FILTER, params.min_filter);
+        self.set_parameter(ctx, WebGl2RenderingContext::TEXTURE_MAG_FILTER, params.mag_filter);
+        self.set_parameter(ctx, WebGl2RenderingContext::TEXTURE_WRAP_S, params.wrap_s);
+        self.set_parameter(ctx, WebGl2RenderingContext::TEXTURE_WRAP_T, params.wrap_t);
+    }
+
+    fn set_parameter(&self, ctx: &WebGl2RenderingContext, parameter: u32, value: u32) {
+        ctx.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&self.texture));
+        ctx.tex_parameteri(WebGl2RenderingContext::TEXTURE_2D, parameter, value as i32);
+    }
+
+    pub fn bind(&self, ctx: &WebGl2RenderingContext, unit: u32) {
+        bind_texture_unit(ctx, unit, WebGl2RenderingContext::TEXTURE_2D, &self.texture);
+    }
+
+    pub fn handle(&self) -> &WebGlTexture {
+        &self.texture
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn format(&self) -> TextureFormat {
+        self.format
+    }
+}
+
+/// A `TEXTURE_CUBE_MAP` with square faces, in the order of
+/// [`CubeMap::FACES`]. The texture is deleted when it is dropped.
+pub struct CubeMap {
+    context: WebGl2RenderingContext,
+    texture: WebGlTexture,
+    size: u32,
+}
+
+impl Drop for CubeMap {
+    fn drop(&mut self) {
+        self.context.delete_texture(Some(&self.texture));
+        gl_object_deleted();
+    }
+}
+
+impl CubeMap {
+    /// GL face targets, in the order faces are passed to the constructors
+    pub const FACES: [u32; 6] = [
+        WebGl2RenderingContext::TEXTURE_CUBE_MAP_POSITIVE_X,
+        WebGl2RenderingContext::TEXTURE_CUBE_MAP_NEGATIVE_X,
+        WebGl2RenderingContext::TEXTURE_CUBE_MAP_POSITIVE_Y,
+        WebGl2RenderingContext::TEXTURE_CUBE_MAP_NEGATIVE_Y,
+        WebGl2RenderingContext::TEXTURE_CUBE_MAP_POSITIVE_Z,
+        WebGl2RenderingContext::TEXTURE_CUBE_MAP_NEGATIVE_Z,
+    ];
+
+    /// Per-face tints used by [`CubeMap::debug`]: +X red, -X cyan, +Y green,
+    /// -Y magenta, +Z blue, -Z yellow
+    pub const DEBUG_COLORS: [[u8; 4]; 6] = [
+        [255, 0, 0, 255],
+        [0, 255, 255, 255],
+        [0, 255, 0, 255],
+        [255, 0, 255, 255],
+        [0, 0, 255, 255],
+        [255, 255, 0, 255],
+    ];
+
+    /// Creates a cube map from six tightly packed `size` x `size` faces
+    pub fn from_pixels(
+        ctx: &WebGl2RenderingContext,
+        size: u32,
+        format: TextureFormat,
+        faces: [&[u8]; 6],
+    ) -> Result<CubeMap, WasmGlError> {
+        let max_size = max_texture_size(ctx)?;
+        for face in faces {
+            validate_texture(size, size, max_size, format, Some(face))?;
+        }
+        let result = CubeMap::create(ctx, size)?;
+        let (internal_format, gl_format, type_) = format.gl_formats();
+        ctx.pixel_storei(WebGl2RenderingContext::UNPACK_ALIGNMENT, 1);
+        for (target, face) in CubeMap::FACES.iter().zip(faces) {
+            ctx.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                *target,
+                0,
+                internal_format as i32,
+                size as i32,
+                size as i32,
+                0,
+                gl_format,
+                type_,
+                Some(face),
+            ).map_err(|_| WasmGlError::Js(JsValue::from_str(&format!("Failed to upload {size}x{size} cube map face"))))?;
+        }
+        Ok(result)
+    }
+
+    /// Loads six square images of equal size over the network
+    pub async fn from_urls(ctx: &WebGl2RenderingContext, urls: [&str; 6]) -> Result<CubeMap, WasmGlError> {
+        let mut images = Vec::with_capacity(6);
+        for url in urls {
+            images.push(load_image(url).await?);
+        }
+        let size = images[0].natural_width();
+        if let Some(image) = images.iter().find(|image| image.natural_width() != size || image.natural_height() != size) {
+            return Err(WasmGlError::InvalidArgument(format!(
+                "Cube map faces must all be {size}x{size}, got {}x{}",
+                image.natural_width(), image.natural_height())));
+        }
+        validate_texture(size, size, max_texture_size(ctx)?, TextureFormat::Rgba8, None)?;
+        let result = CubeMap::create(ctx, size)?;
+        let (internal_format, format, type_) = TextureFormat::Rgba8.gl_formats();
+        for (target, image) in CubeMap::FACES.iter().zip(&images) {
+            ctx.tex_image_2d_with_u32_and_u32_and_html_image_element(
+                *target, 0, internal_format as i32, format, type_, image)?;
+        }
+        Ok(result)
+    }
+
+    /// A 1x1 cube map with each face tinted by [`CubeMap::DEBUG_COLORS`], for
+    /// checking face orientation without any images
+    pub fn debug(ctx: &WebGl2RenderingContext) -> Result<CubeMap, WasmGlError> {
+        let [a, b, c, d, e, f] = &CubeMap::DEBUG_COLORS;
+        CubeMap::from_pixels(ctx, 1, TextureFormat::Rgba8, [a, b, c, d, e, f])
+    }
+
+    /// Allocates six `size` x `size` faces with undefined contents, e.g. for
+    /// rendering into
+    pub fn empty(ctx: &WebGl2RenderingContext, size: u32, format: TextureFormat) -> Result<CubeMap, WasmGlError> {
+        validate_texture(size, size, max_texture_size(ctx)?, format, None)?;
+        let result = CubeMap::create(ctx, size)?;
+        let (internal_format, gl_format, type_) = format.gl_formats();
+        for target in CubeMap::FACES {
+            ctx.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                target, 0, internal_format as i32, size as i32, size as i32, 0, gl_format, type_, None,
+            ).map_err(|_| WasmGlError::Js(JsValue::from_str(&format!("Failed to allocate {size}x{size} cube map face"))))?;
+        }
+        Ok(result)
+    }
+
+    /// Creates the texture with linear, edge clamped sampling, leaving it
+    /// bound to `TEXTURE_CUBE_MAP`
+    fn create(ctx: &WebGl2RenderingContext, size: u32) -> Result<CubeMap, WasmGlError> {
+        let texture = ctx.create_texture().ok_or(WasmGlError::ResourceCreation { what: "cube map" })?;
+        ctx.bind_texture(WebGl2RenderingContext::TEXTURE_CUBE_MAP, Some(&texture));
+        for (parameter, value) in [
+            (WebGl2RenderingContext::TEXTURE_MIN_FILTER, WebGl2RenderingContext::LINEAR),
+            (WebGl2RenderingContext::TEXTURE_MAG_FILTER, WebGl2RenderingContext::LINEAR),
+            (WebGl2RenderingContext::TEXTURE_WRAP_S, WebGl2RenderingContext::CLAMP_TO_EDGE),
+            (WebGl2RenderingContext::TEXTURE_WRAP_T, WebGl2RenderingContext::CLAMP_TO_EDGE),
+            (WebGl2RenderingContext::TEXTURE_WRAP_R, WebGl2RenderingContext::CLAMP_TO_EDGE),
+        ] {
+            ctx.tex_parameteri(WebGl2RenderingContext::TEXTURE_CUBE_MAP, parameter, value as i32);
+        }
+        gl_object_created();
+        Ok(CubeMap { context: ctx.clone(), texture, size })
+    }
+
+    pub fn bind(&self, ctx: &WebGl2RenderingContext, unit: u32) {
+        bind_texture_unit(ctx, unit, WebGl2RenderingContext::TEXTURE_CUBE_MAP, &self.texture);
+    }
+
+    pub fn handle(&self) -> &WebGlTexture {
+        &self.texture
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+}
+
+/// Maps a `checkFramebufferStatus` result to an error unless complete
+fn framebuffer_status(status: u32) -> Result<(), WasmGlError> {
+    match status {
+        WebGl2RenderingContext::FRAMEBUFFER_COMPLETE => Ok(()),
+        status => Err(WasmGlError::FramebufferIncomplete { status }),
+    }
+}
+
+/// `COLOR_ATTACHMENT0..count`, for `drawBuffers`, if the context supports
+/// that many at once
+fn draw_buffer_list(count: usize, max_draw_buffers: u32) -> Result<Vec<u32>, WasmGlError> {
+    if count > max_draw_buffers as usize {
+        return Err(WasmGlError::InvalidArgument(format!(
+            "{count} color attachments exceed MAX_DRAW_BUFFERS ({max_draw_buffers})")));
+    }
+    Ok((0..count as u32).map(|i| WebGl2RenderingContext::COLOR_ATTACHMENT0 + i).collect())
+}
+
+enum DepthAttachment {
+    Texture(Texture2D),
+    Renderbuffer(WebGlRenderbuffer),
+}
+
+/// A texture for a [`FramebufferBuilder`] to create, or one passed in
+enum TextureSource {
+    New(TextureFormat),
+    Existing(Texture2D),
+}
+
+impl TextureSource {
+    fn build(self, ctx: &WebGl2RenderingContext, width: u32, height: u32, filter: u32) -> Result<Texture2D, WasmGlError> {
+        match self {
+            TextureSource::New(format) => Texture2D::new(ctx).format(format).size(width, height).filter(filter, filter).build(),
+            TextureSource::Existing(texture) if (texture.width(), texture.height()) == (width, height) => Ok(texture),
+            TextureSource::Existing(texture) => Err(WasmGlError::InvalidArgument(format!(
+                "Attached texture is {}x{} but the framebuffer is {width}x{height}",
+                texture.width(),
+                texture.height()))),
+        }
+    }
+}
+
+enum DepthSpec {
+    Texture(TextureSource),
+    Renderbuffer,
+}
+
+/// Describes the attachments of a [`Framebuffer`], see [`Framebuffer::new`]
+pub struct FramebufferBuilder<'a> {
+    ctx: &'a WebGl2RenderingContext,
+    width: u32,
+    height: u32,
+    color: Vec<TextureSource>,
+    depth: Option<DepthSpec>,
+}
+
+impl FramebufferBuilder<'_> {
+    /// Adds a color texture at the next `COLOR_ATTACHMENTi`, which fragment
+    /// shader output `i` (`layout(location = i) out`) is written to
+    pub fn with_color(mut self, format: TextureFormat) -> Self {
+        self.color.push(TextureSource::New(format));
+        self
+    }
+
+    /// Attaches an existing texture at the next `COLOR_ATTACHMENTi`. The
+    /// framebuffer takes ownership, and the texture must be its size.
+    pub fn attach_color(mut self, texture: Texture2D) -> Self {
+        self.color.push(TextureSource::Existing(texture));
+        self
+    }
+
+    /// Adds a sampleable depth texture, e.g. for shadow maps
+    pub fn with_depth_texture(mut self) -> Self {
+        self.depth = Some(DepthSpec::Texture(TextureSource::New(TextureFormat::Depth32F)));
+        self
+    }
+
+    /// Attaches an existing [`TextureFormat::Depth32F`] texture as the depth
+    /// attachment, as for [`FramebufferBuilder::attach_color`]
+    pub fn attach_depth(mut self, texture: Texture2D) -> Self {
+        self.depth = Some(DepthSpec::Texture(TextureSource::Existing(texture)));
+        self
+    }
+
+    /// Adds a depth renderbuffer, for depth testing only
+    pub fn with_depth_renderbuffer(mut self) -> Self {
+        self.depth = Some(DepthSpec::Renderbuffer);
+        self
+    }
+
+    /// Creates and attaches everything, failing if the result is incomplete
+    pub fn build(self) -> Result<Framebuffer, WasmGlError> {
+        let ctx = self.ctx;
+        let max_size = max_texture_size(ctx)?;
+        validate_texture(self.width, self.height, max_size, TextureFormat::Rgba8, None)?;
+        let (width, height) = (self.width, self.height);
+        // Every draw buffer has its own attachment point, so this also keeps
+        // within MAX_COLOR_ATTACHMENTS
+        let max_draw_buffers = integer_parameter(ctx, WebGl2RenderingContext::MAX_DRAW_BUFFERS, "MAX_DRAW_BUFFERS")?;
+        let draw_buffers = draw_buffer_list(self.color.len(), max_draw_buffers)?;
+        let color = self.color
+            .into_iter()
+            .map(|source| source.build(ctx, width, height, WebGl2RenderingContext::LINEAR))
+            .collect::<Result<Vec<_>, WasmGlError>>()?;
+        let depth = match self.depth {
+            Some(DepthSpec::Texture(source)) => Some(DepthAttachment::Texture(
+                source.build(ctx, width, height, WebGl2RenderingContext::NEAREST)?)),
+            Some(DepthSpec::Renderbuffer) => Some(DepthAttachment::Renderbuffer(
+                ctx.create_renderbuffer().ok_or(WasmGlError::ResourceCreation { what: "renderbuffer" })?)),
+            None => None,
+        };
+        // Created last, so that failing to create an attachment can't leak it
+        let framebuffer = ctx.create_framebuffer().ok_or(WasmGlError::ResourceCreation { what: "framebuffer" })?;
+        gl_object_created();
+        if let Some(DepthAttachment::Renderbuffer(_)) = depth {
+            gl_object_created();
+        }
+        let result = Framebuffer { context: ctx.clone(), framebuffer, width, height, color, depth };
+        result.allocate_renderbuffer(ctx);
+
+        ctx.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&result.framebuffer));
+        for (i, texture) in result.color.iter().enumerate() {
+            ctx.framebuffer_texture_2d(
+                WebGl2RenderingContext::FRAMEBUFFER,
+                WebGl2RenderingContext::COLOR_ATTACHMENT0 + i as u32,
+                WebGl2RenderingContext::TEXTURE_2D,
+                Some(texture.handle()),
+                0);
+        }
+        if draw_buffers.len() > 1 {
+            // Fragment shader output i goes to COLOR_ATTACHMENTi
+            let buffers: js_sys::Array = draw_buffers.iter().map(|&buffer| JsValue::from(buffer)).collect();
+            ctx.draw_buffers(&buffers);
+        }
+        match &result.depth {
+            Some(DepthAttachment::Texture(texture)) => ctx.framebuffer_texture_2d(
+                WebGl2RenderingContext::FRAMEBUFFER,       // target
+                WebGl2RenderingContext::DEPTH_ATTACHMENT,  // attachment point
+                WebGl2RenderingContext::TEXTURE_2D,        // texture target
+                Some(texture.handle()),         // texture
+                0),                   // mip level
+            Some(DepthAttachment::Renderbuffer(renderbuffer)) => ctx.framebuffer_renderbuffer(
+                WebGl2RenderingContext::FRAMEBUFFER,
+                WebGl2RenderingContext::DEPTH_ATTACHMENT,
+                WebGl2RenderingContext::RENDERBUFFER,
+                Some(renderbuffer)),
+            None => {}
+        }
+        check_gl(ctx, "Framebuffer attachment");
+        let status = framebuffer_status(ctx.check_framebuffer_status(WebGl2RenderingContext::FRAMEBUFFER));
+        ctx.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        status.map(|_| result)
+    }
+}
+
+/// An offscreen render target owning its attachments, which are deleted
+/// along with it
+pub struct Framebuffer {
+    context: WebGl2RenderingContext,
+    framebuffer: WebGlFramebuffer,
+    width: u32,
+    height: u32,
+    color: Vec<Texture2D>,
+    depth: Option<DepthAttachment>,
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        self.context.delete_framebuffer(Some(&self.framebuffer));
+        gl_object_deleted();
+        if let Some(DepthAttachment::Renderbuffer(renderbuffer)) = &self.depth {
+            self.context.delete_renderbuffer(Some(renderbuffer));
+            gl_object_deleted();
+        }
+    }
+}
+
+impl Framebuffer {
+    /// Starts describing a `width` x `height` framebuffer, e.g.
+    /// `Framebuffer::new(ctx, w, h).with_color(TextureFormat::Rgba8).with_depth_renderbuffer().build()`
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(ctx: &WebGl2RenderingContext, width: u32, height: u32) -> FramebufferBuilder<'_> {
+        FramebufferBuilder { ctx, width, height, color: Vec::new(), depth: None }
+    }
+
+    fn allocate_renderbuffer(&self, ctx: &WebGl2RenderingContext) {
+        if let Some(DepthAttachment::Renderbuffer(renderbuffer)) = &self.depth {
+            ctx.bind_renderbuffer(WebGl2RenderingContext::RENDERBUFFER, Some(renderbuffer));
+            ctx.renderbuffer_storage(
+                WebGl2RenderingContext::RENDERBUFFER,
+                WebGl2RenderingContext::DEPTH_COMPONENT24,
+                self.width as i32,
+                self.height as i32);
+            ctx.bind_renderbuffer(WebGl2RenderingContext::RENDERBUFFER, None);
+        }
+    }
+
+    /// Reallocates every attachment at a new size, discarding the contents
+    pub fn resize(&mut self, ctx: &WebGl2RenderingContext, width: u32, height: u32) -> Result<(), WasmGlError> {
+        validate_texture(width, height, max_texture_size(ctx)?, TextureFormat::Rgba8, None)?;
+        self.width = width;
+        self.height = height;
+        for texture in &mut self.color {
+            texture.resize(ctx, width, height)?;
+        }
+        if let Some(DepthAttachment::Texture(texture)) = &mut self.depth {
+            texture.resize(ctx, width, height)?;
+        }
+        self.allocate_renderbuffer(ctx);
+        Ok(())
+    }
+
+    /// Renders into this framebuffer, with the viewport covering all of it
+    pub fn bind(&self, ctx: &WebGl2RenderingContext) {
+        ctx.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&self.framebuffer));
+        ctx.viewport(0, 0, self.width as i32, self.height as i32);
+    }
+
+    /// Returns to the default framebuffer with a viewport of the given size
+    pub fn unbind(&self, ctx: &WebGl2RenderingContext, screen_w: i32, screen_h: i32) {
+        ctx.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        ctx.viewport(0, 0, screen_w, screen_h);
+    }
+
+    /// Copies the first color attachment onto the whole default framebuffer,
+    /// scaling with `filter` (`NEAREST` or `LINEAR`)
+    pub fn blit_to_screen(&self, ctx: &WebGl2RenderingContext, screen_w: i32, screen_h: i32, filter: u32) {
+        ctx.bind_framebuffer(WebGl2RenderingContext::READ_FRAMEBUFFER, Some(&self.framebuffer));
+        ctx.bind_framebuffer(WebGl2RenderingContext::DRAW_FRAMEBUFFER, None);
+        ctx.blit_framebuffer(
+            0, 0, self.width as i32, self.height as i32,
+            0, 0, screen_w, screen_h,
+            WebGl2RenderingContext::COLOR_BUFFER_BIT, filter);
+        ctx.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+    }
+
+    pub fn color_texture(&self, i: usize) -> Option<&Texture2D> {
+        self.color.get(i)
+    }
+
+    /// The depth attachment, if it was added as a texture
+    pub fn depth_texture(&self) -> Option<&Texture2D> {
+        match &self.depth {
+            Some(DepthAttachment::Texture(texture)) => Some(texture),
+            _ => None,
+        }
+    }
+
+    pub fn handle(&self) -> &WebGlFramebuffer {
+        &self.framebuffer
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+/// Errors if the bound read framebuffer can't be read back as RGBA8. The
+/// default framebuffer always can; a `Framebuffer` needs a normalized
+/// (`Rgba8`, `Rgb8` or `R8`) first color attachment.
+fn check_readable(ctx: &WebGl2RenderingContext) -> Result<(), WasmGlError> {
+    let binding = ctx.get_parameter(WebGl2RenderingContext::READ_FRAMEBUFFER_BINDING)?;
+    if binding.is_null() {
+        return Ok(());
+    }
+    let attachment = |pname: u32| ctx
+        .get_framebuffer_attachment_parameter(
+            WebGl2RenderingContext::READ_FRAMEBUFFER, WebGl2RenderingContext::COLOR_ATTACHMENT0, pname)
+        .ok()
+        .and_then(|value| value.as_f64())
+        .map(|value| value as u32);
+    match attachment(WebGl2RenderingContext::FRAMEBUFFER_ATTACHMENT_OBJECT_TYPE) {
+        None | Some(WebGl2RenderingContext::NONE) => {
+            return Err(WasmGlError::InvalidArgument(String::from(
+                "Cannot read pixels: the framebuffer has no color attachment")));
+        }
+        _ => {}
+    }
+    match attachment(WebGl2RenderingContext::FRAMEBUFFER_ATTACHMENT_COMPONENT_TYPE) {
+        Some(WebGl2RenderingContext::UNSIGNED_NORMALIZED) => Ok(()),
+        component_type => Err(WasmGlError::InvalidArgument(format!(
+            "Cannot read pixels as RGBA8: the color attachment has component type {component_type:?}, not UNSIGNED_NORMALIZED"
+        ))),
+    }
+}
+
+/// Reverses the order of `row_len` byte rows, turning GL's bottom-up
+/// layout into the top-down one images use
+fn flip_rows(pixels: &mut [u8], row_len: usize) {
+    if row_len == 0 {
+        return;
+    }
+    let rows = pixels.len() / row_len;
+    for row in 0..rows / 2 {
+        let (top, bottom) = pixels.split_at_mut((rows - 1 - row) * row_len);
+        top[row * row_len..(row + 1) * row_len].swap_with_slice(&mut bottom[..row_len]);
+    }
+}
+
+/// Reads a rectangle of the bound framebuffer (the canvas if none is) as
+/// top-down RGBA8 rows. `x` and `y` are measured from the bottom left.
+///
+/// Unless the context was created with `preserveDrawingBuffer`, the canvas
+/// is cleared once the browser composites it, so read it from within the
+/// render loop callback, after drawing. An empty rectangle reads nothing.
+pub fn read_pixels(ctx: &WebGl2RenderingContext, x: i32, y: i32, width: u32, height: u32) -> Result<Vec<u8>, WasmGlError> {
+    if width == 0 || height == 0 {
+        return Ok(vec![]);
+    }
+    check_readable(ctx)?;
+    let mut pixels = vec![0u8; width as usize * height as usize * 4];
+    ctx.read_pixels_with_opt_u8_array(
+        x, y, width as i32, height as i32,
+        WebGl2RenderingContext::RGBA, WebGl2RenderingContext::UNSIGNED_BYTE,
+        Some(&mut pixels))?;
+    flip_rows(&mut pixels, width as usize * 4);
+    Ok(pixels)
+}
+
+/// Encodes the whole bound framebuffer as a PNG data URL, drawing it through
+/// an offscreen 2D canvas. The same timing as [`read_pixels`] applies.
+pub fn screenshot_png(ctx: &WebGl2RenderingContext, width: u32, height: u32) -> Result<String, JsValue> {
+    let pixels = read_pixels(ctx, 0, 0, width, height)?;
+    let canvas: HtmlCanvasElement = window().unwrap().document().unwrap()
+        .create_element("canvas")?
+        .dyn_into()?;
+    canvas.set_width(width);
+    canvas.set_height(height);
+    let canvas_ctx: CanvasRenderingContext2d = canvas
+        .get_context("2d")?
+        .ok_or("2D canvas is not supported")?
+        .dyn_into()?;
+    let image = ImageData::new_with_u8_clamped_array_and_sh(Clamped(&pixels), width, height)?;
+    canvas_ctx.put_image_data(&image, 0., 0.)?;
+    canvas.to_data_url_with_type("image/png")
+}
+
+/// How the main shader samples the shadow map
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShadowFilter {
+    /// A single depth comparison, giving hard texel-sized edges
+    Hard,
+    /// Percentage-closer filtering over a 3x3 texel kernel
+    Pcf3x3,
+    /// Percentage-closer filtering over a 5x5 texel kernel
+    Pcf5x5,
+}
+
+impl ShadowFilter {
+    /// Kernel radius in texels, as uploaded to `shadowKernelRadius`
+    pub fn kernel_radius(self) -> i32 {
+        match self {
+            ShadowFilter::Hard => 0,
+            ShadowFilter::Pcf3x3 => 1,
+            ShadowFilter::Pcf5x5 => 2,
+        }
+    }
+}
+
+/// Slope-scaled depth offset applied with `polygonOffset` while rendering
+/// the shadow map
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PolygonOffset {
+    pub factor: f32,
+    pub units: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowConfig {
+    /// Defaults to `Hard`, as PCF is expensive on weak mobile GPUs
+    pub filter: ShadowFilter,
+    /// Constant subtracted from the fragment's light-space depth before
+    /// comparing it against the shadow map
+    pub bias: f32,
+    /// Offset applied to depths written during the shadow pass, which adapts
+    /// to surface slope. Tuned independently from `bias`, since steep
+    /// surfaces like grass blades need more of it than flat ground.
+    pub polygon_offset: Option<PolygonOffset>,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        ShadowConfig {
+            filter: ShadowFilter::Hard,
+            bias: 0.001,
+            polygon_offset: Some(PolygonOffset { factor: 1., units: 2. }),
+        }
+    }
+}
+
+/// A light infinitely far away, such as the sun, so all its rays are
+/// parallel and its shadows need an orthographic projection
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirectionalLight {
+    /// The way the light travels, which needn't be normalized
+    pub direction: Vector3<f32>,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vector3<f32>) -> DirectionalLight {
+        DirectionalLight { direction }
+    }
+
+    /// Unit vector from a surface towards the light, as used for diffuse
+    /// lighting
+    pub fn reverse_direction(&self) -> Vector3<f32> {
+        -self.direction.normalize()
+    }
+
+    /// A point along the reversed direction, outside the box `min..max`, for
+    /// effects that need a light position
+    pub fn position(&self, min: Vector3<f32>, max: Vector3<f32>) -> Vector3<f32> {
+        let center = (min + max) / 2.;
+        center + self.reverse_direction() * (max - min).norm()
+    }
+
+    /// The view and projection matrices of a shadow map covering the world
+    /// space box `min..max`, with the orthographic box fitted as tightly
+    /// around it as the light direction allows
+    pub fn shadow_matrices(&self, min: Vector3<f32>, max: Vector3<f32>) -> (Matrix4<f32>, Matrix4<f32>) {
+        self.slice_shadow_matrices(&box_corners(min, max), min, max)
+    }
+
+    /// Like [`DirectionalLight::shadow_matrices`], but only as wide and tall
+    /// as needed to cover `points`, e.g. the corners of one cascade's slice of
+    /// the camera frustum. The depth range still covers the whole box, so
+    /// everything in it can cast shadows into the slice.
+    pub fn slice_shadow_matrices(
+        &self,
+        points: &[Vector3<f32>],
+        min: Vector3<f32>,
+        max: Vector3<f32>,
+    ) -> (Matrix4<f32>, Matrix4<f32>) {
+        let forward = self.direction.normalize();
+        // `look_at` can't use an up vector parallel to the view direction
+        let up = if forward.cross(&Vector3::y()).norm() < 1e-3 { Vector3::z() } else { Vector3::y() };
+        let view = look_at(self.position(min, max), (min + max) / 2., up);
+
+        let bounds = |points: &[Vector3<f32>]| {
+            points.iter().fold(
+                (Vector3::repeat(f32::INFINITY), Vector3::repeat(f32::NEG_INFINITY)),
+                |(low, high), point| {
+                    let point = view.transform_point(&(*point).into()).coords;
+                    (low.inf(&point), high.sup(&point))
+                },
+            )
+        };
+        let (view_min, view_max) = bounds(points);
+        let (box_min, box_max) = bounds(&box_corners(min, max));
+        // The view looks down -z, so the nearest corner has the largest z
+        let (near, far) = (-view_max.z.max(box_max.z), -view_min.z.min(box_min.z));
+        let projection = orthographic_matrix(view_min.x, view_max.x, view_min.y, view_max.y, near, far);
+        (view, projection)
+    }
+}
+
+/// The eight corners of the box `min..max`
+fn box_corners(min: Vector3<f32>, max: Vector3<f32>) -> [Vector3<f32>; 8] {
+    std::array::from_fn(|i| Vector3::new(
+        if i & 1 == 0 { min.x } else { max.x },
+        if i & 2 == 0 { min.y } else { max.y },
+        if i & 4 == 0 { min.z } else { max.z },
+    ))
+}
+
+/// A light in a [`LightSet`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Light {
+    /// Parallel rays travelling along `direction`, which needn't be
+    /// normalized
+    Directional { direction: Vector3<f32>, color: Color, intensity: f32 },
+    /// Rays from `position`, fading out linearly to nothing at `range`
+    Point { position: Vector3<f32>, color: Color, intensity: f32, range: f32 },
+}
+
+/// The lights `main.fsh` shades with, uploaded to its `uLights` array. The
+/// first light is the one whose shadow map is sampled.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LightSet {
+    lights: Vec<Light>,
+    /// Added to every surface regardless of the lights
+    pub ambient: Color,
+}
+
+impl LightSet {
+    /// Length of the `uLights` array, `MAX_LIGHTS` in `main.fsh`
+    pub const MAX_LIGHTS: usize = 8;
+
+    pub fn new(ambient: Color) -> LightSet {
+        LightSet { lights: Vec::new(), ambient }
+    }
+
+    /// Adds `light`, which is an error once there are already
+    /// [`LightSet::MAX_LIGHTS`]
+    pub fn add(&mut self, light: Light) -> Result<(), WasmGlError> {
+        if self.lights.len() == Self::MAX_LIGHTS {
+            return Err(WasmGlError::InvalidArgument(format!(
+                "Can't add another light, the shader supports at most {}", Self::MAX_LIGHTS)));
+        }
+        self.lights.push(light);
+        Ok(())
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    pub fn lights_mut(&mut self) -> &mut [Light] {
+        &mut self.lights
+    }
+
+    pub fn clear(&mut self) {
+        self.lights.clear();
+    }
+
+    /// Uploads `uLightCount`, `uAmbient` and each light's `uLights[i]`
+    /// fields to `shader`, which must be enabled
+    pub fn apply(&self, ctx: &WebGl2RenderingContext, shader: &Shader) {
+        let rgb = |color: Color| Vector3::new(color.r, color.g, color.b);
+        shader.set_i32(ctx, "uLightCount", self.lights.len() as i32);
+        shader.set_vec3(ctx, "uAmbient", &rgb(self.ambient));
+        for (i, light) in self.lights.iter().enumerate() {
+            let (kind, direction, position, color, intensity, range) = match *light {
+                Light::Directional { direction, color, intensity } => {
+                    (0, direction.normalize(), Vector3::zeros(), color, intensity, 0.)
+                }
+                Light::Point { position, color, intensity, range } => {
+                    (1, Vector3::zeros(), position, color, intensity, range)
+                }
+            };
+            let field = |name| format!("uLights[{i}].{name}");
+            shader.set_i32(ctx, &field("kind"), kind);
+            shader.set_vec3(ctx, &field("direction"), &direction);
+            shader.set_vec3(ctx, &field("position"), &position);
+            shader.set_vec3(ctx, &field("color"), &rgb(color));
+            shader.set_f32(ctx, &field("intensity"), intensity);
+            shader.set_f32(ctx, &field("range"), range);
+        }
+    }
+}
+
+/// Depth texture and framebuffer for rendering the scene from a light's
+/// point of view
+pub struct ShadowMap {
+    framebuffer: Framebuffer,
+    pub projection: Matrix4<f32>,
+    pub view: Matrix4<f32>,
+    pub config: ShadowConfig,
+}
+
+impl ShadowMap {
+    pub fn new(ctx: &WebGl2RenderingContext, size: u32) -> Result<ShadowMap, WasmGlError> {
+        Ok(ShadowMap {
+            framebuffer: Framebuffer::new(ctx, size, size).with_depth_texture().build()?,
+            projection: Matrix4::identity(),
+            view: Matrix4::identity(),
+            config: ShadowConfig::default(),
+        })
+    }
+
+    /// Uniforms a shader must register to sample this shadow map
+    pub const UNIFORMS: [&'static str; 3] = ["shadowKernelRadius", "shadowTexelSize", "shadowBias"];
+
+    pub fn size(&self) -> u32 {
+        self.framebuffer.width()
+    }
+
+    /// Size of one shadow map texel in texture coordinates
+    pub fn texel_size(&self) -> f32 {
+        1. / self.size() as f32
+    }
+
+    /// Uploads the sampling configuration to `shader`, which must be enabled
+    pub fn apply(&self, ctx: &WebGl2RenderingContext, shader: &Shader) {
+        shader.set_i32(ctx, "shadowKernelRadius", self.config.filter.kernel_radius());
+        shader.set_f32(ctx, "shadowTexelSize", self.texel_size());
+        shader.set_f32(ctx, "shadowBias", self.config.bias);
+    }
+
+    /// Reallocates the depth texture at `new_size`, discarding its contents
+    pub fn resize(&mut self, ctx: &WebGl2RenderingContext, new_size: u32) -> Result<(), WasmGlError> {
+        self.framebuffer.resize(ctx, new_size, new_size)
+    }
+
+    /// Light projection * view, for rendering the shadow pass
+    pub fn projection_view(&self) -> Matrix4<f32> {
+        self.projection * self.view
+    }
+
+    /// Maps world space into shadow map texture space (`0..1` on every axis)
+    pub fn texture_matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_scaling(0.5).append_translation(&Vector3::new(0.5, 0.5, 0.5))
+            * self.projection_view()
+    }
+
+    /// Binds the shadow framebuffer, sets the viewport to cover it, clears
+    /// the depth and enables the configured polygon offset
+    pub fn begin(&self, ctx: &WebGl2RenderingContext) {
+        self.framebuffer.bind(ctx);
+        ctx.clear(WebGl2RenderingContext::DEPTH_BUFFER_BIT);
+        if let Some(offset) = self.config.polygon_offset {
+            ctx.enable(WebGl2RenderingContext::POLYGON_OFFSET_FILL);
+            ctx.polygon_offset(offset.factor, offset.units);
+        }
+    }
+
+    /// Returns to the default framebuffer with a viewport of the given size
+    pub fn end(&self, ctx: &WebGl2RenderingContext, screen_w: i32, screen_h: i32) {
+        if self.config.polygon_offset.is_some() {
+            ctx.disable(WebGl2RenderingContext::POLYGON_OFFSET_FILL);
+        }
+        self.framebuffer.unbind(ctx, screen_w, screen_h);
+    }
+
+    pub fn bind_texture(&self, ctx: &WebGl2RenderingContext, unit: u32) {
+        self.texture().bind(ctx, unit);
+    }
+
+    /// The depth texture the shadow pass renders into
+    pub fn texture(&self) -> &Texture2D {
+        self.framebuffer.depth_texture().unwrap()
+    }
+}
+
+/// Counts frames to redo expensive work such as [`PointShadow`] only every
+/// `interval` frames
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UpdateThrottle {
+    /// 1 to update every frame
+    pub interval: u32,
+    countdown: u32,
+}
+
+impl UpdateThrottle {
+    /// Updates on the first frame, then every `interval` frames
+    pub fn new(interval: u32) -> UpdateThrottle {
+        UpdateThrottle { interval, countdown: 0 }
+    }
+
+    /// Advances a frame, returning whether to update in it
+    pub fn tick(&mut self) -> bool {
+        if self.countdown == 0 {
+            self.countdown = self.interval.max(1) - 1;
+            true
+        } else {
+            self.countdown -= 1;
+            false
+        }
+    }
+
+    /// Makes the next [`UpdateThrottle::tick`] update, e.g. after the light
+    /// moved
+    pub fn force(&mut self) {
+        self.countdown = 0;
+    }
+}
+
+/// Shadows cast in every direction from a point light, as the depth seen
+/// from its position along each axis, rendered into the faces of a cube map.
+/// `main.fsh` samples it for the light `pointShadowLight` indexes, with the
+/// vector from the light to the fragment.
+pub struct PointShadow {
+    context: WebGl2RenderingContext,
+    framebuffer: WebGlFramebuffer,
+    cube_map: CubeMap,
+    pub position: Vector3<f32>,
+    /// Depth range of the face projections; nothing further than `far` from
+    /// the light casts or receives shadows
+    pub near: f32,
+    pub far: f32,
+    /// Subtracted from the fragment's depth before comparing, to avoid acne
+    pub bias: f32,
+    /// How often [`PointShadow::render`] redraws the six faces
+    pub throttle: UpdateThrottle,
+}
+
+impl Drop for PointShadow {
+    fn drop(&mut self) {
+        self.context.delete_framebuffer(Some(&self.framebuffer));
+        gl_object_deleted();
+    }
+}
+
+impl PointShadow {
+    /// Uniforms a shader must register to sample a point shadow, besides the
+    /// cube map sampler
+    pub const UNIFORMS: [&'static str; 3] = ["pointShadowLight", "pointShadowRange", "pointShadowBias"];
+
+    /// View directions and up vectors of the faces, in [`CubeMap::FACES`]
+    /// order, following the cube map face orientations
+    const FACE_AXES: [([f32; 3], [f32; 3]); 6] = [
+        ([1., 0., 0.], [0., -1., 0.]),
+        ([-1., 0., 0.], [0., -1., 0.]),
+        ([0., 1., 0.], [0., 0., 1.]),
+        ([0., -1., 0.], [0., 0., -1.]),
+        ([0., 0., 1.], [0., -1., 0.]),
+        ([0., 0., -1.], [0., -1., 0.]),
+    ];
+
+    /// A `size` x `size` depth cube map for a light at `position` reaching
+    /// `far`, rendered every frame
+    pub fn new(ctx: &WebGl2RenderingContext, size: u32, position: Vector3<f32>, far: f32) -> Result<PointShadow, WasmGlError> {
+        let cube_map = CubeMap::empty(ctx, size, TextureFormat::Depth32F)?;
+        // Float depth can't be filtered, and is compared in the shader
+        for parameter in [WebGl2RenderingContext::TEXTURE_MIN_FILTER, WebGl2RenderingContext::TEXTURE_MAG_FILTER] {
+            ctx.tex_parameteri(WebGl2RenderingContext::TEXTURE_CUBE_MAP, parameter, WebGl2RenderingContext::NEAREST as i32);
+        }
+        let framebuffer = ctx.create_framebuffer().ok_or(WasmGlError::ResourceCreation { what: "framebuffer" })?;
+        gl_object_created();
+        let result = PointShadow {
+            context: ctx.clone(),
+            framebuffer,
+            cube_map,
+            position,
+            near: 0.05,
+            far,
+            bias: 0.002,
+            throttle: UpdateThrottle::new(1),
+        };
+        ctx.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&result.framebuffer));
+        result.attach_face(ctx, 0);
+        let status = ctx.check_framebuffer_status(WebGl2RenderingContext::FRAMEBUFFER);
+        ctx.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        framebuffer_status(status)?;
+        Ok(result)
+    }
+
+    fn attach_face(&self, ctx: &WebGl2RenderingContext, face: usize) {
+        ctx.framebuffer_texture_2d(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            WebGl2RenderingContext::DEPTH_ATTACHMENT,
+            CubeMap::FACES[face],
+            Some(self.cube_map.handle()),
+            0,
+        );
+    }
+
+    /// The 90° square projection shared by all faces
+    pub fn projection(&self) -> Matrix4<f32> {
+        Matrix4::new_perspective(1., std::f32::consts::FRAC_PI_2, self.near, self.far)
+    }
+
+    /// Projection * view of each face, in [`CubeMap::FACES`] order
+    pub fn face_matrices(&self) -> [Matrix4<f32>; 6] {
+        cube_face_matrices(self.position, self.projection())
+    }
+
+    /// If the throttle allows it this frame, renders each face: binds it,
+    /// clears its depth and calls `draw_face` with the face index and its
+    /// projection * view, which the shadow pass should render with. Returns
+    /// to the default framebuffer with a viewport of the given size
+    /// afterwards, and returns whether it rendered.
+    pub fn render(
+        &mut self,
+        ctx: &WebGl2RenderingContext,
+        screen_w: i32,
+        screen_h: i32,
+        mut draw_face: impl FnMut(usize, &Matrix4<f32>),
+    ) -> bool {
+        if !self.throttle.tick() {
+            return false;
+        }
+        ctx.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&self.framebuffer));
+        let size = self.cube_map.size() as i32;
+        ctx.viewport(0, 0, size, size);
+        for (face, projection_view) in self.face_matrices().iter().enumerate() {
+            self.attach_face(ctx, face);
+            ctx.clear(WebGl2RenderingContext::DEPTH_BUFFER_BIT);
+            draw_face(face, projection_view);
+        }
+        ctx.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        ctx.viewport(0, 0, screen_w, screen_h);
+        true
+    }
+
+    /// Uploads the sampling configuration to `shader`, which must be enabled,
+    /// for the light at index `light` of its [`LightSet`]
+    pub fn apply(&self, ctx: &WebGl2RenderingContext, shader: &Shader, light: usize) {
+        shader.set_i32(ctx, "pointShadowLight", light as i32);
+        shader.set_vec2(ctx, "pointShadowRange", &Vector2::new(self.near, self.far));
+        shader.set_f32(ctx, "pointShadowBias", self.bias);
+    }
+
+    /// Disables point shadows in `shader`, which must be enabled. Shaders
+    /// that registered [`PointShadow::UNIFORMS`] need this or
+    /// [`PointShadow::apply`], as `pointShadowLight` otherwise defaults to 0.
+    pub fn apply_none(ctx: &WebGl2RenderingContext, shader: &Shader) {
+        shader.set_i32(ctx, "pointShadowLight", -1);
+    }
+
+    pub fn bind_texture(&self, ctx: &WebGl2RenderingContext, unit: u32) {
+        self.cube_map.bind(ctx, unit);
+    }
+
+    /// The depth cube map the faces render into
+    pub fn cube_map(&self) -> &CubeMap {
+        &self.cube_map
+    }
+}
+
+fn cube_face_matrices(position: Vector3<f32>, projection: Matrix4<f32>) -> [Matrix4<f32>; 6] {
+    let eye = position.into();
+    PointShadow::FACE_AXES.map(|(direction, up)| {
+        let target = eye + Vector3::from(direction);
+        projection * Matrix4::look_at_rh(&eye, &target, &Vector3::from(up))
+    })
+}
+
+/// View depths splitting `near..far` into `count` cascades, `count + 1` of
+/// them from `near` to `far`. `lambda` blends the logarithmic split (1),
+/// which keeps texel density even, with the uniform one (0), which gives
+/// far cascades more of the range.
+pub fn cascade_splits(near: f32, far: f32, count: usize, lambda: f32) -> Vec<f32> {
+    (0..=count)
+        .map(|i| {
+            let fraction = i as f32 / count as f32;
+            let logarithmic = near * (far / near).powf(fraction);
+            let uniform = near + (far - near) * fraction;
+            lambda * logarithmic + (1. - lambda) * uniform
+        })
+        .collect()
+}
+
+/// A directional light's shadow map split into cascades along the camera's
+/// view depth, each fitted to its slice of the frustum so shadows close to
+/// the camera get more texels. The cascades are layers of one depth texture
+/// array, which `main.fsh` samples by view depth when `cascadeCount` is
+/// non-zero.
+pub struct CascadedShadowMap {
+    context: WebGl2RenderingContext,
+    texture: WebGlTexture,
+    framebuffer: WebGlFramebuffer,
+    size: u32,
+    /// Blends the split scheme, see [`cascade_splits`]
+    pub lambda: f32,
+    /// View depth past which nothing is shadowed, if closer than the
+    /// camera's far plane, e.g. where fog hides everything anyway
+    pub max_distance: f32,
+    /// Fraction of each cascade, at its far end, cross-faded into the next
+    /// to hide the seam
+    pub blend: f32,
+    /// Tints fragments red, green, blue or yellow by cascade, for tuning
+    pub debug_tint: bool,
+    pub config: ShadowConfig,
+    splits: Vec<f32>,
+    /// Light projection * view of each cascade
+    projection_views: Vec<Matrix4<f32>>,
+}
+
+impl Drop for CascadedShadowMap {
+    fn drop(&mut self) {
+        self.context.delete_texture(Some(&self.texture));
+        self.context.delete_framebuffer(Some(&self.framebuffer));
+        gl_object_deleted();
+        gl_object_deleted();
+    }
+}
+
+impl CascadedShadowMap {
+    /// The size of the `cascade*` uniform arrays in `main.fsh`
+    pub const MAX_CASCADES: usize = 4;
+
+    /// Uniforms a shader must register to sample the cascades, besides the
+    /// `sampler2DArray` and the [`ShadowMap::UNIFORMS`], which are shared
+    pub const UNIFORMS: [&'static str; 3] = ["cascadeCount", "cascadeBlend", "cascadeDebug"];
+
+    /// `count` cascades of `size` x `size` texels. Call
+    /// [`CascadedShadowMap::update`] before rendering.
+    pub fn new(ctx: &WebGl2RenderingContext, size: u32, count: usize) -> Result<CascadedShadowMap, WasmGlError> {
+        let framebuffer = ctx.create_framebuffer().ok_or(WasmGlError::ResourceCreation { what: "framebuffer" })?;
+        let Some(texture) = ctx.create_texture() else {
+            ctx.delete_framebuffer(Some(&framebuffer));
+            return Err(WasmGlError::ResourceCreation { what: "texture array" });
+        };
+        gl_object_created();
+        gl_object_created();
+        let mut result = CascadedShadowMap {
+            context: ctx.clone(),
+            texture,
+            framebuffer,
+            size,
+            lambda: 0.75,
+            max_distance: f32::INFINITY,
+            blend: 0.1,
+            debug_tint: false,
+            config: ShadowConfig::default(),
+            splits: Vec::new(),
+            projection_views: Vec::new(),
+        };
+        result.set_count(ctx, count)?;
+        Ok(result)
+    }
+
+    pub fn count(&self) -> usize {
+        self.splits.len().saturating_sub(1)
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Reallocates the texture array for `count` cascades, discarding its
+    /// contents. Call [`CascadedShadowMap::update`] afterwards.
+    pub fn set_count(&mut self, ctx: &WebGl2RenderingContext, count: usize) -> Result<(), WasmGlError> {
+        if !(1..=Self::MAX_CASCADES).contains(&count) {
+            return Err(WasmGlError::InvalidArgument(format!(
+                "Expected 1 to {} cascades, got {count}", Self::MAX_CASCADES)));
+        }
+        validate_texture(self.size, self.size, max_texture_size(ctx)?, TextureFormat::Depth32F, None)?;
+        let (internal_format, format, type_) = TextureFormat::Depth32F.gl_formats();
+        let target = WebGl2RenderingContext::TEXTURE_2D_ARRAY;
+        ctx.bind_texture(target, Some(&self.texture));
+        ctx.tex_image_3d_with_opt_u8_array(
+            target, 0, internal_format as i32, self.size as i32, self.size as i32, count as i32, 0, format, type_, None,
+        )?;
+        check_gl(ctx, "CascadedShadowMap texImage3D");
+        // Float depth can't be filtered, and is compared in the shader
+        for (parameter, value) in [
+            (WebGl2RenderingContext::TEXTURE_MIN_FILTER, WebGl2RenderingContext::NEAREST),
+            (WebGl2RenderingContext::TEXTURE_MAG_FILTER, WebGl2RenderingContext::NEAREST),
+            (WebGl2RenderingContext::TEXTURE_WRAP_S, WebGl2RenderingContext::CLAMP_TO_EDGE),
+            (WebGl2RenderingContext::TEXTURE_WRAP_T, WebGl2RenderingContext::CLAMP_TO_EDGE),
+        ] {
+            ctx.tex_parameteri(target, parameter, value as i32);
+        }
+        // Placeholder splits until the next update
+        self.splits = vec![0.; count + 1];
+        self.projection_views = vec![Matrix4::identity(); count];
+
+        ctx.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&self.framebuffer));
+        self.attach_layer(ctx, 0);
+        check_gl(ctx, "CascadedShadowMap attachment");
+        let status = ctx.check_framebuffer_status(WebGl2RenderingContext::FRAMEBUFFER);
+        ctx.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        framebuffer_status(status)
+    }
+
+    fn attach_layer(&self, ctx: &WebGl2RenderingContext, layer: usize) {
+        ctx.framebuffer_texture_layer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            WebGl2RenderingContext::DEPTH_ATTACHMENT,
+            Some(&self.texture),
+            0,
+            layer as i32,
+        );
+    }
+
+    /// Splits `camera`'s frustum and fits each cascade's projection to its
+    /// slice, for `light` shining on the world space box `min..max` that
+    /// holds every shadow caster
+    pub fn update(&mut self, camera: &Camera, light: &DirectionalLight, min: Vector3<f32>, max: Vector3<f32>) {
+        let far = camera.far.min(self.max_distance);
+        self.splits = cascade_splits(camera.near, far, self.count(), self.lambda);
+        self.projection_views = self
+            .splits
+            .windows(2)
+            .map(|depths| {
+                let corners = camera.frustum_corners(depths[0], depths[1]);
+                let (view, projection) = light.slice_shadow_matrices(&corners, min, max);
+                projection * view
+            })
+            .collect();
+    }
+
+    /// View depths where each cascade ends, after the camera's near plane
+    pub fn splits(&self) -> &[f32] {
+        &self.splits[1..]
+    }
+
+    /// Light projection * view of each cascade, for rendering its shadow pass
+    pub fn projection_views(&self) -> &[Matrix4<f32>] {
+        &self.projection_views
+    }
+
+    /// Renders each cascade: binds its layer, clears its depth and calls
+    /// `draw_cascade` with the cascade index and its projection * view, which
+    /// the shadow pass should render with. Returns to the default framebuffer
+    /// with a viewport of the given size afterwards.
+    pub fn render(
+        &self,
+        ctx: &WebGl2RenderingContext,
+        screen_w: i32,
+        screen_h: i32,
+        mut draw_cascade: impl FnMut(usize, &Matrix4<f32>),
+    ) {
+        ctx.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&self.framebuffer));
+        ctx.viewport(0, 0, self.size as i32, self.size as i32);
+        if let Some(offset) = self.config.polygon_offset {
+            ctx.enable(WebGl2RenderingContext::POLYGON_OFFSET_FILL);
+            ctx.polygon_offset(offset.factor, offset.units);
+        }
+        for (i, projection_view) in self.projection_views.iter().enumerate() {
+            self.attach_layer(ctx, i);
+            ctx.clear(WebGl2RenderingContext::DEPTH_BUFFER_BIT);
+            draw_cascade(i, projection_view);
+        }
+        if self.config.polygon_offset.is_some() {
+            ctx.disable(WebGl2RenderingContext::POLYGON_OFFSET_FILL);
+        }
+        ctx.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        ctx.viewport(0, 0, screen_w, screen_h);
+    }
+
+    /// Uploads the cascades' splits and texture matrices and the sampling
+    /// configuration to `shader`, which must be enabled
+    pub fn apply(&self, ctx: &WebGl2RenderingContext, shader: &Shader) {
+        shader.set_i32(ctx, "cascadeCount", self.count() as i32);
+        shader.set_f32(ctx, "cascadeBlend", self.blend);
+        shader.set_i32(ctx, "cascadeDebug", self.debug_tint as i32);
+        let to_texture = Matrix4::new_scaling(0.5).append_translation(&Vector3::new(0.5, 0.5, 0.5));
+        for (i, (split, projection_view)) in self.splits().iter().zip(&self.projection_views).enumerate() {
+            shader.set_f32(ctx, &format!("cascadeSplits[{i}]"), *split);
+            shader.set_mat4(ctx, &format!("cascadeMatrices[{i}]"), &(to_texture * projection_view));
+        }
+        shader.set_i32(ctx, "shadowKernelRadius", self.config.filter.kernel_radius());
+        shader.set_f32(ctx, "shadowTexelSize", 1. / self.size as f32);
+        shader.set_f32(ctx, "shadowBias", self.config.bias);
+    }
+
+    /// Disables the cascades in `shader`, which must be enabled, so it
+    /// samples the single [`ShadowMap`] instead
+    pub fn apply_none(ctx: &WebGl2RenderingContext, shader: &Shader) {
+        shader.set_i32(ctx, "cascadeCount", 0);
+    }
+
+    pub fn bind_texture(&self, ctx: &WebGl2RenderingContext, unit: u32) {
+        bind_texture_unit(ctx, unit, WebGl2RenderingContext::TEXTURE_2D_ARRAY, &self.texture);
+    }
+}
+
+/// Makes `TEXTURE0 + unit` the active unit and binds `texture` to `target`
+/// on it, e.g. `TEXTURE_2D` or `TEXTURE_CUBE_MAP`. The active unit stays
+/// changed afterwards.
+pub fn bind_texture_unit(ctx: &WebGl2RenderingContext, unit: u32, target: u32, texture: &WebGlTexture) {
+    ctx.active_texture(WebGl2RenderingContext::TEXTURE0 + unit);
+    ctx.bind_texture(target, Some(texture));
+    record_texture_bind(ctx);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FogMode {
+    None,
+    /// Fog ramps up linearly between the two view-space depths
+    Linear { start: f32, end: f32 },
+    /// Fog amount is `1 - e^(-density * depth)`
+    Exponential { density: f32 },
+}
+
+/// Depth fog blending lit colors toward `color`, applied in `main.fsh`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FogConfig {
+    pub color: Color,
+    pub mode: FogMode,
+}
+
+impl Default for FogConfig {
+    fn default() -> Self {
+        FogConfig { color: Color::default(), mode: FogMode::None }
+    }
+}
+
+impl FogConfig {
+    /// Uniforms a shader must register to accept fog
+    pub const UNIFORMS: [&'static str; 5] = ["fogMode", "fogColor", "fogDensity", "fogStart", "fogEnd"];
+
+    /// Uploads the fog uniforms to `shader`, which must be enabled
+    pub fn apply(&self, ctx: &WebGl2RenderingContext, shader: &Shader) {
+        let (mode, density, start, end) = match self.mode {
+            FogMode::None => (0, 0., 0., 0.),
+            FogMode::Linear { start, end } => (1, 0., start, end),
+            FogMode::Exponential { density } => (2, density, 0., 0.),
+        };
+        shader.set_i32(ctx, "fogMode", mode);
+        shader.set_vec3(ctx, "fogColor", &Vector3::new(self.color.r, self.color.g, self.color.b));
+        shader.set_f32(ctx, "fogDensity", density);
+        shader.set_f32(ctx, "fogStart", start);
+        shader.set_f32(ctx, "fogEnd", end);
+    }
+}
+
+/// Cel shading for `main.fsh`, quantizing the diffuse term into bands
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ToonConfig {
+    /// Number of bands, 0 disables toon shading
+    pub bands: u32,
+    /// Fraction (`0..=1`) of each band smoothed into the next, 0 for hard edges
+    pub edge: f32,
+}
+
+impl ToonConfig {
+    /// Uniforms a shader must register to accept toon shading
+    pub const UNIFORMS: [&'static str; 2] = ["toonBands", "toonEdge"];
+
+    /// Uploads the toon uniforms to `shader`, which must be enabled
+    pub fn apply(&self, ctx: &WebGl2RenderingContext, shader: &Shader) {
+        shader.set_i32(ctx, "toonBands", self.bands as i32);
+        shader.set_f32(ctx, "toonEdge", self.edge.clamp(0., 1.));
+    }
+}
+
+/// Sways instanced geometry in `instancing.glsl`, bending each vertex along
+/// the wind in proportion to its height above the mesh origin
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WindConfig {
+    /// Horizontal direction, along world X and Z. Normalized when applied.
+    pub direction: Vector2<f32>,
+    /// Sideways offset per unit of height at the peak of a gust, 0 for none
+    pub strength: f32,
+}
+
+impl Default for WindConfig {
+    fn default() -> Self {
+        WindConfig { direction: Vector2::new(1., 0.), strength: 0. }
+    }
+}
+
+impl WindConfig {
+    /// Uniforms a shader must register to accept wind
+    pub const UNIFORMS: [&'static str; 2] = ["windDirection", "windStrength"];
+
+    /// Uploads the wind uniforms to `shader`, which must be enabled
+    pub fn apply(&self, ctx: &WebGl2RenderingContext, shader: &Shader) {
+        let direction = self.direction.try_normalize(1e-6).unwrap_or_else(Vector2::zeros);
+        shader.set_vec2(ctx, "windDirection", &direction);
+        shader.set_f32(ctx, "windStrength", self.strength);
+    }
+}
+
+/// A free-look perspective camera. Yaw rotates about +Y and pitch about the
+/// camera's right axis; yaw = pitch = 0 looks down -Z.
+#[derive(Clone, Debug)]
+pub struct Camera {
+    pub position: Vector3<f32>,
+    yaw: f32,
+    pitch: f32,
+    pub fovy: f32,
+    pub near: f32,
+    pub far: f32,
+    aspect: f32,
+}
+
+impl Camera {
+    /// Pitch stays just short of vertical so the view basis never degenerates.
+    pub const MAX_PITCH: f32 = 89. * std::f32::consts::PI / 180.;
+
+    pub fn new(position: Vector3<f32>, fovy: f32, near: f32, far: f32) -> Camera {
+        Camera {
+            position,
+            yaw: 0.,
+            pitch: 0.,
+            fovy,
+            near,
+            far,
+            aspect: 1.,
+        }
+    }
+
+    pub fn yaw(&self) -> f32 {
+        self.yaw
+    }
+
+    pub fn pitch(&self) -> f32 {
+        self.pitch
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.aspect
+    }
+
+    pub fn set_aspect(&mut self, width: i32, height: i32) {
+        // Same guard as `perspective_matrix`
+        self.aspect = width.max(1) as f32 / height.max(1) as f32;
+    }
+
+    /// Adds to the current yaw and pitch, in radians.
+    pub fn rotate(&mut self, yaw: f32, pitch: f32) {
+        self.set_rotation(self.yaw + yaw, self.pitch + pitch);
+    }
+
+    /// Sets the yaw and pitch, in radians, clamping the pitch
+    pub fn set_rotation(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = yaw % std::f32::consts::TAU;
+        self.pitch = pitch.clamp(-Self::MAX_PITCH, Self::MAX_PITCH);
+    }
+
+    pub fn forward(&self) -> Vector3<f32> {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        Vector3::new(cos_pitch * sin_yaw, sin_pitch, -cos_pitch * cos_yaw)
+    }
+
+    pub fn right(&self) -> Vector3<f32> {
+        self.forward().cross(&Vector3::y()).normalize()
+    }
+
+    pub fn up(&self) -> Vector3<f32> {
+        self.right().cross(&self.forward())
+    }
+
+    /// Moves the camera along its own right/up/forward axes
+    /// (x = right, y = up, z = forward).
+    pub fn translate_local(&mut self, offset: Vector3<f32>) {
+        self.position += self.right() * offset.x + self.up() * offset.y + self.forward() * offset.z;
+    }
+
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        look_at(self.position, self.position + self.forward(), Vector3::y())
+    }
+
+    pub fn projection_matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_perspective(self.aspect, self.fovy, self.near, self.far)
+    }
+
+    /// World space corners of the part of the view frustum between the view
+    /// depths `near` and `far`, near corners first
+    pub fn frustum_corners(&self, near: f32, far: f32) -> [Vector3<f32>; 8] {
+        let projection = Matrix4::new_perspective(self.aspect, self.fovy, near, far);
+        let inverse = (projection * self.view_matrix()).try_inverse().unwrap_or_else(Matrix4::identity);
+        box_corners(Vector3::repeat(-1.), Vector3::repeat(1.)).map(|ndc| {
+            inverse.transform_point(&ndc.into()).coords
+        })
+    }
+}
+
+/// Perspective projection for a `width` x `height` pixel viewport, with the
+/// vertical field of view `fovy` in radians
+pub fn perspective_matrix(width: i32, height: i32, fovy: f32, near: f32, far: f32) -> Matrix4<f32> {
+    // Avoid a NaN matrix while the canvas is collapsed
+    let aspect = width.max(1) as f32 / height.max(1) as f32;
+    Matrix4::new_perspective(aspect, fovy, near, far)
+}
+
+/// Right-handed view matrix for an eye at `eye` facing `target`, with
+/// `up` roughly towards the top of the screen. `up` must not be parallel to
+/// the view direction.
+pub fn look_at(eye: Vector3<f32>, target: Vector3<f32>, up: Vector3<f32>) -> Matrix4<f32> {
+    let forward = (target - eye).normalize();
+    let right = forward.cross(&up).normalize();
+    let up = right.cross(&forward);
+    Matrix4::new(
+        right.x, right.y, right.z, -right.dot(&eye),
+        up.x, up.y, up.z, -up.dot(&eye),
+        -forward.x, -forward.y, -forward.z, forward.dot(&eye),
+        0., 0., 0., 1.,
+    )
+}
+
+/// Orthographic projection of the view-space box `left..right`,
+/// `bottom..top`, `-near..-far` onto clip space, as with `glOrtho`
+pub fn orthographic_matrix(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Matrix4<f32> {
+    let (width, height, depth) = (right - left, top - bottom, far - near);
+    Matrix4::new(
+        2. / width, 0., 0., -(right + left) / width,
+        0., 2. / height, 0., -(top + bottom) / height,
+        0., 0., -2. / depth, -(far + near) / depth,
+        0., 0., 0., 1.,
+    )
+}
+
+/// Backing store size for a canvas laid out at `css_width` x `css_height`
+/// CSS pixels, so that it renders at native resolution on HiDPI displays.
+/// `max_pixel_ratio` caps the ratio to limit fill rate, e.g. on phones.
+pub fn backing_size(css_width: f64, css_height: f64, pixel_ratio: f64, max_pixel_ratio: Option<f64>) -> (u32, u32) {
+    let ratio = match max_pixel_ratio {
+        Some(max) => pixel_ratio.min(max),
+        None => pixel_ratio,
+    };
+    // `as` saturates, so NaN and negative sizes become 0
+    ((css_width * ratio).round() as u32, (css_height * ratio).round() as u32)
+}
+
+/// Sizes `canvas` to fill the window: its CSS size is set to the window's
+/// logical size and its backing store to the physical pixel size (see
+/// [`backing_size`]), which is returned for the viewport and projection
+pub fn resize_canvas(window: &Window, canvas: &HtmlCanvasElement, max_pixel_ratio: Option<f64>) -> (i32, i32) {
+    let css_width = window.inner_width().unwrap().as_f64().unwrap_or_default();
+    let css_height = window.inner_height().unwrap().as_f64().unwrap_or_default();
+    let style = canvas.style();
+    style.set_property("width", &format!("{css_width}px")).unwrap();
+    style.set_property("height", &format!("{css_height}px")).unwrap();
+    let (width, height) = backing_size(css_width, css_height, window.device_pixel_ratio(), max_pixel_ratio);
+    canvas.set_width(width);
+    canvas.set_height(height);
+    (canvas.width() as i32, canvas.height() as i32)
+}
+
+/// Calls `on_change` whenever `devicePixelRatio` changes, e.g. when the
+/// window moves to another monitor or the page is zoomed. A media query only
+/// matches one resolution, so a new one is registered after every change,
+/// for as long as `on_change` returns true.
+fn watch_pixel_ratio(on_change: Rc<dyn Fn() -> bool>) -> Result<(), JsValue> {
+    let window = window().unwrap();
+    let query = format!("(resolution: {}dppx)", window.device_pixel_ratio());
+    let list = match window.match_media(&query)? {
+        Some(list) => list,
+        None => return Ok(()),
+    };
+    let callback = Closure::once_into_js(move || {
+        if on_change() {
+            watch_pixel_ratio(on_change).unwrap_throw();
+        }
+    });
+    let options = AddEventListenerOptions::new();
+    options.set_once(true);
+    list.add_event_listener_with_callback_and_add_event_listener_options(
+        "change", callback.unchecked_ref(), &options)
+}
+
+type FrameClosure = Closure<dyn FnMut(f64)>;
+
+/// Shared between a [`RenderLoopHandle`] and its JS callbacks, which only
+/// hold weak references so that dropping the handle frees everything
+struct LoopState {
+    callback: RefCell<Box<dyn FnMut(bool, f64)>>,
+    frame: RefCell<Option<FrameClosure>>,
+    resize: RefCell<Option<Closure<dyn FnMut()>>>,
+    frame_id: Cell<Option<i32>>,
+    /// Latest `requestAnimationFrame` timestamp so far, in milliseconds
+    last_frame: Cell<Option<f64>>,
+    frame_times: RefCell<FrameTimes>,
+    paused: Cell<bool>,
+    stopped: Cell<bool>,
+}
+
+/// Seconds from the latest `requestAnimationFrame` timestamp in `last` to
+/// `now`, or 0 for the first frame, advancing `last` to `now`. Timestamps can
+/// repeat or (rarely) go backwards, which gives 0 and keeps the latest
+/// timestamp, so deltas never go negative and add up to the time elapsed.
+fn frame_delta(last: &Cell<Option<f64>>, now: f64) -> f64 {
+    match last.get() {
+        Some(latest) if latest >= now => 0.,
+        latest => {
+            last.set(Some(now));
+            latest.map_or(0., |latest| (now - latest) / 1000.)
+        }
+    }
+}
+
+/// The most recent frame timestamps, kept as-is so that averaging only
+/// costs anything when someone asks for it
+struct FrameTimes {
+    times: [f64; FrameTimes::WINDOW],
+    len: usize,
+    next: usize,
+}
+
+impl Default for FrameTimes {
+    fn default() -> Self {
+        FrameTimes { times: [0.; FrameTimes::WINDOW], len: 0, next: 0 }
+    }
+}
+
+impl FrameTimes {
+    const WINDOW: usize = 60;
+
+    fn push(&mut self, timestamp: f64) {
+        self.times[self.next] = timestamp;
+        self.next = (self.next + 1) % Self::WINDOW;
+        self.len = (self.len + 1).min(Self::WINDOW);
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Frames per second over the window, or 0 before there are two frames
+    fn fps(&self) -> f32 {
+        if self.len < 2 {
+            return 0.;
+        }
+        let newest = self.times[(self.next + Self::WINDOW - 1) % Self::WINDOW];
+        let oldest = self.times[(self.next + Self::WINDOW - self.len) % Self::WINDOW];
+        let elapsed = newest - oldest;
+        if elapsed <= 0. {
+            return 0.;
+        }
+        ((self.len - 1) as f64 * 1000. / elapsed) as f32
+    }
+}
+
+impl LoopState {
+    fn run(&self, resize: bool, delta: f64) {
+        // A callback that re-enters the loop (e.g. by dispatching a resize
+        // event) just skips the nested call instead of panicking
+        if let Ok(mut callback) = self.callback.try_borrow_mut() {
+            callback(resize, delta);
+        }
+    }
+
+    fn schedule(&self) {
+        if self.paused.get() || self.stopped.get() || self.frame_id.get().is_some() {
+            return;
+        }
+        if let Some(frame) = self.frame.borrow().as_ref() {
+            self.frame_id.set(Some(request_animation_frame(frame)));
+        }
+    }
+
+    fn cancel(&self) {
+        if let Some(id) = self.frame_id.take() {
+            window().unwrap().cancel_animation_frame(id).unwrap_throw();
+        }
+    }
+}
+
+/// Controls a loop started by [`render_loop`]. Dropping the handle stops the
+/// loop, so keep it alive (or [`RenderLoopHandle::forget`] it) for as long as
+/// rendering should continue.
+pub struct RenderLoopHandle {
+    state: Rc<LoopState>,
+}
+
+impl RenderLoopHandle {
+    /// Stops scheduling frames until [`RenderLoopHandle::resume`]. Resizes
+    /// are still delivered so the first frame after resuming is correct, and
+    /// that frame gets a delta of 0 rather than the time spent paused.
+    pub fn pause(&self) {
+        self.state.paused.set(true);
+        self.state.last_frame.set(None);
+        self.state.frame_times.borrow_mut().clear();
+        self.state.cancel();
+    }
+
+    pub fn resume(&self) {
+        self.state.paused.set(false);
+        self.state.schedule();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.get()
+    }
+
+    /// Frames per second averaged over roughly the last second of frames,
+    /// or 0 until two frames have run since starting or resuming
+    pub fn last_fps(&self) -> f32 {
+        self.state.frame_times.borrow().fps()
+    }
+
+    /// Cancels the pending frame and removes the event listeners for good,
+    /// freeing their closures. Safe to call from within the callback, which
+    /// then isn't called again.
+    pub fn stop(&self) {
+        if self.state.stopped.replace(true) {
+            return;
+        }
+        self.state.cancel();
+        self.state.frame.borrow_mut().take();
+        if let Some(resize) = self.state.resize.borrow_mut().take() {
+            window()
+                .unwrap()
+                .remove_event_listener_with_callback("resize", resize.as_ref().unchecked_ref())
+                .unwrap_throw();
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.state.stopped.get()
+    }
+
+    /// Keeps the loop running for the lifetime of the page
+    pub fn forget(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for RenderLoopHandle {
+    fn drop(&mut self) {
+        // The closures are freed along with the state. Freeing one while JS
+        // is still running it is fine, wasm-bindgen defers that until it
+        // returns.
+        self.stop();
+    }
+}
+
+/// Calls `callback` every animation frame with `false`, and with `true` once
+/// up front and whenever the window is resized or its pixel ratio changes.
+/// The second argument is the time in seconds since the previous frame, for
+/// framerate-independent motion; it is 0 for resizes and the first frame.
+pub fn render_loop(callback: impl FnMut(bool, f64) + 'static) -> Result<RenderLoopHandle, JsValue> {
+    let state = Rc::new(LoopState {
+        callback: RefCell::new(Box::new(callback)),
+        frame: RefCell::new(None),
+        resize: RefCell::new(None),
+        frame_id: Cell::new(None),
+        last_frame: Cell::new(None),
+        frame_times: RefCell::new(FrameTimes::default()),
+        paused: Cell::new(false),
+        stopped: Cell::new(false),
+    });
+    state.run(true, 0.);
+
+    let weak = Rc::downgrade(&state);
+    *state.frame.borrow_mut() = Some(Closure::new(move |timestamp: f64| {
+        if let Some(state) = weak.upgrade() {
+            if state.stopped.get() {
+                return;
+            }
+            state.frame_id.set(None);
+            let delta = frame_delta(&state.last_frame, timestamp);
+            state.frame_times.borrow_mut().push(timestamp);
+            state.run(false, delta);
+            state.schedule();
+        }
+    }));
+    let weak = Rc::downgrade(&state);
+    let resize = Closure::<dyn FnMut()>::new(move || {
+        if let Some(state) = weak.upgrade() {
+            if !state.stopped.get() {
+                state.run(true, 0.);
+            }
+        }
+    });
+    window().unwrap().add_event_listener_with_callback("resize", resize.as_ref().unchecked_ref())?;
+    *state.resize.borrow_mut() = Some(resize);
+
+    let weak: Weak<LoopState> = Rc::downgrade(&state);
+    watch_pixel_ratio(Rc::new(move || match weak.upgrade() {
+        Some(state) if !state.stopped.get() => {
+            state.run(true, 0.);
+            true
+        }
+        _ => false,
+    }))?;
+
+    state.schedule();
+    Ok(RenderLoopHandle { state })
+}
+
+/// [`render_loop`] for callbacks that don't need the frame delta
+pub fn render_loop_simple(mut callback: impl FnMut(bool) + 'static) -> Result<RenderLoopHandle, JsValue> {
+    render_loop(move |resize, _| callback(resize))
+}
+
+/// Splits variable frame deltas into whole fixed-size steps, carrying the
+/// remainder over to the next frame
+struct FixedTimestep {
+    step: f64,
+    accumulator: f64,
+}
+
+impl FixedTimestep {
+    /// Most steps run in one frame. After a long stall (e.g. a debugger
+    /// pause) the backlog is dropped past this, rather than letting ever
+    /// longer catch-up frames fall further behind.
+    const MAX_STEPS: u32 = 10;
+
+    /// Runs `update` once per whole step in `delta` plus the carried
+    /// remainder, returning how far into the next step the frame is (0..1)
+    fn advance(&mut self, delta: f64, mut update: impl FnMut(f64)) -> f64 {
+        self.accumulator += delta;
+        let mut steps = 0;
+        // Tolerate rounding so that e.g. 0.1s is exactly six 1/60s steps
+        while self.accumulator >= self.step - 1e-9 {
+            if steps == Self::MAX_STEPS {
+                self.accumulator %= self.step;
+                break;
+            }
+            update(self.step);
+            self.accumulator = (self.accumulator - self.step).max(0.);
+            steps += 1;
+        }
+        self.accumulator / self.step
+    }
+}
+
+/// The update rate of [`render_loop_fixed`]
+pub const FIXED_STEP: f64 = 1. / 60.;
+
+/// A [`render_loop`] for simulations that need a deterministic step: `update`
+/// runs zero or more times per frame with exactly [`FIXED_STEP`] seconds,
+/// then `render` is called once with the resize flag and how far (0..1) the
+/// frame is between the last update and the next, for interpolating state.
+/// At most 10 updates run per frame, dropping the rest after long stalls.
+pub fn render_loop_fixed(
+    mut update: impl FnMut(f64) + 'static,
+    mut render: impl FnMut(bool, f64) + 'static,
+) -> Result<RenderLoopHandle, JsValue> {
+    let mut timestep = FixedTimestep { step: FIXED_STEP, accumulator: 0. };
+    render_loop(move |resize, delta| {
+        let alpha = timestep.advance(delta, &mut update);
+        render(resize, alpha);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BROKEN_SOURCE: &str = "#version 300 es
+precision mediump float;
+uniform vec3 color;
+out vec4 fragColor;
+void main() {
+    fragColor = vec4(undefined_color, 1);
+}
+";
+
+    #[test]
+    fn angle_logs_quote_the_line_and_token() {
+        let log = "ERROR: 0:6: 'undefined_color' : undeclared identifier\n\
+                   ERROR: 0:6: 'constructor' : not enough data provided for construction\n\0";
+        let annotated = annotate_compile_log(BROKEN_SOURCE, log);
+        let expected = "\
+ERROR: 0:6: 'undefined_color' : undeclared identifier
+  4 | out vec4 fragColor;
+  5 | void main() {
+> 6 |     fragColor = vec4(undefined_color, 1);
+    |                      ^^^^^^^^^^^^^^^
+  7 | }
+ERROR: 0:6: 'constructor' : not enough data provided for construction
+  4 | out vec4 fragColor;
+  5 | void main() {
+> 6 |     fragColor = vec4(undefined_color, 1);
+  7 | }
+";
+        assert_eq!(annotated, expected);
+    }
+
+    #[test]
+    fn mesa_logs_mark_the_column() {
+        let annotated = annotate_compile_log(BROKEN_SOURCE, "0:1(10): error: syntax error, unexpected IDENTIFIER");
+        let expected = "\
+0:1(10): error: syntax error, unexpected IDENTIFIER
+> 1 | #version 300 es
+    |          ^
+  2 | precision mediump float;
+  3 | uniform vec3 color;
+";
+        assert_eq!(annotated, expected);
+    }
+
+    #[test]
+    fn carets_line_up_under_tabs() {
+        let source = BROKEN_SOURCE.replace("    fragColor", "\tfragColor");
+        let annotated = annotate_compile_log(&source, "ERROR: 0:6: 'undefined_color' : undeclared identifier");
+        assert!(annotated.contains("> 6 | \tfragColor = vec4(undefined_color, 1);\n    | \t                 ^^^"), "{}", annotated);
+    }
+
+    #[test]
+    fn unparseable_logs_are_unchanged() {
+        for log in ["Compile failed.", "ERROR: 0:99: 'x' : past the end", "WARNING: 12 lines"] {
+            assert_eq!(annotate_compile_log(BROKEN_SOURCE, log), log);
+        }
+        assert_eq!(log_location("ERROR: 0:27: 'foo' : undeclared identifier"), Some((27, None)));
+        assert_eq!(log_location("0:3(12): error: `x' undeclared"), Some((3, Some(12))));
+        assert_eq!(log_location("ERROR: 10:30 pm"), None);
+    }
+
+    #[test]
+    fn draw_buffers_are_limited() {
+        assert_eq!(
+            draw_buffer_list(2, 4).unwrap(),
+            [WebGl2RenderingContext::COLOR_ATTACHMENT0, WebGl2RenderingContext::COLOR_ATTACHMENT1]);
+        assert!(draw_buffer_list(0, 4).unwrap().is_empty());
+        let err = draw_buffer_list(5, 4).unwrap_err();
+        assert_eq!(err.to_string(), "5 color attachments exceed MAX_DRAW_BUFFERS (4)");
+    }
+
+    #[test]
+    fn framebuffer_status_names_the_problem() {
+        assert!(framebuffer_status(WebGl2RenderingContext::FRAMEBUFFER_COMPLETE).is_ok());
+        assert_eq!(
+            framebuffer_status(WebGl2RenderingContext::FRAMEBUFFER_INCOMPLETE_DIMENSIONS).unwrap_err().to_string(),
+            "Framebuffer is incomplete: its attachments differ in size");
+        assert_eq!(framebuffer_status(0).unwrap_err().to_string(), "Framebuffer is incomplete (status 0x0)");
+    }
+
+    #[test]
+    fn cube_map_debug_colors_are_distinct() {
+        for (i, a) in CubeMap::DEBUG_COLORS.iter().enumerate() {
+            assert!(CubeMap::DEBUG_COLORS[i + 1..].iter().all(|b| a != b));
+        }
+    }
+
+    #[test]
+    fn sampler_defaults_need_no_mipmaps() {
+        let params = SamplerParams::default();
+        assert_eq!((params.min_filter, params.mag_filter), (WebGl2RenderingContext::NEAREST, WebGl2RenderingContext::NEAREST));
+        assert_eq!((params.wrap_s, params.wrap_t), (WebGl2RenderingContext::CLAMP_TO_EDGE, WebGl2RenderingContext::CLAMP_TO_EDGE));
+    }
+
+    #[test]
+    fn texture_size_is_validated() {
+        assert!(validate_texture(4, 2, 16, TextureFormat::Rgba8, None).is_ok());
+        assert!(validate_texture(16, 16, 16, TextureFormat::R8, None).is_ok());
+        assert!(validate_texture(17, 1, 16, TextureFormat::R8, None).is_err());
+        assert!(validate_texture(0, 1, 16, TextureFormat::R8, None).is_err());
+    }
+
+    #[test]
+    fn texture_pixel_length_is_validated() {
+        assert!(validate_texture(3, 2, 16, TextureFormat::Rgb8, Some(&[0; 18])).is_ok());
+        assert!(validate_texture(3, 2, 16, TextureFormat::Rgb8, Some(&[0; 24])).is_err());
+        assert!(validate_texture(3, 2, 16, TextureFormat::R8, Some(&[0; 6])).is_ok());
+    }
+
+    #[test]
+    fn rows_are_flipped_top_down() {
+        let mut pixels = [1, 1, 2, 2, 3, 3];
+        flip_rows(&mut pixels, 2);
+        assert_eq!(pixels, [3, 3, 2, 2, 1, 1]);
+        let mut pixels = [1, 2, 3, 4];
+        flip_rows(&mut pixels, 2);
+        assert_eq!(pixels, [3, 4, 1, 2]);
+        // Zero width rectangles
+        flip_rows(&mut [], 0);
+    }
+
+    #[test]
+    fn frame_deltas_are_non_negative_and_accumulate() {
+        let timestamps = [1000., 1016.5, 1033., 1033., 1030., 1100.];
+        let last = Cell::new(None);
+        let mut total = 0.;
+        for &now in &timestamps {
+            let delta = frame_delta(&last, now);
+            assert!(delta >= 0., "{} at {}", delta, now);
+            total += delta;
+        }
+        assert_eq!(frame_delta(&Cell::new(None), 1000.), 0.);
+        // The backwards step isn't counted again on the way forwards
+        assert!((total - 0.1).abs() < 1e-9, "{}", total);
+        assert_eq!(last.get(), Some(1100.));
+    }
+
+    #[test]
+    fn fps_averages_recent_frames() {
+        let mut times = FrameTimes::default();
+        assert_eq!(times.fps(), 0.);
+        times.push(500.);
+        assert_eq!(times.fps(), 0.);
+        // A long stall that should fall out of the window, then steady 60fps
+        let mut now = 5000.;
+        for i in 0..200 {
+            times.push(now);
+            now += if i % 2 == 0 { 15. } else { 1000. / 30. - 15. };
+        }
+        assert!((times.fps() - 60.).abs() < 0.5, "{}", times.fps());
+        times.clear();
+        assert_eq!(times.fps(), 0.);
+    }
+
+    #[test]
+    fn fixed_timestep_splits_frames_into_steps() {
+        let mut timestep = FixedTimestep { step: 1. / 60., accumulator: 0. };
+        let mut updates = 0;
+        let alpha = timestep.advance(0.1, |dt| {
+            assert_eq!(dt, 1. / 60.);
+            updates += 1;
+        });
+        assert_eq!(updates, 6);
+        assert!(alpha < 1e-6, "{}", alpha);
+
+        updates = 0;
+        let alpha = timestep.advance(0.025, |_| updates += 1);
+        assert_eq!(updates, 1);
+        assert!((alpha - 0.5).abs() < 1e-6, "{}", alpha);
+        // The leftover half step completes on the next frame
+        let alpha = timestep.advance(0.01, |_| updates += 1);
+        assert_eq!(updates, 2);
+        assert!((alpha - 0.1).abs() < 1e-6, "{}", alpha);
+    }
+
+    #[test]
+    fn fixed_timestep_drops_backlog_after_stalls() {
+        let mut timestep = FixedTimestep { step: 1. / 60., accumulator: 0. };
+        let mut updates = 0;
+        let alpha = timestep.advance(5., |_| updates += 1);
+        assert_eq!(updates, FixedTimestep::MAX_STEPS);
+        assert!((0. ..1.).contains(&alpha));
+        timestep.advance(1. / 60., |_| updates += 1);
+        assert_eq!(updates, FixedTimestep::MAX_STEPS + 1);
+    }
+
+    #[test]
+    fn ranges_are_clamped_to_the_length() {
+        assert_eq!(clamp_range(1, 2, 5), 1..3);
+        assert_eq!(clamp_range(3, 4, 5), 3..5);
+        assert_eq!(clamp_range(7, 1, 5), 5..5);
+        assert_eq!(clamp_range(2, usize::MAX, 5), 2..5);
+    }
+
+    #[test]
+    fn element_byte_range_scales_by_element_size() {
+        assert_eq!(element_byte_range::<u8>(3, 4), (3, 4));
+        assert_eq!(element_byte_range::<u16>(3, 4), (6, 8));
+        // `Vertex` is two packed `Position`s and a UV, so 32 bytes
+        assert_eq!(element_byte_range::<crate::Vertex>(2, 5), (64, 160));
+        assert_eq!(element_byte_range::<Matrix4<f32>>(1, 0), (64, 0));
+    }
+
+    #[test]
+    fn integer_attribute_types_are_recognized() {
+        assert!(is_integer_type(WebGl2RenderingContext::INT_VEC4));
+        assert!(is_integer_type(WebGl2RenderingContext::UNSIGNED_INT));
+        assert!(!is_integer_type(WebGl2RenderingContext::FLOAT_VEC4));
+        assert!(!is_integer_type(WebGl2RenderingContext::FLOAT_MAT4));
+    }
+
+    #[test]
+    fn index_type_fits_vertex_count() {
+        assert_eq!(index_type_for(0), WebGl2RenderingContext::UNSIGNED_BYTE);
+        assert_eq!(index_type_for(256), WebGl2RenderingContext::UNSIGNED_BYTE);
+        assert_eq!(index_type_for(257), WebGl2RenderingContext::UNSIGNED_SHORT);
+        assert_eq!(index_type_for(300), WebGl2RenderingContext::UNSIGNED_SHORT);
+        assert_eq!(index_type_for(65536), WebGl2RenderingContext::UNSIGNED_SHORT);
+        assert_eq!(index_type_for(65537), WebGl2RenderingContext::UNSIGNED_INT);
+    }
+
+    #[test]
+    fn backing_size_scales_by_capped_pixel_ratio() {
+        assert_eq!(backing_size(800., 600., 1., None), (800, 600));
+        assert_eq!(backing_size(800., 600., 2., None), (1600, 1200));
+        assert_eq!(backing_size(800., 600., 3., Some(1.5)), (1200, 900));
+        assert_eq!(backing_size(801., 601., 1.25, None), (1001, 751));
+        assert_eq!(backing_size(f64::NAN, -5., 2., None), (0, 0));
+    }
+
+    #[test]
+    fn includes_expand_recursively() {
+        let includes = HashMap::from([
+            ("lighting", "#include \"math\"\nfloat light() { return half(2.0); }"),
+            ("math", "float half(float x) { return x * 0.5; }"),
+        ]);
+        let source = "#version 300 es\n  #include \"lighting\"\nvoid main() {}";
+        assert_eq!(
+            preprocess_includes(source, &includes).unwrap(),
+            "#version 300 es\nfloat half(float x) { return x * 0.5; }\nfloat light() { return half(2.0); }\nvoid main() {}\n"
+        );
+    }
+
+    #[test]
+    fn include_cycles_are_rejected() {
+        let includes = HashMap::from([("self", "#include \"self\"")]);
+        let err = preprocess_includes("#include \"self\"", &includes).unwrap_err();
+        assert_eq!(err, "Include cycle: self -> self");
+
+        let includes = HashMap::from([("a", "#include \"b\""), ("b", "#include \"a\"")]);
+        let err = preprocess_includes("#include \"a\"", &includes).unwrap_err();
+        assert_eq!(err, "Include cycle: a -> b -> a");
+    }
+
+    #[test]
+    fn bad_includes_are_rejected() {
+        let includes = HashMap::from([("a", "")]);
+        assert!(preprocess_includes("#include \"b\"", &includes).is_err());
+        assert!(preprocess_includes("#include a", &includes).is_err());
+        // Repeated, non-nested includes are fine
+        assert!(preprocess_includes("#include \"a\"\n#include \"a\"", &includes).is_ok());
+    }
+
+    #[test]
+    fn uniform_cache_skips_identical_values() {
+        let mut cache = UniformCache::default();
+        let bits = |x: f32| std::iter::once(x.to_bits());
+        assert!(cache.replace("time", bits(1.)));
+        assert!(!cache.replace("time", bits(1.)));
+        assert!(cache.replace("time", bits(2.)));
+        assert!(cache.replace("other", bits(2.)));
+        assert!(!cache.replace("time", bits(2.)));
+        // Compared by bits, so NaN doesn't force a re-upload every time
+        assert!(cache.replace("time", bits(f32::NAN)));
+        assert!(!cache.replace("time", bits(f32::NAN)));
+        // A different length is a different value
+        assert!(cache.replace("time", [0, 0].iter().copied()));
+        assert!(!cache.replace("time", [0, 0].iter().copied()));
+        assert!(cache.replace("time", [0].iter().copied()));
+    }
+
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).norm() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn camera_defaults_to_looking_down_negative_z() {
+        let camera = Camera::new(Vector3::new(0., 1., 0.), 1., 0.1, 100.);
+        assert_close(camera.forward(), -Vector3::z());
+        assert_close(camera.right(), Vector3::x());
+        assert_close(camera.up(), Vector3::y());
+        let expected = Matrix4::new_translation(&Vector3::new(0., -1., 0.));
+        assert!((camera.view_matrix() - expected).norm() < 1e-5);
+    }
+
+    #[test]
+    fn camera_yaw_turns_forward_about_y() {
+        let mut camera = Camera::new(Vector3::zeros(), 1., 0.1, 100.);
+        camera.rotate(std::f32::consts::FRAC_PI_2, 0.);
+        assert_close(camera.forward(), Vector3::x());
+        camera.rotate(std::f32::consts::FRAC_PI_2, 0.);
+        assert_close(camera.forward(), Vector3::z());
+        camera.rotate(-std::f32::consts::FRAC_PI_4 * 3., 0.);
+        assert_close(camera.forward(), Vector3::new(1., 0., -1.).normalize());
+        assert_close(camera.up(), Vector3::y());
+    }
+
+    #[test]
+    fn camera_projection_follows_aspect() {
+        let mut camera = Camera::new(Vector3::zeros(), 1., 0.1, 100.);
+        camera.set_aspect(1600, 900);
+        assert_eq!(camera.projection_matrix(), perspective_matrix(1600, 900, 1., 0.1, 100.));
+        camera.set_aspect(0, 0);
+        assert!(camera.projection_matrix().iter().all(|x| x.is_finite()));
+    }
+
+    #[test]
+    fn camera_pitch_is_clamped() {
+        let mut camera = Camera::new(Vector3::zeros(), 1., 0.1, 100.);
+        camera.rotate(0., 10.);
+        assert_eq!(camera.pitch(), Camera::MAX_PITCH);
+        camera.rotate(0., -20.);
+        assert_eq!(camera.pitch(), -Camera::MAX_PITCH);
+        assert!(camera.view_matrix().iter().all(|x| x.is_finite()));
+    }
+
+    #[test]
+    fn camera_translates_along_local_axes() {
+        let mut camera = Camera::new(Vector3::zeros(), 1., 0.1, 100.);
+        camera.rotate(std::f32::consts::FRAC_PI_2, 0.);
+        // Facing +X after a quarter turn
+        camera.translate_local(Vector3::new(0., 0., 2.));
+        assert_close(camera.position, Vector3::new(2., 0., 0.));
+        camera.translate_local(Vector3::new(1., 0., 0.));
+        assert_close(camera.position, Vector3::new(2., 0., 1.));
+    }
+
+    #[test]
+    fn perspective_uses_viewport_aspect() {
+        let fovy = 90f32.to_radians();
+        assert_eq!(
+            perspective_matrix(1600, 900, fovy, 0.1, 100.),
+            Matrix4::new_perspective(16. / 9., fovy, 0.1, 100.));
+        assert!(perspective_matrix(0, 0, fovy, 0.1, 100.).iter().all(|v| v.is_finite()));
+    }
+
+    #[test]
+    fn frame_uniforms_follow_std140() {
+        use std::mem::{offset_of, size_of};
+        assert_eq!(offset_of!(FrameUniforms, view), 64);
+        assert_eq!(offset_of!(FrameUniforms, light_projection_view), 128);
+        assert_eq!(offset_of!(FrameUniforms, shadow_view), 192);
+        assert_eq!(offset_of!(FrameUniforms, light_pos), 256);
+        assert_eq!(offset_of!(FrameUniforms, camera_pos), 272);
+        assert_eq!(size_of::<FrameUniforms>(), 288);
+    }
+
+    #[test]
+    fn look_at_matches_nalgebra() {
+        use nalgebra::Point3;
+        for (eye, target, up) in [
+            (Vector3::new(0., 0., 5.), Vector3::zeros(), Vector3::y()),
+            (Vector3::new(1., 3., -1.), Vector3::zeros(), Vector3::y()),
+            (Vector3::new(-2., 0.5, 4.), Vector3::new(3., 1., -2.), Vector3::new(0.2, 1., 0.)),
+            (Vector3::new(0., 10., 0.), Vector3::zeros(), Vector3::z()),
+        ] {
+            let expected = Matrix4::look_at_rh(&Point3::from(eye), &Point3::from(target), &up);
+            let actual = look_at(eye, target, up);
+            assert!((actual - expected).norm() < 1e-5, "{} != {}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn orthographic_maps_box_to_clip_cube() {
+        use nalgebra::Vector4;
+        let projection = orthographic_matrix(-4., 2., -1., 3., 0.5, 10.5);
+        let clip = |x: f32, y: f32, z: f32| (projection * Vector4::new(x, y, z, 1.)).xyz();
+        assert_close(clip(-1., 1., -5.5), Vector3::zeros());
+        for (corner, expected) in [
+            ((-4., -1., -0.5), (-1., -1., -1.)),
+            ((2., 3., -10.5), (1., 1., 1.)),
+            ((2., -1., -0.5), (1., -1., -1.)),
+            ((-4., 3., -10.5), (-1., 1., 1.)),
+        ] {
+            assert_close(clip(corner.0, corner.1, corner.2), Vector3::new(expected.0, expected.1, expected.2));
+        }
+        assert_eq!(
+            orthographic_matrix(-4., 2., -1., 3., 0.5, 10.5),
+            Matrix4::new_orthographic(-4., 2., -1., 3., 0.5, 10.5));
+    }
+
+    #[test]
+    fn array_uniforms_are_registered_per_element() {
+        assert_eq!(element_names(String::from("weights[0]"), 3), ["weights", "weights[0]", "weights[1]", "weights[2]"]);
+        assert_eq!(element_names(String::from("uLights[2].color"), 1), ["uLights[2].color"]);
+        assert_eq!(element_names(String::from("model"), 1), ["model"]);
+    }
+
+    #[test]
+    fn light_sets_are_limited_to_the_shader_array() {
+        let mut lights = LightSet::default();
+        let sun = Light::Directional { direction: -Vector3::y(), color: Color::default(), intensity: 1. };
+        for _ in 0..LightSet::MAX_LIGHTS {
+            lights.add(sun).unwrap();
+        }
+        assert!(lights.add(sun).unwrap_err().to_string().contains("at most 8"));
+        assert_eq!(lights.lights().len(), LightSet::MAX_LIGHTS);
+    }
+
+    #[test]
+    fn directional_shadows_fit_the_scene_box() {
+        let (min, max) = (Vector3::new(-5., 0., -5.), Vector3::new(5., 1., 5.));
+        // Including straight down, where `up` can't be +y
+        for direction in [Vector3::new(-1., -3., 1.), Vector3::new(0., -2., 0.)] {
+            let light = DirectionalLight::new(direction);
+            let (view, projection) = light.shadow_matrices(min, max);
+            let mut extent = Vector3::<f32>::zeros();
+            for i in 0..8 {
+                let corner = Vector3::new([min.x, max.x][i & 1], [min.y, max.y][i >> 1 & 1], [min.z, max.z][i >> 2]);
+                let clip = projection.transform_point(&view.transform_point(&corner.into())).coords;
+                assert!(clip.iter().all(|v| v.abs() <= 1. + 1e-5), "{:?} {:?}", direction, clip);
+                extent = extent.sup(&clip.abs());
+            }
+            // Tight on every axis
+            assert_close(extent, Vector3::repeat(1.));
+            assert_close(view.transform_vector(&light.reverse_direction()), Vector3::z());
+        }
+    }
+
+    #[test]
+    fn cascade_splits_blend_log_and_uniform() {
+        assert_eq!(cascade_splits(1., 100., 2, 0.), [1., 50.5, 100.]);
+        let log = cascade_splits(1., 100., 2, 1.);
+        assert!((log[1] - 10.).abs() < 1e-4, "{:?}", log);
+        let mixed = cascade_splits(0.1, 50., 4, 0.75);
+        assert_eq!((mixed[0], mixed[4]), (0.1, 50.));
+        assert!(mixed.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", mixed);
+    }
+
+    #[test]
+    fn frustum_slices_span_their_depths() {
+        let mut camera = Camera::new(Vector3::new(1., 2., 3.), 1., 0.1, 100.);
+        camera.set_rotation(0.5, -0.2);
+        camera.set_aspect(16, 9);
+        let corners = camera.frustum_corners(2., 10.);
+        for (i, corner) in corners.iter().enumerate() {
+            let depth = -camera.view_matrix().transform_point(&(*corner).into()).z;
+            let expected = if i < 4 { 2. } else { 10. };
+            assert!((depth - expected).abs() < 1e-3, "{} {}", i, depth);
+        }
+    }
+
+    #[test]
+    fn cascades_fit_their_slice_but_keep_every_caster() {
+        let (min, max) = (Vector3::new(-5., 0., -5.), Vector3::new(5., 1., 5.));
+        let light = DirectionalLight::new(Vector3::new(-1., -3., 1.));
+        let camera = Camera::new(Vector3::new(0., 1., 4.), 1., 0.1, 100.);
+        let slice = camera.frustum_corners(0.1, 2.);
+        let (view, projection) = light.slice_shadow_matrices(&slice, min, max);
+        let clip = |point: &Vector3<f32>| projection.transform_point(&view.transform_point(&(*point).into())).coords;
+        let mut extent = Vector2::<f32>::zeros();
+        for corner in &slice {
+            let clip = clip(corner);
+            extent = extent.sup(&clip.xy().abs());
+        }
+        assert_close(extent.push(0.), Vector3::new(1., 1., 0.));
+        // Much tighter than the whole box, yet anything in it is in depth range
+        let (_, box_projection) = light.shadow_matrices(min, max);
+        assert!(projection[(0, 0)] > box_projection[(0, 0)] * 2.);
+        assert!(box_corners(min, max).iter().all(|corner| clip(corner).z.abs() <= 1. + 1e-5));
+    }
+
+    #[test]
+    fn point_shadow_faces_look_down_each_axis() {
+        let (near, far) = (0.05, 4.);
+        let position = Vector3::new(1., 2., 3.);
+        let projection = Matrix4::new_perspective(1., std::f32::consts::FRAC_PI_2, near, far);
+        let faces = cube_face_matrices(position, projection);
+        for (matrix, (direction, up)) in faces.iter().zip(PointShadow::FACE_AXES) {
+            let (direction, up) = (Vector3::from(direction), Vector3::from(up));
+            let ahead = matrix.transform_point(&(position + direction * 2.).into()).coords;
+            assert_close(Vector3::new(ahead.x, ahead.y, 0.), Vector3::zeros());
+            // The depth `pointShadowFactor` in main.fsh reconstructs
+            let depth = 2.;
+            let expected = (far + near) / (far - near) - 2. * far * near / ((far - near) * depth);
+            assert!((ahead.z - expected).abs() < 1e-5, "{} != {}", ahead.z, expected);
+            // Up in the face is up in the image
+            let above = matrix.transform_point(&(position + direction * 2. + up * 0.5).into()).coords;
+            assert!(above.y > 0.1, "{:?}", above);
+            // Behind the light is outside the face
+            let behind = matrix.transform_point(&(position - direction).into()).coords;
+            assert!(behind.z.abs() > 1., "{:?}", behind);
+        }
+    }
+
+    #[test]
+    fn throttles_update_every_interval() {
+        let mut throttle = UpdateThrottle::new(3);
+        let ticks: Vec<bool> = (0..7).map(|_| throttle.tick()).collect();
+        assert_eq!(ticks, [true, false, false, true, false, false, true]);
+        throttle.force();
+        assert!(throttle.tick());
+        // 0 means every frame, like 1
+        let mut every = UpdateThrottle::new(0);
+        assert!((0..3).all(|_| every.tick()));
+    }
+
+    fn triangles() -> DrawSpec {
+        DrawSpec {
+            mode: WebGl2RenderingContext::TRIANGLES,
+            index_type: <u16 as IndexType>::GL_TYPE,
+            count: 6,
+            offset: 0,
+            instances: 1,
+        }
+    }
+
+    #[test]
+    fn draw_spec_accepts_valid() {
+        assert!(triangles().validate().is_ok());
+        assert!(DrawSpec { offset: 4, ..triangles() }.validate().is_ok());
+    }
+
+    #[test]
+    fn draw_spec_rejects_invalid() {
+        assert!(DrawSpec { mode: WebGl2RenderingContext::FLOAT, ..triangles() }.validate().is_err());
+        assert!(DrawSpec { index_type: WebGl2RenderingContext::FLOAT, ..triangles() }.validate().is_err());
+        assert!(DrawSpec { offset: 3, ..triangles() }.validate().is_err());
+        assert!(DrawSpec { instances: -1, ..triangles() }.validate().is_err());
+    }
+
+    #[cfg(feature = "gl-debug")]
+    #[test]
+    fn gl_errors_are_named() {
+        assert_eq!(gl_error_name(WebGl2RenderingContext::INVALID_VALUE), "INVALID_VALUE");
+        assert_eq!(gl_error_name(WebGl2RenderingContext::CONTEXT_LOST_WEBGL), "CONTEXT_LOST_WEBGL");
+        assert_eq!(gl_error_name(0x1234), "unknown error");
+    }
 }
@@ -0,0 +1,367 @@
+//! Collections of meshes drawn together in each render pass
+
+use std::any::Any;
+
+use nalgebra::{Matrix3, Matrix4, Vector3};
+use web_sys::{WebGl2RenderingContext, WebGlVertexArrayObject};
+
+use crate::error::WasmGlError;
+use crate::renderer::{no_index_buffer, BindCache, CullMode, DrawSpec, IndexBuffer, RenderState, Shader, VAO};
+
+/// A [`VAO`] with its buffer types erased, so meshes with different vertex
+/// layouts can share a [`Scene`]
+pub trait VertexArray: Any {
+    fn handle(&self) -> &WebGlVertexArrayObject;
+
+    /// How many instances its instance buffer holds, if it has one
+    fn instance_count(&self) -> Option<usize>;
+
+    /// Uploads whichever of its buffers changed, returning whether any did
+    fn update_buffers(&self, ctx: &WebGl2RenderingContext) -> bool;
+
+    /// For downcasting to the `VAO<T>`
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: 'static> VertexArray for VAO<T> {
+    fn handle(&self) -> &WebGlVertexArrayObject {
+        &self.handle
+    }
+
+    fn instance_count(&self) -> Option<usize> {
+        // Only VAOs with named buffers know which buffer is per-instance
+        (self as &dyn Any).downcast_ref::<VAO>()?.instance_count()
+    }
+
+    fn update_buffers(&self, ctx: &WebGl2RenderingContext) -> bool {
+        // Other VAOs' buffers are updated by whoever owns their layout
+        match (self as &dyn Any).downcast_ref::<VAO>() {
+            Some(vao) => vao.update_buffers(ctx),
+            None => false,
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// How a mesh's surface reflects light in `main.fsh`'s Blinn-Phong shading
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Material {
+    pub base_color: Vector3<f32>,
+    /// Color of specular highlights, black for none
+    pub specular_color: Vector3<f32>,
+    /// Specular exponent, higher for smaller and sharper highlights
+    pub shininess: f32,
+    /// Output alpha, only blended when `transparent` is set
+    pub opacity: f32,
+    /// Draws after the opaque meshes, blended and without casting shadows
+    pub transparent: bool,
+    /// `None` for two-sided surfaces, whose back faces `main.fsh` shades
+    /// with the normal flipped
+    pub cull: CullMode,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            base_color: Vector3::new(1., 1., 1.),
+            specular_color: Vector3::zeros(),
+            shininess: 32.,
+            opacity: 1.,
+            transparent: false,
+            cull: CullMode::Back,
+        }
+    }
+}
+
+impl Material {
+    /// Uniforms a shader registers to shade with a material
+    pub const UNIFORMS: [&'static str; 4] = ["baseColor", "specularColor", "shininess", "opacity"];
+
+    /// Uploads whichever of [`Material::UNIFORMS`] `shader` registered, so
+    /// the same material works for passes that ignore it. The shader must be
+    /// enabled.
+    pub fn apply(&self, ctx: &WebGl2RenderingContext, shader: &Shader) {
+        if shader.get_uniform("baseColor").is_some() {
+            shader.set_vec3(ctx, "baseColor", &self.base_color);
+        }
+        if shader.get_uniform("specularColor").is_some() {
+            shader.set_vec3(ctx, "specularColor", &self.specular_color);
+        }
+        if shader.get_uniform("shininess").is_some() {
+            shader.set_f32(ctx, "shininess", self.shininess);
+        }
+        if shader.get_uniform("opacity").is_some() {
+            shader.set_f32(ctx, "opacity", self.opacity);
+        }
+    }
+}
+
+/// An indexed VAO with its own transform
+pub struct Mesh {
+    vao: Box<dyn VertexArray>,
+    // Only kept alive, the element buffer binding is part of the VAO. None
+    // when the VAO owns its index buffer.
+    _indices: Option<IndexBuffer>,
+    pub draw_spec: DrawSpec,
+    pub model: Matrix4<f32>,
+    pub material: Material,
+    /// Center of the mesh's bounding box in model space, which orders
+    /// transparent meshes. Taken from [`VAO::bounds`] by [`Mesh::indexed`],
+    /// otherwise the origin.
+    pub center: Vector3<f32>,
+}
+
+impl Mesh {
+    /// `indices` must have been uploaded with `vao` bound. Draws `instances`
+    /// instances of the triangles, with an identity transform.
+    pub fn new<T: 'static>(vao: VAO<T>, indices: IndexBuffer, instances: i32) -> Mesh {
+        Mesh {
+            draw_spec: DrawSpec {
+                instances,
+                ..indices.draw_spec(WebGl2RenderingContext::TRIANGLES)
+            },
+            vao: Box::new(vao),
+            _indices: Some(indices),
+            model: Matrix4::identity(),
+            material: Material::default(),
+            center: Vector3::zeros(),
+        }
+    }
+
+    /// Draws the triangles of the index buffer set with
+    /// [`VaoBuilder::set_index_buffer`](crate::renderer::VaoBuilder::set_index_buffer),
+    /// which is an error if it wasn't set. There is one instance per element
+    /// of the instance buffer, or a single instance without one.
+    pub fn indexed(vao: VAO) -> Result<Mesh, WasmGlError> {
+        let draw_spec = vao
+            .draw_spec(WebGl2RenderingContext::TRIANGLES)
+            .ok_or_else(no_index_buffer)?;
+        let center = vao.bounds().map_or_else(Vector3::zeros, |(min, max)| (min + max) / 2.);
+        Ok(Mesh {
+            draw_spec,
+            vao: Box::new(vao),
+            _indices: None,
+            model: Matrix4::identity(),
+            material: Material::default(),
+            center,
+        })
+    }
+
+    /// `draw_spec` with the instance count taken from the VAO's instance
+    /// buffer, if it has one, as of now
+    pub fn current_draw_spec(&self) -> DrawSpec {
+        match self.vao.instance_count() {
+            Some(instances) => DrawSpec { instances: instances as i32, ..self.draw_spec },
+            None => self.draw_spec,
+        }
+    }
+
+    /// The VAO this mesh was created with, if it is a `VAO<T>`, for
+    /// updating its buffers
+    pub fn vao_mut<T: 'static>(&mut self) -> Option<&mut VAO<T>> {
+        self.vao.as_any_mut().downcast_mut()
+    }
+}
+
+/// Attribute for per-vertex colors, e.g. from a
+/// [`ColoredVertex`](crate::ColoredVertex), which multiply the material's
+/// base color
+pub const VERTEX_COLOR: &str = "color";
+
+/// Identifies a mesh added to a [`Scene`]. Ids aren't reused after removal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MeshId(u32);
+
+/// Meshes drawn in the order they were added
+#[derive(Default)]
+pub struct Scene {
+    meshes: Vec<(MeshId, Mesh)>,
+    next_id: u32,
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        Scene::default()
+    }
+
+    pub fn add(&mut self, mesh: Mesh) -> MeshId {
+        let id = self.next_id();
+        self.meshes.push((id, mesh));
+        id
+    }
+
+    fn next_id(&mut self) -> MeshId {
+        let id = MeshId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    /// Removes the mesh, returning it (and so its GL resources, which are
+    /// deleted once it is dropped)
+    pub fn remove(&mut self, id: MeshId) -> Option<Mesh> {
+        let index = self.meshes.iter().position(|(mesh_id, _)| *mesh_id == id)?;
+        Some(self.meshes.remove(index).1)
+    }
+
+    pub fn get(&self, id: MeshId) -> Option<&Mesh> {
+        self.meshes.iter().find(|(mesh_id, _)| *mesh_id == id).map(|(_, mesh)| mesh)
+    }
+
+    pub fn get_mut(&mut self, id: MeshId) -> Option<&mut Mesh> {
+        self.meshes.iter_mut().find(|(mesh_id, _)| *mesh_id == id).map(|(_, mesh)| mesh)
+    }
+
+    pub fn len(&self) -> usize {
+        self.meshes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.meshes.is_empty()
+    }
+
+    /// Draws every opaque mesh with `shader`, after enabling it, leaving out
+    /// transparent ones. Per-pass uniforms should already be set; the
+    /// per-mesh `model` uniform is uploaded, and `normalMatrix` and the
+    /// [`Material`] uniforms for whichever of them `shader` registered.
+    /// Meshes without a [`VERTEX_COLOR`] attribute array read white from it
+    /// instead. Changed buffers of meshes' VAOs are uploaded first. Each mesh
+    /// is culled as its material says, and culling is disabled afterwards.
+    pub fn draw_opaque(&self, ctx: &WebGl2RenderingContext, cache: &BindCache, shader: &Shader) {
+        self.draw_opaque_culling(ctx, cache, shader, None);
+    }
+
+    /// Like [`Scene::draw_opaque`], but culling `cull` instead of the
+    /// material's choice for meshes that cull at all, e.g.
+    /// [`CullMode::Front`] for a shadow pass to reduce acne. Two-sided meshes
+    /// are still drawn without culling.
+    pub fn draw_opaque_culling(
+        &self,
+        ctx: &WebGl2RenderingContext,
+        cache: &BindCache,
+        shader: &Shader,
+        cull: Option<CullMode>,
+    ) {
+        enable_for_meshes(ctx, cache, shader);
+        for (_, mesh) in self.meshes.iter().filter(|(_, mesh)| !mesh.material.transparent) {
+            let mesh_cull = match (mesh.material.cull, cull) {
+                (CullMode::None, _) | (_, None) => mesh.material.cull,
+                (_, Some(cull)) => cull,
+            };
+            mesh_cull.apply(ctx);
+            draw_mesh(ctx, cache, shader, mesh);
+        }
+        CullMode::None.apply(ctx);
+    }
+
+    /// [`Scene::draw_opaque`] then [`Scene::draw_transparent`]. Anything
+    /// else opaque, such as a skybox, should instead be drawn between the two.
+    pub fn draw_all(&self, ctx: &WebGl2RenderingContext, cache: &BindCache, shader: &Shader, view: &Matrix4<f32>) {
+        self.draw_opaque(ctx, cache, shader);
+        self.draw_transparent(ctx, cache, shader, view);
+    }
+
+    /// Draws the transparent meshes as [`Scene::draw_opaque`] draws the
+    /// opaque ones, but with [`RenderState::transparent`] and furthest from
+    /// the camera at `view` first, then restores [`RenderState::opaque`]
+    pub fn draw_transparent(
+        &self,
+        ctx: &WebGl2RenderingContext,
+        cache: &BindCache,
+        shader: &Shader,
+        view: &Matrix4<f32>,
+    ) {
+        let transparent = self.back_to_front(view);
+        if transparent.is_empty() {
+            return;
+        }
+        enable_for_meshes(ctx, cache, shader);
+        RenderState::transparent().apply(ctx);
+        for (_, mesh) in transparent {
+            mesh.material.cull.apply(ctx);
+            draw_mesh(ctx, cache, shader, mesh);
+        }
+        CullMode::None.apply(ctx);
+        RenderState::opaque().apply(ctx);
+    }
+
+    /// The order [`Scene::draw_transparent`] draws the transparent meshes in, for
+    /// the camera at `view`
+    pub fn transparent_order(&self, view: &Matrix4<f32>) -> Vec<MeshId> {
+        self.back_to_front(view).into_iter().map(|(id, _)| id).collect()
+    }
+
+    /// The transparent meshes, sorted by decreasing view space depth of their
+    /// centers
+    fn back_to_front(&self, view: &Matrix4<f32>) -> Vec<(MeshId, &Mesh)> {
+        let mut meshes: Vec<(f32, MeshId, &Mesh)> = self
+            .meshes
+            .iter()
+            .filter(|(_, mesh)| mesh.material.transparent)
+            .map(|(id, mesh)| {
+                let center = (view * mesh.model).transform_point(&mesh.center.into());
+                // The camera looks down -z
+                (-center.z, *id, mesh)
+            })
+            .collect();
+        meshes.sort_by(|(a, ..), (b, ..)| b.total_cmp(a));
+        meshes.into_iter().map(|(_, id, mesh)| (id, mesh)).collect()
+    }
+}
+
+fn enable_for_meshes(ctx: &WebGl2RenderingContext, cache: &BindCache, shader: &Shader) {
+    shader.enable_cached(ctx, cache);
+    // The value disabled attribute arrays read is context state rather
+    // than VAO state, so it's set again in case another shader changed it
+    if let Some(color) = shader.get_attr(VERTEX_COLOR) {
+        ctx.vertex_attrib4f(color, 1., 1., 1., 1.);
+    }
+}
+
+fn draw_mesh(ctx: &WebGl2RenderingContext, cache: &BindCache, shader: &Shader, mesh: &Mesh) {
+    shader.set_mat4(ctx, "model", &mesh.model);
+    if shader.get_uniform("normalMatrix").is_some() {
+        shader.set_mat3(ctx, "normalMatrix", &normal_matrix(&mesh.model));
+    }
+    mesh.material.apply(ctx, shader);
+    mesh.vao.update_buffers(ctx);
+    cache.bind_vertex_array(ctx, Some(mesh.vao.handle()));
+    mesh.current_draw_spec().draw_bound(ctx);
+}
+
+/// Inverse transpose of the upper 3x3 of `model`, for transforming normals
+pub fn normal_matrix(model: &Matrix4<f32>) -> Matrix3<f32> {
+    model
+        .fixed_view::<3, 3>(0, 0)
+        .try_inverse()
+        .unwrap_or_else(Matrix3::identity)
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normal_matrix_undoes_non_uniform_scale() {
+        let model = Matrix4::new_nonuniform_scaling(&Vector3::new(2., 1., 1.));
+        let normal = normal_matrix(&model) * Vector3::new(1., 1., 0.);
+        assert_eq!(normal, Vector3::new(0.5, 1., 0.));
+        assert_eq!(normal_matrix(&Matrix4::zeros()), Matrix3::identity());
+    }
+
+    #[test]
+    fn ids_are_unique_and_not_reused() {
+        let mut scene = Scene::new();
+        let ids: Vec<MeshId> = (0..4).map(|_| scene.next_id()).collect();
+        for (i, id) in ids.iter().enumerate() {
+            assert!(!ids[..i].contains(id), "{:?} was handed out twice", id);
+        }
+        // Removing a mesh doesn't free its id for the next one
+        assert!(scene.remove(ids[3]).is_none());
+        assert!(!ids.contains(&scene.next_id()));
+        assert!(scene.get(ids[0]).is_none());
+    }
+}
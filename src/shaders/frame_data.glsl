@@ -0,0 +1,9 @@
+// Shared by every pass, see `FrameUniforms`
+layout(std140) uniform FrameData {
+	mat4 projection;
+	mat4 view;
+	mat4 lightProjectionView;
+	mat4 shadowView;
+	vec3 lightPos;
+	vec3 cameraPos;
+};
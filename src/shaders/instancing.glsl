@@ -0,0 +1,22 @@
+uniform mat4 model;
+uniform float uTime;
+uniform vec2 windDirection;
+uniform float windStrength;
+in vec3 pos;
+in mat4 instanceModel;
+
+// World space sway of a vertex `height` above its mesh's origin. Two sine
+// octaves make the gusts irregular, and the phase comes from the instance's
+// position so neighbouring instances don't move in lockstep.
+vec3 windOffset(float height, vec3 instanceOrigin) {
+	float phase = dot(instanceOrigin.xz, vec2(0.8, 1.3));
+	float gust = sin(uTime * 1.7 + phase) + 0.35 * sin(uTime * 4.3 + phase * 2.1);
+	return vec3(windDirection.x, 0, windDirection.y) * windStrength * gust * max(height, 0.);
+}
+
+// World space position of this vertex for the current instance
+vec4 instancePosition() {
+	vec4 worldPos = instanceModel * model * vec4(pos, 1);
+	worldPos.xyz += windOffset(pos.y, instanceModel[3].xyz);
+	return worldPos;
+}
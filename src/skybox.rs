@@ -0,0 +1,109 @@
+//! Cube mapped background drawn behind all other geometry
+
+use nalgebra::Matrix4;
+use web_sys::WebGl2RenderingContext;
+
+use crate::error::WasmGlError;
+use crate::renderer::{BindCache, CubeMap, OrThrow, Shader, VaoBuilder, VAO};
+use crate::Position;
+
+/// Corner `i` of the `[-1, 1]` cube has bit 0, 1 and 2 set for +X, +Y and
+/// +Z respectively
+fn cube_corners() -> Vec<Position> {
+    (0..8)
+        .map(|i| {
+            let coord = |bit: u32| if i & (1 << bit) != 0 { 1. } else { -1. };
+            Position { x: coord(0), y: coord(1), z: coord(2) }
+        })
+        .collect()
+}
+
+/// Two triangles per face, counter-clockwise when seen from inside the cube
+const CUBE_INDICES: [u32; 36] = [
+    1, 7, 3, 1, 5, 7, // +X
+    0, 2, 6, 0, 6, 4, // -X
+    2, 3, 7, 2, 7, 6, // +Y
+    0, 5, 1, 0, 4, 5, // -Y
+    4, 7, 5, 4, 6, 7, // +Z
+    0, 1, 3, 0, 3, 2, // -Z
+];
+
+pub struct Skybox {
+    shader: Shader,
+    vao: VAO,
+    pub cube_map: CubeMap,
+}
+
+impl Skybox {
+    pub fn new(context: &WebGl2RenderingContext, cube_map: CubeMap) -> Result<Skybox, WasmGlError> {
+        let shader = Shader::new(
+            context,
+            include_str!("./shaders/skybox.vsh"),
+            include_str!("./shaders/skybox.fsh"),
+            &["projection", "view", "skybox"],
+            &["pos"],
+            None)?;
+        let vao = VaoBuilder::new(context)?
+            .add_vertex_buffer("corners", cube_corners(), WebGl2RenderingContext::STATIC_DRAW)?
+            .set_index_buffer(&CUBE_INDICES, WebGl2RenderingContext::STATIC_DRAW)?
+            .build();
+        let corners = vao.buffer::<Position>("corners").unwrap();
+        VBO_bind!(corners, context, shader.find_attr("pos"), Position, 3, WebGl2RenderingContext::FLOAT);
+        Ok(Skybox { shader, vao, cube_map })
+    }
+
+    /// Draws the sky at the far plane, so it should come after the opaque
+    /// geometry to only shade uncovered pixels. Only the rotation of `view`
+    /// is used. Leaves the cube map bound to texture unit 0.
+    pub fn draw(
+        &self,
+        context: &WebGl2RenderingContext,
+        cache: &BindCache,
+        projection: &Matrix4<f32>,
+        view: &Matrix4<f32>,
+    ) {
+        self.shader.enable_cached(context, cache);
+        self.shader.set_mat4(context, "projection", projection);
+        self.shader.set_mat4(context, "view", view);
+        self.cube_map.bind(context, 0);
+        self.shader.set_texture(context, "skybox", 0);
+        // The sky's depth is exactly 1, which LESS would reject against the
+        // cleared depth buffer
+        context.depth_func(WebGl2RenderingContext::LEQUAL);
+        // Can't fail, the index buffer was set in `new`
+        self.vao.draw_elements_cached(context, cache, WebGl2RenderingContext::TRIANGLES).or_throw();
+        context.depth_func(WebGl2RenderingContext::LESS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cube_faces_point_inward() {
+        let corners = cube_corners();
+        for tri in CUBE_INDICES.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| corners[tri[i] as usize]);
+            let normal = (b - a).cross(&(c - a));
+            let centroid = (a + b + c) * (1. / 3.);
+            assert!(normal.dot(&centroid) < 0., "{:?} faces outward", tri);
+        }
+    }
+
+    #[test]
+    fn cube_covers_every_face_once() {
+        let corners = cube_corners();
+        let mut faces: Vec<_> = CUBE_INDICES
+            .chunks_exact(6)
+            .map(|quad| {
+                let center = quad.iter().fold(Position::default(), |sum, &i| sum + corners[i as usize]);
+                let center = center * (1. / 6.);
+                (center.x.round() as i32, center.y.round() as i32, center.z.round() as i32)
+            })
+            .collect();
+        faces.sort();
+        faces.dedup();
+        assert_eq!(faces.len(), 6);
+    }
+}
@@ -0,0 +1,275 @@
+//! Per-frame counts of the work submitted to GL, for performance work
+
+use std::cell::RefCell;
+
+use wasm_bindgen::JsValue;
+use web_sys::WebGl2RenderingContext;
+
+thread_local! {
+    static COLLECTORS: RefCell<Collectors<WebGl2RenderingContext>> =
+        const { RefCell::new(Collectors::new()) };
+}
+
+/// What the renderer submitted over one frame. Only work going through this
+/// crate's wrappers is counted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameCounts {
+    pub draw_calls: u32,
+    /// Points, lines or triangles, summed over every instance
+    pub primitives: u64,
+    /// Bytes uploaded by [`VBO`](crate::renderer::VBO)s
+    pub upload_bytes: u64,
+    /// Textures bound to units for sampling
+    pub texture_binds: u32,
+    /// Programs made current
+    pub shader_switches: u32,
+}
+
+impl FrameCounts {
+    const ZERO: FrameCounts = FrameCounts {
+        draw_calls: 0,
+        primitives: 0,
+        upload_bytes: 0,
+        texture_binds: 0,
+        shader_switches: 0,
+    };
+
+    fn record_draw(&mut self, mode: u32, count: i32, instances: i32) {
+        self.draw_calls += 1;
+        self.primitives += primitive_count(mode, count.max(0) as u32) as u64 * instances.max(0) as u64;
+    }
+
+    /// A `{ name: count }` object, for `console.table`
+    fn to_js(self) -> JsValue {
+        let object = js_sys::Object::new();
+        for (name, value) in [
+            ("draw calls", self.draw_calls as f64),
+            ("primitives", self.primitives as f64),
+            ("upload bytes", self.upload_bytes as f64),
+            ("texture binds", self.texture_binds as f64),
+            ("shader switches", self.shader_switches as f64),
+        ] {
+            // Only fails on frozen objects and proxies
+            let _ = js_sys::Reflect::set(&object, &name.into(), &value.into());
+        }
+        object.into()
+    }
+}
+
+/// The counts of the current frame of every [`FrameStats`], each for its own
+/// context. Work on other contexts isn't counted.
+struct Collectors<C> {
+    collectors: Vec<Collector<C>>,
+    next_id: u64,
+}
+
+struct Collector<C> {
+    id: u64,
+    context: C,
+    counts: FrameCounts,
+}
+
+impl<C: PartialEq> Collectors<C> {
+    const fn new() -> Self {
+        Collectors { collectors: Vec::new(), next_id: 0 }
+    }
+
+    /// Starts counting for `context` from zero, returning the id to take the
+    /// counts with. Each user counts separately, so several can collect for
+    /// the same context.
+    fn add_user(&mut self, context: C) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.collectors.push(Collector { id, context, counts: FrameCounts::ZERO });
+        id
+    }
+
+    fn remove_user(&mut self, id: u64) {
+        self.collectors.retain(|collector| collector.id != id);
+    }
+
+    /// Applies `update` to the counts of every user collecting for `context`
+    fn update(&mut self, context: &C, update: impl Fn(&mut FrameCounts)) {
+        for collector in self.collectors.iter_mut().filter(|collector| collector.context == *context) {
+            update(&mut collector.counts);
+        }
+    }
+
+    /// The counts so far for user `id`, resetting them
+    fn take(&mut self, id: u64) -> FrameCounts {
+        self.collectors
+            .iter_mut()
+            .find(|collector| collector.id == id)
+            .map_or(FrameCounts::ZERO, |collector| std::mem::replace(&mut collector.counts, FrameCounts::ZERO))
+    }
+}
+
+/// Collects [`FrameCounts`] for one context, one frame at a time. Counting
+/// runs while the `FrameStats` is alive; [`FrameStats::end_frame`] should be
+/// called once at the end of each render loop callback to close the frame
+/// and start the next. Several `FrameStats` on the same context each count
+/// every frame in full.
+#[derive(Debug)]
+pub struct FrameStats {
+    /// Which counts in `COLLECTORS` are ours
+    id: u64,
+    last_frame: FrameCounts,
+    frames: u64,
+    /// Logs the last frame's counts with `console.table` every this many
+    /// frames
+    pub log_interval: Option<u32>,
+}
+
+impl FrameStats {
+    /// Starts counting the work submitted to `context` from zero, so work
+    /// done while setting up isn't counted towards the first frame
+    pub fn new(context: &WebGl2RenderingContext) -> FrameStats {
+        let id = COLLECTORS.with(|collectors| collectors.borrow_mut().add_user(context.clone()));
+        FrameStats { id, last_frame: FrameCounts::ZERO, frames: 0, log_interval: None }
+    }
+
+    /// The counts of the last frame closed by [`FrameStats::end_frame`]
+    pub fn last_frame(&self) -> &FrameCounts {
+        &self.last_frame
+    }
+
+    /// Frames closed so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Takes the counts accumulated since the last call as the last frame,
+    /// resetting them for the next
+    pub fn end_frame(&mut self) {
+        self.last_frame = COLLECTORS.with(|collectors| collectors.borrow_mut().take(self.id));
+        self.frames += 1;
+        if log_due(self.frames, self.log_interval) {
+            web_sys::console::table_1(&self.last_frame.to_js());
+        }
+    }
+}
+
+impl Drop for FrameStats {
+    fn drop(&mut self) {
+        // Ignored if the thread local is already gone at thread exit
+        let _ = COLLECTORS.try_with(|collectors| collectors.borrow_mut().remove_user(self.id));
+    }
+}
+
+/// Whether frame number `frames` is logged every `interval` frames. An
+/// interval of 0 logs nothing.
+// `u64::is_multiple_of` needs Rust 1.87
+#[allow(clippy::manual_is_multiple_of)]
+fn log_due(frames: u64, interval: Option<u32>) -> bool {
+    match interval {
+        Some(interval) if interval > 0 => frames % interval as u64 == 0,
+        _ => false,
+    }
+}
+
+fn update_counts(ctx: &WebGl2RenderingContext, update: impl Fn(&mut FrameCounts)) {
+    COLLECTORS.with(|collectors| collectors.borrow_mut().update(ctx, update));
+}
+
+/// How many primitives `count` vertices (or indices) make in `mode`
+pub fn primitive_count(mode: u32, count: u32) -> u32 {
+    match mode {
+        WebGl2RenderingContext::TRIANGLES => count / 3,
+        WebGl2RenderingContext::TRIANGLE_STRIP | WebGl2RenderingContext::TRIANGLE_FAN => count.saturating_sub(2),
+        WebGl2RenderingContext::LINES => count / 2,
+        WebGl2RenderingContext::LINE_STRIP => count.saturating_sub(1),
+        // A loop of one vertex draws nothing
+        WebGl2RenderingContext::LINE_LOOP if count > 1 => count,
+        WebGl2RenderingContext::POINTS => count,
+        _ => 0,
+    }
+}
+
+/// Called after every draw call, with its vertex (or index) and instance
+/// counts
+pub(crate) fn record_draw(ctx: &WebGl2RenderingContext, mode: u32, count: i32, instances: i32) {
+    update_counts(ctx, |counts| counts.record_draw(mode, count, instances));
+}
+
+pub(crate) fn record_upload(ctx: &WebGl2RenderingContext, bytes: usize) {
+    update_counts(ctx, |counts| counts.upload_bytes += bytes as u64);
+}
+
+pub(crate) fn record_texture_bind(ctx: &WebGl2RenderingContext) {
+    update_counts(ctx, |counts| counts.texture_binds += 1);
+}
+
+pub(crate) fn record_shader_switch(ctx: &WebGl2RenderingContext) {
+    update_counts(ctx, |counts| counts.shader_switches += 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primitives_follow_the_draw_mode() {
+        assert_eq!(primitive_count(WebGl2RenderingContext::TRIANGLES, 7), 2);
+        assert_eq!(primitive_count(WebGl2RenderingContext::TRIANGLE_STRIP, 6), 4);
+        assert_eq!(primitive_count(WebGl2RenderingContext::TRIANGLE_FAN, 1), 0);
+        assert_eq!(primitive_count(WebGl2RenderingContext::LINES, 5), 2);
+        assert_eq!(primitive_count(WebGl2RenderingContext::LINE_STRIP, 4), 3);
+        assert_eq!(primitive_count(WebGl2RenderingContext::LINE_LOOP, 4), 4);
+        assert_eq!(primitive_count(WebGl2RenderingContext::LINE_LOOP, 1), 0);
+        assert_eq!(primitive_count(WebGl2RenderingContext::POINTS, 9), 9);
+    }
+
+    #[test]
+    fn counts_are_kept_per_context() {
+        let mut collectors = Collectors::new();
+        collectors.update(&1, |counts| counts.texture_binds += 1);
+        let first = collectors.add_user(1);
+        let second = collectors.add_user(2);
+        collectors.update(&1, |counts| counts.record_draw(WebGl2RenderingContext::TRIANGLES, 6, 3));
+        collectors.update(&1, |counts| counts.record_draw(WebGl2RenderingContext::LINES, 4, 1));
+        collectors.update(&2, |counts| counts.upload_bytes += 64);
+        assert_eq!(collectors.take(first), FrameCounts { draw_calls: 2, primitives: 8, ..FrameCounts::ZERO });
+        assert_eq!(collectors.take(first), FrameCounts::ZERO);
+        assert_eq!(collectors.take(second).upload_bytes, 64);
+        collectors.remove_user(second);
+        collectors.update(&2, |counts| counts.upload_bytes += 64);
+        assert_eq!(collectors.take(second), FrameCounts::ZERO);
+    }
+
+    #[test]
+    fn users_of_one_context_count_separately() {
+        let mut collectors = Collectors::new();
+        let first = collectors.add_user(1);
+        collectors.update(&1, |counts| counts.shader_switches += 1);
+        // A second user starts from zero without resetting the first
+        let second = collectors.add_user(1);
+        collectors.update(&1, |counts| counts.texture_binds += 1);
+        assert_eq!(collectors.take(first), FrameCounts { shader_switches: 1, texture_binds: 1, ..FrameCounts::ZERO });
+        assert_eq!(collectors.take(second), FrameCounts { texture_binds: 1, ..FrameCounts::ZERO });
+        // Taking one user's frame leaves the other's alone
+        collectors.update(&1, |counts| counts.draw_calls += 1);
+        assert_eq!(collectors.take(first).draw_calls, 1);
+        collectors.update(&1, |counts| counts.draw_calls += 1);
+        assert_eq!(collectors.take(second).draw_calls, 2);
+    }
+
+    #[test]
+    fn counting_stops_after_the_last_user() {
+        let mut collectors = Collectors::new();
+        let first = collectors.add_user(1);
+        let second = collectors.add_user(1);
+        collectors.remove_user(first);
+        collectors.update(&1, |counts| counts.shader_switches += 1);
+        assert_eq!(collectors.take(second).shader_switches, 1);
+        collectors.remove_user(second);
+        assert!(collectors.collectors.is_empty());
+    }
+
+    #[test]
+    fn logs_every_interval_frames() {
+        assert!(log_due(6, Some(3)));
+        assert!(!log_due(7, Some(3)));
+        assert!(!log_due(6, None));
+        assert!(!log_due(6, Some(0)));
+    }
+}
@@ -3,11 +3,1279 @@
 #![cfg(target_arch = "wasm32")]
 
 extern crate wasm_bindgen_test;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
 use wasm_bindgen_test::*;
+use nalgebra::{Matrix4, Vector3};
+use web_sys::{Event, HtmlCanvasElement, HtmlImageElement, KeyboardEvent, KeyboardEventInit, WebGl2RenderingContext, WebGlProgram};
+
+use wasmgl::error::WasmGlError;
+use wasmgl::input::InputState;
+use wasmgl::postprocess::{DebugQuad, PostProcessPass, ScreenCorner};
+use wasmgl::{init, init_with_id, Color, Position, Vertex};
+use wasmgl::renderer::{
+    buffer_uploads, live_gl_objects, read_pixels, render_loop, BindCache, Camera, CascadedShadowMap, CubeMap, CullMode,
+    DirectionalLight, Framebuffer, Light, LightSet, NamedBuffers, PointShadow, RenderState, Shader, ShadowFilter,
+    ShadowMap, Std140, Texture2D, TextureFormat, UniformBuffer, UpdateThrottle, VaoBuilder, TIME_UNIFORM, VBO,
+};
+use wasmgl::scene::{Material, Mesh, Scene, VERTEX_COLOR};
+use wasmgl::stats::{FrameCounts, FrameStats};
 
 wasm_bindgen_test_configure!(run_in_browser);
 
+const VERTEX_SRC: &str = r##"#version 300 es
+in vec3 pos;
+uniform mat4 projection;
+
+void main() {
+	gl_Position = projection * vec4(pos, 1);
+}
+"##;
+
+const FRAGMENT_SRC: &str = r##"#version 300 es
+precision highp float;
+uniform vec3 color;
+out vec4 outColor;
+
+void main() {
+	outColor = vec4(color, 1);
+}
+"##;
+
+fn context() -> WebGl2RenderingContext {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let canvas: HtmlCanvasElement = document.create_element("canvas").unwrap().dyn_into().unwrap();
+    canvas
+        .get_context("webgl2")
+        .unwrap()
+        .unwrap()
+        .dyn_into::<WebGl2RenderingContext>()
+        .unwrap()
+}
+
+/// Resolves on the next animation frame
+async fn next_frame() {
+    let frame = js_sys::Promise::new(&mut |resolve, _| {
+        web_sys::window().unwrap().request_animation_frame(&resolve).unwrap();
+    });
+    JsFuture::from(frame).await.unwrap();
+}
+
 #[wasm_bindgen_test]
 fn pass() {
     assert_eq!(1 + 1, 2);
 }
+
+#[wasm_bindgen_test]
+fn shader_compiles() {
+    let ctx = context();
+    let shader = Shader::new(&ctx, VERTEX_SRC, FRAGMENT_SRC, &["projection", "color"], &["pos"], None);
+    assert!(shader.is_ok());
+}
+
+#[wasm_bindgen_test]
+fn soft_particle_shader_compiles_with_every_input() {
+    let ctx = context();
+    let shader = Shader::soft_particles(&ctx, None).unwrap();
+    for uniform in ["projection", "view", "sceneDepth", "viewportSize", "near", "far", "softness"] {
+        assert!(shader.get_uniform(uniform).is_some(), "{}", uniform);
+    }
+    for attribute in ["corner", "center", "size", "color"] {
+        assert!(shader.get_attr(attribute).is_some(), "{}", attribute);
+    }
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn broken_fragment_shader_is_err() {
+    let ctx = context();
+    let broken = FRAGMENT_SRC.replace("vec4(color, 1)", "vec4(undefined_color, 1)");
+    let err = Shader::new(&ctx, VERTEX_SRC, &broken, &[], &["pos"], None)
+        .err()
+        .expect("broken shader should not compile");
+    match err {
+        WasmGlError::ShaderCompile { stage, log } => {
+            assert_eq!(stage, "fragment");
+            assert!(log.contains("undefined_color"), "unexpected log: {}", log);
+        }
+        err => panic!("expected a compile error, got {}", err),
+    }
+}
+
+#[wasm_bindgen_test]
+fn bind_cache_skips_repeated_binds() {
+    let ctx = context();
+    let cache = BindCache::new();
+    let a = ctx.create_program().unwrap();
+    let b = ctx.create_program().unwrap();
+    assert!(cache.use_program(&ctx, Some(&a)));
+    assert!(!cache.use_program(&ctx, Some(&a)));
+    assert!(cache.use_program(&ctx, Some(&b)));
+    assert!(cache.use_program(&ctx, None));
+    assert!(!cache.use_program(&ctx, None));
+    cache.invalidate();
+    assert!(cache.use_program(&ctx, None));
+}
+
+#[wasm_bindgen_test]
+fn auto_discovers_declared_names() {
+    let ctx = context();
+    let shader = Shader::new_auto(&ctx, VERTEX_SRC, FRAGMENT_SRC, None).unwrap();
+    let mut uniforms: Vec<&str> = shader.uniform_names().collect();
+    uniforms.sort_unstable();
+    assert_eq!(uniforms, ["color", "projection"]);
+    assert_eq!(shader.attribute_names().collect::<Vec<_>>(), ["pos"]);
+}
+
+#[wasm_bindgen_test]
+fn lookups_hit_and_miss() {
+    let ctx = context();
+    let shader = Shader::new(&ctx, VERTEX_SRC, FRAGMENT_SRC, &["projection", "color"], &["pos"], None).unwrap();
+    assert!(shader.get_uniform("projection").is_some());
+    assert!(shader.get_uniform("reverseLightDir").is_none());
+    assert_eq!(shader.get_attr("pos"), Some(shader.find_attr("pos")));
+    assert_eq!(shader.get_attr("normal"), None);
+}
+
+#[wasm_bindgen_test]
+#[should_panic(expected = "Uniform `reverseLightDir` was not found")]
+fn find_uniform_names_missing_uniform() {
+    let ctx = context();
+    let shader = Shader::new(&ctx, VERTEX_SRC, FRAGMENT_SRC, &[], &[], None).unwrap();
+    shader.find_uniform("reverseLightDir");
+}
+
+#[wasm_bindgen_test]
+fn set_mat4_uploads_column_major() {
+    let ctx = context();
+    let shader = Shader::new(&ctx, VERTEX_SRC, FRAGMENT_SRC, &["projection", "color"], &["pos"], None).unwrap();
+    shader.enable(&ctx);
+    // Translation lives in the last column, i.e. elements 12..15
+    let matrix = Matrix4::new_translation(&Vector3::new(1., 2., 3.));
+    shader.set_mat4(&ctx, "projection", &matrix);
+    let uploaded = js_sys::Float32Array::from(ctx.get_uniform(shader.program(), shader.find_uniform("projection")));
+    assert_eq!(uploaded.to_vec(), matrix.as_slice());
+    assert_eq!(&uploaded.to_vec()[12..15], &[1., 2., 3.]);
+
+    shader.set_vec3(&ctx, "color", &Vector3::new(0.25, 0.5, 0.75));
+    let uploaded = js_sys::Float32Array::from(ctx.get_uniform(shader.program(), shader.find_uniform("color")));
+    assert_eq!(uploaded.to_vec(), [0.25, 0.5, 0.75]);
+}
+
+#[wasm_bindgen_test]
+fn cached_setter_uploads_once() {
+    let ctx = context();
+    let mut shader = Shader::new(&ctx, VERTEX_SRC, FRAGMENT_SRC, &["projection", "color"], &["pos"], None).unwrap();
+    shader.enable(&ctx);
+    let color = Vector3::new(0.25, 0.5, 0.75);
+    assert!(shader.set_vec3_cached(&ctx, "color", &color));
+    assert!(!shader.set_vec3_cached(&ctx, "color", &color));
+    assert!(shader.set_vec3_cached(&ctx, "color", &Vector3::zeros()));
+    shader.invalidate_uniforms();
+    assert!(shader.set_vec3_cached(&ctx, "color", &Vector3::zeros()));
+}
+
+#[wasm_bindgen_test]
+fn set_texture_uploads_unit_index() {
+    let ctx = context();
+    let fragment = r##"#version 300 es
+precision highp float;
+uniform sampler2D albedo;
+out vec4 outColor;
+
+void main() {
+	outColor = texture(albedo, vec2(0.5));
+}
+"##;
+    let shader = Shader::new(&ctx, VERTEX_SRC, fragment, &["projection", "albedo"], &["pos"], None).unwrap();
+    shader.enable(&ctx);
+    shader.set_texture(&ctx, "albedo", 3);
+    let unit = ctx.get_uniform(shader.program(), shader.find_uniform("albedo"));
+    assert_eq!(unit.as_f64(), Some(3.));
+}
+
+#[wasm_bindgen_test]
+fn dropping_shader_deletes_program() {
+    let ctx = context();
+    let programs: Vec<_> = (0..32)
+        .map(|_| {
+            let shader = Shader::new(&ctx, VERTEX_SRC, FRAGMENT_SRC, &["projection", "color"], &["pos"], None).unwrap();
+            assert!(ctx.is_program(Some(shader.program())));
+            shader.program().clone()
+        })
+        .collect();
+    assert!(programs.iter().all(|program| !ctx.is_program(Some(program))));
+}
+
+#[wasm_bindgen_test]
+fn failed_reload_keeps_previous_program() {
+    let ctx = context();
+    let mut shader = Shader::new(&ctx, VERTEX_SRC, FRAGMENT_SRC, &["projection", "color"], &["pos"], None).unwrap();
+    let original = shader.program().clone();
+
+    let broken = FRAGMENT_SRC.replace("vec4(color, 1)", "vec4(undefined_color, 1)");
+    assert!(shader.reload(&ctx, VERTEX_SRC, &broken).is_err());
+    assert_eq!(shader.program(), &original);
+    assert!(ctx.is_program(Some(&original)));
+    shader.enable(&ctx);
+    shader.set_vec3(&ctx, "color", &Vector3::new(1., 0., 0.));
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+
+    let tinted = FRAGMENT_SRC.replace("vec4(color, 1)", "vec4(color * 0.5, 1)");
+    let pos = shader.find_attr("pos");
+    shader.reload(&ctx, VERTEX_SRC, &tinted).unwrap();
+    assert_ne!(shader.program(), &original);
+    assert_eq!(shader.find_attr("pos"), pos);
+    assert!(shader.get_uniform("color").is_some());
+}
+
+#[wasm_bindgen_test]
+fn texture_from_unaligned_rgb_pixels() {
+    let ctx = context();
+    let pixels = [255u8; 3 * 3 * 2];
+    let texture = Texture2D::from_pixels(&ctx, 3, 2, TextureFormat::Rgb8, &pixels)
+        .unwrap()
+        .wrap(&ctx, WebGl2RenderingContext::REPEAT, WebGl2RenderingContext::REPEAT);
+    texture.bind(&ctx, 1);
+    assert_eq!((texture.width(), texture.height()), (3, 2));
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+    assert!(Texture2D::from_pixels(&ctx, 1 << 20, 1, TextureFormat::R8, &[]).is_err());
+}
+
+#[wasm_bindgen_test]
+fn texture_builder_defaults_to_nearest_and_clamp() {
+    let ctx = context();
+    let texture = Texture2D::new(&ctx).format(TextureFormat::R8).size(5, 3).build().unwrap();
+    texture.bind(&ctx, 0);
+    let parameter = |name| ctx.get_tex_parameter(WebGl2RenderingContext::TEXTURE_2D, name).as_f64().unwrap() as u32;
+    assert_eq!(parameter(WebGl2RenderingContext::TEXTURE_MIN_FILTER), WebGl2RenderingContext::NEAREST);
+    assert_eq!(parameter(WebGl2RenderingContext::TEXTURE_MAG_FILTER), WebGl2RenderingContext::NEAREST);
+    assert_eq!(parameter(WebGl2RenderingContext::TEXTURE_WRAP_S), WebGl2RenderingContext::CLAMP_TO_EDGE);
+    assert_eq!(parameter(WebGl2RenderingContext::TEXTURE_WRAP_T), WebGl2RenderingContext::CLAMP_TO_EDGE);
+    assert_eq!((texture.width(), texture.height(), texture.format()), (5, 3, TextureFormat::R8));
+
+    let repeating = Texture2D::new(&ctx).size(2, 2).wrap(WebGl2RenderingContext::REPEAT, WebGl2RenderingContext::REPEAT).build().unwrap();
+    repeating.bind(&ctx, 0);
+    assert_eq!(parameter(WebGl2RenderingContext::TEXTURE_WRAP_S), WebGl2RenderingContext::REPEAT);
+    assert!(Texture2D::new(&ctx).build().is_err());
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+const PNG_1X1: &str = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNk+M9QDwADhgGAWjR9awAAAABJRU5ErkJggg==";
+
+#[wasm_bindgen_test]
+async fn texture_from_image_requires_it_to_be_loaded() {
+    let ctx = context();
+    let image = HtmlImageElement::new().unwrap();
+    assert!(Texture2D::from_image(&ctx, &image, false).is_err());
+
+    image.set_src(PNG_1X1);
+    JsFuture::from(image.decode()).await.unwrap();
+    let texture = Texture2D::from_image(&ctx, &image, false).unwrap();
+    assert_eq!((texture.width(), texture.height(), texture.format()), (1, 1, TextureFormat::Rgba8));
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+async fn texture_from_url_loads_and_reports_failures() {
+    let ctx = context();
+    let texture = Texture2D::from_url(&ctx, PNG_1X1, true).await.unwrap();
+    assert_eq!((texture.width(), texture.height()), (1, 1));
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+    assert!(Texture2D::from_url(&ctx, "data:image/png;base64,AAAA", false).await.is_err());
+}
+
+#[wasm_bindgen_test]
+fn vbo_update_uses_its_access_type() {
+    let ctx = context();
+    for usage in [WebGl2RenderingContext::STATIC_DRAW, WebGl2RenderingContext::DYNAMIC_DRAW] {
+        let vbo = VBO::new(&ctx, Some(vec![0f32; 6]), WebGl2RenderingContext::ARRAY_BUFFER, usage).unwrap();
+        vbo.update(&ctx);
+        assert_eq!(vbo.access_type(), usage);
+        let uploaded = ctx.get_buffer_parameter(WebGl2RenderingContext::ARRAY_BUFFER, WebGl2RenderingContext::BUFFER_USAGE);
+        assert_eq!(uploaded.as_f64(), Some(usage as f64));
+    }
+}
+
+#[wasm_bindgen_test]
+fn clean_vbo_is_not_reuploaded() {
+    let ctx = context();
+    let mut vbo = VBO::new(&ctx, Some(vec![0f32; 6]), WebGl2RenderingContext::ARRAY_BUFFER, WebGl2RenderingContext::STATIC_DRAW).unwrap();
+    assert!(vbo.update(&ctx));
+    assert!(!vbo.update(&ctx));
+    vbo.buffer_mut()[0] = 1.;
+    assert!(vbo.update(&ctx));
+    assert!(!vbo.update(&ctx));
+    vbo.mark_dirty();
+    assert!(vbo.update(&ctx));
+}
+
+#[wasm_bindgen_test]
+fn tracked_vbo_edits_are_uploaded() {
+    let ctx = context();
+    let mut vbo = VBO::new(&ctx, Some(vec![0f32; 4]), WebGl2RenderingContext::ARRAY_BUFFER, WebGl2RenderingContext::DYNAMIC_DRAW).unwrap();
+    vbo.update(&ctx);
+    vbo.as_mut_slice_tracked(1..3).copy_from_slice(&[1., 2.]);
+    assert!(vbo.update(&ctx));
+    assert_eq!(vbo.read_back(&ctx), [0., 1., 2., 0.]);
+    vbo.push(3.);
+    vbo.extend([4., 5.]);
+    vbo.splice_range(0..1, [9., 9.]);
+    assert!(vbo.update(&ctx));
+    assert_eq!(vbo.read_back(&ctx), [9., 9., 1., 2., 0., 3., 4., 5.]);
+    vbo.splice_range(2..4, [7., 8.]);
+    assert!(vbo.update(&ctx));
+    assert_eq!(vbo.read_back(&ctx), [9., 9., 7., 8., 0., 3., 4., 5.]);
+    vbo.clear();
+    assert!(vbo.update(&ctx));
+    assert!(!vbo.update(&ctx));
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn clean_buffers_are_not_uploaded_by_draws() {
+    let ctx = context();
+    let shader = Shader::new(&ctx, VERTEX_SRC, FRAGMENT_SRC, &["projection", "color"], &["pos"], None).unwrap();
+    let mut vao = VaoBuilder::new(&ctx)
+        .unwrap()
+        .add_vertex_buffer("pos", vec![Position::default(); 3], WebGl2RenderingContext::DYNAMIC_DRAW)
+        .unwrap()
+        .set_index_buffer(&[0, 1, 2], WebGl2RenderingContext::STATIC_DRAW)
+        .unwrap()
+        .build();
+    vao.buffer::<Position>("pos").unwrap().bind(&ctx, shader.find_attr("pos"), 3, WebGl2RenderingContext::FLOAT, false, 0);
+    shader.enable(&ctx);
+    let before = buffer_uploads();
+    vao.draw_elements(&ctx, WebGl2RenderingContext::TRIANGLES).unwrap();
+    vao.draw_elements(&ctx, WebGl2RenderingContext::TRIANGLES).unwrap();
+    assert_eq!(buffer_uploads(), before);
+    // A tracked change is uploaded by the next draw, once
+    vao.buffer_mut::<Position>("pos").unwrap().as_mut_slice_tracked(0..1)[0].x = 1.;
+    vao.draw_elements(&ctx, WebGl2RenderingContext::TRIANGLES).unwrap();
+    vao.draw_elements(&ctx, WebGl2RenderingContext::TRIANGLES).unwrap();
+    assert_eq!(buffer_uploads(), before + 1);
+    // So do draws through a spec
+    let spec = vao.draw_spec(WebGl2RenderingContext::TRIANGLES).unwrap();
+    vao.buffer_mut::<Position>("pos").unwrap().as_mut_slice_tracked(1..2)[0].x = 1.;
+    vao.draw(&ctx, &spec);
+    vao.buffer_mut::<Position>("pos").unwrap().as_mut_slice_tracked(2..3)[0].x = 1.;
+    vao.draw_cached(&ctx, &BindCache::new(), &spec);
+    assert_eq!(buffer_uploads(), before + 3);
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn frame_stats_count_each_frame_separately() {
+    let ctx = context();
+    let shader = Shader::new(&ctx, VERTEX_SRC, FRAGMENT_SRC, &["projection", "color"], &["pos"], None).unwrap();
+    let mut vao = VaoBuilder::new(&ctx)
+        .unwrap()
+        .add_vertex_buffer("pos", vec![Position::default(); 4], WebGl2RenderingContext::DYNAMIC_DRAW)
+        .unwrap()
+        .set_index_buffer(&[0, 1, 2, 2, 1, 3], WebGl2RenderingContext::STATIC_DRAW)
+        .unwrap()
+        .build();
+    let texture = Texture2D::from_pixels(&ctx, 1, 1, TextureFormat::R8, &[0]).unwrap();
+    let cache = BindCache::new();
+
+    let mut stats = FrameStats::new(&ctx);
+    shader.enable_cached(&ctx, &cache);
+    shader.enable_cached(&ctx, &cache);
+    texture.bind(&ctx, 0);
+    vao.buffer_mut::<Position>("pos").unwrap().as_mut_slice_tracked(1..3)[0].x = 1.;
+    vao.draw_elements(&ctx, WebGl2RenderingContext::TRIANGLES).unwrap();
+    vao.draw_elements(&ctx, WebGl2RenderingContext::LINES).unwrap();
+    stats.end_frame();
+    assert_eq!(*stats.last_frame(), FrameCounts {
+        draw_calls: 2,
+        primitives: 5,
+        upload_bytes: 2 * std::mem::size_of::<Position>() as u64,
+        texture_binds: 1,
+        shader_switches: 1,
+    });
+    // Work on another context isn't counted
+    let other = context();
+    texture.bind(&ctx, 0);
+    Texture2D::from_pixels(&other, 1, 1, TextureFormat::R8, &[0]).unwrap().bind(&other, 0);
+    stats.end_frame();
+    assert_eq!(*stats.last_frame(), FrameCounts { texture_binds: 1, ..FrameCounts::default() });
+    stats.end_frame();
+    assert_eq!(*stats.last_frame(), FrameCounts::default());
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn framebuffer_renders_to_its_color_texture() {
+    let ctx = context();
+    let target = Framebuffer::new(&ctx, 4, 4)
+        .with_color(TextureFormat::Rgba8)
+        .with_depth_renderbuffer()
+        .build()
+        .unwrap();
+    assert!(target.color_texture(0).is_some());
+    assert!(target.depth_texture().is_none());
+
+    target.bind(&ctx);
+    ctx.clear_color(1., 0., 0., 1.);
+    ctx.clear(WebGl2RenderingContext::COLOR_BUFFER_BIT | WebGl2RenderingContext::DEPTH_BUFFER_BIT);
+    let mut pixel = [0u8; 4];
+    ctx.read_pixels_with_opt_u8_array(
+        0, 0, 1, 1,
+        WebGl2RenderingContext::RGBA, WebGl2RenderingContext::UNSIGNED_BYTE,
+        Some(&mut pixel)).unwrap();
+    target.unbind(&ctx, 1, 1);
+    assert_eq!(pixel, [255, 0, 0, 255]);
+}
+
+#[wasm_bindgen_test]
+fn framebuffer_with_attached_color_and_depth_is_complete() {
+    let ctx = context();
+    let color = Texture2D::new(&ctx).size(8, 4).build().unwrap();
+    let depth = Texture2D::new(&ctx).format(TextureFormat::Depth32F).size(8, 4).build().unwrap();
+    let color_handle = color.handle().clone();
+    let target = Framebuffer::new(&ctx, 8, 4).attach_color(color).attach_depth(depth).build().unwrap();
+    assert_eq!(target.color_texture(0).unwrap().handle(), &color_handle);
+    assert!(target.depth_texture().is_some());
+
+    target.bind(&ctx);
+    let status = ctx.check_framebuffer_status(WebGl2RenderingContext::FRAMEBUFFER);
+    target.unbind(&ctx, 1, 1);
+    assert_eq!(status, WebGl2RenderingContext::FRAMEBUFFER_COMPLETE);
+
+    let small = Texture2D::new(&ctx).size(2, 2).build().unwrap();
+    let err = Framebuffer::new(&ctx, 8, 4).attach_color(small).build().err().unwrap();
+    assert!(err.to_string().contains("2x2"), "{}", err);
+}
+
+#[wasm_bindgen_test]
+fn framebuffer_draws_to_every_color_attachment() {
+    let ctx = context();
+    let target = Framebuffer::new(&ctx, 4, 4)
+        .with_color(TextureFormat::Rgba8)
+        .with_color(TextureFormat::Rgba8)
+        .with_depth_renderbuffer()
+        .build()
+        .unwrap();
+    target.bind(&ctx);
+    let draw_buffer = |i| ctx.get_parameter(WebGl2RenderingContext::DRAW_BUFFER0 + i).unwrap().as_f64().unwrap() as u32;
+    assert_eq!(draw_buffer(0), WebGl2RenderingContext::COLOR_ATTACHMENT0);
+    assert_eq!(draw_buffer(1), WebGl2RenderingContext::COLOR_ATTACHMENT1);
+    assert_eq!(draw_buffer(2), WebGl2RenderingContext::NONE);
+    assert_eq!(ctx.check_framebuffer_status(WebGl2RenderingContext::FRAMEBUFFER), WebGl2RenderingContext::FRAMEBUFFER_COMPLETE);
+    target.unbind(&ctx, 1, 1);
+    assert!(target.color_texture(1).is_some());
+
+    let max = ctx.get_parameter(WebGl2RenderingContext::MAX_DRAW_BUFFERS).unwrap().as_f64().unwrap() as usize;
+    let too_many = (0..=max).fold(Framebuffer::new(&ctx, 1, 1), |builder, _| builder.with_color(TextureFormat::R8));
+    assert!(matches!(too_many.build(), Err(WasmGlError::InvalidArgument(_))));
+}
+
+#[wasm_bindgen_test]
+fn framebuffer_without_attachments_is_incomplete() {
+    let ctx = context();
+    let err = Framebuffer::new(&ctx, 4, 4).build().err().unwrap();
+    assert!(matches!(err, WasmGlError::FramebufferIncomplete { .. }), "{}", err);
+    assert!(err.to_string().contains("no attachments"), "{}", err);
+}
+
+#[wasm_bindgen_test]
+fn dropping_vbo_deletes_buffer() {
+    let ctx = context();
+    let buffers: Vec<_> = (0..32)
+        .map(|_| {
+            let vbo = VBO::new(&ctx, Some(vec![0u16; 3]), WebGl2RenderingContext::ARRAY_BUFFER, WebGl2RenderingContext::STATIC_DRAW).unwrap();
+            // A buffer only exists once it has been bound
+            vbo.update(&ctx);
+            let handle = vbo.handle().clone();
+            assert!(ctx.is_buffer(Some(&handle)));
+            handle
+        })
+        .collect();
+    assert!(buffers.iter().all(|buffer| !ctx.is_buffer(Some(buffer))));
+}
+
+#[wasm_bindgen_test]
+fn dropping_wrappers_deletes_every_gl_object() {
+    let ctx = context();
+    let before = live_gl_objects();
+    {
+        let shader = Shader::new_auto(&ctx, VERTEX_SRC, FRAGMENT_SRC, None).unwrap();
+        let texture = Texture2D::new(&ctx).size(2, 2).build().unwrap();
+        let cube_map = CubeMap::debug(&ctx).unwrap();
+        let target = Framebuffer::new(&ctx, 2, 2).with_color(TextureFormat::Rgba8).with_depth_renderbuffer().build().unwrap();
+        // Program, texture, cube map, then framebuffer, renderbuffer and color texture
+        assert_eq!(live_gl_objects(), before + 6);
+        assert!(ctx.is_program(Some(shader.program())));
+        let handles = (texture.handle().clone(), target.handle().clone());
+        drop((texture, target, cube_map));
+        assert!(!ctx.is_texture(Some(&handles.0)));
+        assert!(!ctx.is_framebuffer(Some(&handles.1)));
+    }
+    assert_eq!(live_gl_objects(), before);
+}
+
+#[wasm_bindgen_test]
+fn dropping_wrappers_after_context_loss_is_harmless() {
+    let ctx = context();
+    let before = live_gl_objects();
+    let texture = Texture2D::new(&ctx).size(2, 2).build().unwrap();
+    let vbo = VBO::new(&ctx, Some(vec![0u16; 3]), WebGl2RenderingContext::ARRAY_BUFFER, WebGl2RenderingContext::STATIC_DRAW).unwrap();
+    let lose_context = ctx.get_extension("WEBGL_lose_context").unwrap().unwrap();
+    js_sys::Reflect::get(&lose_context, &"loseContext".into())
+        .unwrap()
+        .unchecked_into::<js_sys::Function>()
+        .call0(&lose_context)
+        .unwrap();
+    drop((texture, vbo));
+    assert_eq!(live_gl_objects(), before);
+}
+
+#[wasm_bindgen_test]
+fn vao_builder_buffers_are_found_by_name_and_type() {
+    let ctx = context();
+    let before = live_gl_objects();
+    let mut vao = VaoBuilder::new(&ctx)
+        .unwrap()
+        .add_vertex_buffer("positions", vec![Position::default(); 3], WebGl2RenderingContext::STATIC_DRAW)
+        .unwrap()
+        .set_index_buffer(&[0, 1, 2], WebGl2RenderingContext::STATIC_DRAW)
+        .unwrap()
+        .build();
+    assert_eq!(vao.buffer::<Position>("positions").unwrap().len(), 3);
+    assert!(vao.buffer::<u16>("positions").is_none());
+    assert!(vao.buffer::<Position>("missing").is_none());
+    assert_eq!(vao.index_buffer().unwrap().len(), 3);
+    // Lookups without the element type
+    assert_eq!(vao.vbo("positions").unwrap().len(), 3);
+    assert!(vao.vbo("missing").is_none());
+
+    // Buffers can be added later, such as per-instance data
+    vao.add_vertex_buffer(&ctx, "offsets", vec![[0f32; 2]; 5], WebGl2RenderingContext::DYNAMIC_DRAW).unwrap();
+    assert_eq!(vao.vbo("offsets").unwrap().len(), 5);
+    assert!(!vao.update_buffers(&ctx));
+    vao.buffer_mut::<[f32; 2]>("offsets").unwrap().buffer_mut()[4] = [1., 2.];
+    assert!(vao.update_buffers(&ctx));
+    assert!(vao.add_vertex_buffer(&ctx, "offsets", vec![0u8], WebGl2RenderingContext::STATIC_DRAW).is_err());
+    // The vertex array, two buffers and the index buffer
+    assert_eq!(live_gl_objects(), before + 4);
+    drop(vao);
+    assert_eq!(live_gl_objects(), before);
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn meshes_draw_one_instance_per_instance_buffer_element() {
+    let ctx = context();
+    let builder = || {
+        VaoBuilder::new(&ctx)
+            .unwrap()
+            .add_vertex_buffer("mesh", vec![Vertex::default(); 3], WebGl2RenderingContext::STATIC_DRAW)
+            .unwrap()
+            .set_index_buffer(&[0, 1, 2], WebGl2RenderingContext::STATIC_DRAW)
+            .unwrap()
+    };
+    assert_eq!(Mesh::indexed(builder().build()).unwrap().current_draw_spec().instances, 1);
+
+    let vao = builder()
+        .add_instance_buffer("instances", vec![Matrix4::<f32>::identity(); 7], WebGl2RenderingContext::DYNAMIC_DRAW)
+        .unwrap()
+        .build();
+    assert_eq!(vao.instance_count(), Some(7));
+    let mut mesh = Mesh::indexed(vao).unwrap();
+    assert_eq!(mesh.current_draw_spec().instances, 7);
+    // Follows the buffer as it changes
+    let vao = mesh.vao_mut::<NamedBuffers>().unwrap();
+    vao.buffer_mut::<Matrix4<f32>>("instances").unwrap().buffer_mut().truncate(2);
+    assert_eq!(mesh.current_draw_spec().instances, 2);
+    // But only counts instances that have been uploaded
+    let vao = mesh.vao_mut::<NamedBuffers>().unwrap();
+    vao.buffer_mut::<Matrix4<f32>>("instances").unwrap().buffer_mut().extend([Matrix4::identity(); 8]);
+    assert_eq!(mesh.current_draw_spec().instances, 7);
+    mesh.vao_mut::<NamedBuffers>().unwrap().update_buffers(&ctx);
+    assert_eq!(mesh.current_draw_spec().instances, 10);
+
+    assert!(builder()
+        .add_instance_buffer("a", vec![0f32], WebGl2RenderingContext::STATIC_DRAW)
+        .unwrap()
+        .add_instance_buffer("b", vec![0f32], WebGl2RenderingContext::STATIC_DRAW)
+        .is_err());
+}
+
+#[wasm_bindgen_test]
+fn draw_elements_uses_the_stored_count_and_type() {
+    let ctx = context();
+    let shader = Shader::new_auto(&ctx, VERTEX_SRC, FRAGMENT_SRC, None).unwrap();
+    shader.enable(&ctx);
+    let builder = || {
+        VaoBuilder::new(&ctx)
+            .unwrap()
+            .add_vertex_buffer("positions", vec![Position::default(); 300], WebGl2RenderingContext::STATIC_DRAW)
+            .unwrap()
+    };
+    let unindexed = builder().build();
+    assert!(unindexed.draw_spec(WebGl2RenderingContext::TRIANGLES).is_none());
+    assert!(unindexed.draw_elements(&ctx, WebGl2RenderingContext::TRIANGLES).is_err());
+
+    // Indices past 255 need 16 bits
+    let vao = builder().set_index_buffer(&[0, 1, 299, 299, 1, 2], WebGl2RenderingContext::STATIC_DRAW).unwrap().build();
+    let spec = vao.draw_spec(WebGl2RenderingContext::TRIANGLES).unwrap();
+    assert_eq!((spec.count, spec.index_type, spec.instances), (6, WebGl2RenderingContext::UNSIGNED_SHORT, 1));
+    vao.draw_elements(&ctx, WebGl2RenderingContext::TRIANGLES).unwrap();
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn instanced_attributes_advance_per_instance() {
+    let ctx = context();
+    let vao = VaoBuilder::new(&ctx)
+        .unwrap()
+        .add_vertex_buffer("mesh", vec![Position::default(); 3], WebGl2RenderingContext::STATIC_DRAW)
+        .unwrap()
+        .add_instance_buffer("offsets", vec![[0f32; 2]; 4], WebGl2RenderingContext::STATIC_DRAW)
+        .unwrap()
+        .build();
+    let offsets = vao.buffer::<[f32; 2]>("offsets").unwrap();
+    vao.add_instanced_attribute(&ctx, offsets, 1, 2, WebGl2RenderingContext::FLOAT, 1);
+    let parameter = |name| ctx.get_vertex_attrib(1, name).unwrap().as_f64();
+    assert_eq!(parameter(WebGl2RenderingContext::VERTEX_ATTRIB_ARRAY_DIVISOR), Some(1.));
+    assert_eq!(parameter(WebGl2RenderingContext::VERTEX_ATTRIB_ARRAY_SIZE), Some(2.));
+    // Other attributes still advance per vertex
+    assert_eq!(ctx.get_vertex_attrib(0, WebGl2RenderingContext::VERTEX_ATTRIB_ARRAY_DIVISOR).unwrap().as_f64(), Some(0.));
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn bind_layout_skips_attributes_the_shader_lacks() {
+    let ctx = context();
+    // Uses `pos` but not `normal`
+    let shader = Shader::new_auto(&ctx, VERTEX_SRC, FRAGMENT_SRC, None).unwrap();
+    let vao = VaoBuilder::new(&ctx)
+        .unwrap()
+        .add_vertex_buffer("mesh", vec![Vertex::default(); 3], WebGl2RenderingContext::STATIC_DRAW)
+        .unwrap()
+        .build();
+    vao.buffer::<Vertex>("mesh").unwrap().bind_layout(&ctx, &shader);
+    let pos = shader.find_attr("pos");
+    let parameter = |name| ctx.get_vertex_attrib(pos, name).unwrap();
+    assert_eq!(parameter(WebGl2RenderingContext::VERTEX_ATTRIB_ARRAY_ENABLED).as_bool(), Some(true));
+    assert_eq!(parameter(WebGl2RenderingContext::VERTEX_ATTRIB_ARRAY_SIZE).as_f64(), Some(3.));
+    assert_eq!(
+        parameter(WebGl2RenderingContext::VERTEX_ATTRIB_ARRAY_STRIDE).as_f64(),
+        Some(std::mem::size_of::<Vertex>() as f64));
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn integer_attributes_are_not_converted() {
+    let ctx = context();
+    let vertex = VERTEX_SRC
+        .replace("in vec3 pos;", "in vec3 pos;\nin ivec4 bones;")
+        .replace("vec4(pos, 1)", "vec4(pos + float(bones.x), 1)");
+    let shader = Shader::new_auto(&ctx, &vertex, FRAGMENT_SRC, None).unwrap();
+    assert_eq!(shader.attr_type("bones"), Some(WebGl2RenderingContext::INT_VEC4));
+    assert_eq!(shader.attr_type("pos"), Some(WebGl2RenderingContext::FLOAT_VEC3));
+    assert_eq!(shader.attr_type("missing"), None);
+    let vao = VaoBuilder::new(&ctx)
+        .unwrap()
+        .add_vertex_buffer("bones", vec![[0i32; 4]; 3], WebGl2RenderingContext::STATIC_DRAW)
+        .unwrap()
+        .build();
+    let bones = shader.find_attr("bones");
+    vao.buffer::<[i32; 4]>("bones").unwrap().bind_integer(&ctx, bones, 4, WebGl2RenderingContext::INT, 0);
+    let parameter = |name| ctx.get_vertex_attrib(bones, name).unwrap();
+    assert_eq!(parameter(WebGl2RenderingContext::VERTEX_ATTRIB_ARRAY_INTEGER).as_bool(), Some(true));
+    assert_eq!(parameter(WebGl2RenderingContext::VERTEX_ATTRIB_ARRAY_TYPE).as_f64(), Some(WebGl2RenderingContext::INT as f64));
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn reserve_allocates_capacity_bytes() {
+    let ctx = context();
+    let mut vbo = VBO::<Matrix4<f32>>::new(&ctx, None, WebGl2RenderingContext::ARRAY_BUFFER, WebGl2RenderingContext::STREAM_DRAW).unwrap();
+    let buffer_size = |ctx: &WebGl2RenderingContext| {
+        ctx.get_buffer_parameter(WebGl2RenderingContext::ARRAY_BUFFER, WebGl2RenderingContext::BUFFER_SIZE).as_f64()
+    };
+    vbo.reserve(&ctx, 10);
+    assert_eq!(buffer_size(&ctx), Some((10 * std::mem::size_of::<Matrix4<f32>>()) as f64));
+
+    vbo.buffer_mut().extend([Matrix4::identity(); 3]);
+    vbo.orphan(&ctx);
+    assert_eq!(buffer_size(&ctx), Some(3. * 64.));
+    vbo.update_range(&ctx, 1, 2);
+    // Past the end is cut short rather than panicking
+    vbo.update_range(&ctx, 2, 5);
+    vbo.update_range(&ctx, 4, 1);
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn gamma_pass_encodes_linear_input() {
+    let ctx = context();
+    let cache = BindCache::new();
+    let grey = Texture2D::from_pixels(&ctx, 1, 1, TextureFormat::Rgba8, &[51, 51, 51, 255]).unwrap();
+    let target = Framebuffer::new(&ctx, 1, 1).with_color(TextureFormat::Rgba8).build().unwrap();
+    PostProcessPass::gamma(&ctx).unwrap().draw(&ctx, &cache, &[&grey], Some(&target), 1, 1);
+    let mut pixel = [0u8; 4];
+    ctx.read_pixels_with_opt_u8_array(
+        0, 0, 1, 1,
+        WebGl2RenderingContext::RGBA, WebGl2RenderingContext::UNSIGNED_BYTE,
+        Some(&mut pixel)).unwrap();
+    target.unbind(&ctx, 1, 1);
+    // Linear 0.2 is roughly 0.48 in sRGB
+    assert!((123..=125).contains(&pixel[0]), "{:?}", pixel);
+    assert_eq!(pixel[3], 255);
+}
+
+#[wasm_bindgen_test]
+fn post_process_pass_rejects_unknown_inputs() {
+    let ctx = context();
+    let err = PostProcessPass::new(&ctx, include_str!("../src/shaders/gamma.fsh"), &["missing"]).err().unwrap();
+    assert!(matches!(&err, WasmGlError::MissingUniform { name } if name == "missing"), "{}", err);
+}
+
+#[wasm_bindgen_test]
+fn debug_quad_draws_into_a_corner_and_restores_state() {
+    let ctx = context();
+    let red = Texture2D::from_pixels(&ctx, 1, 1, TextureFormat::Rgba8, &[255, 0, 0, 255]).unwrap();
+    let shader = Shader::new(&ctx, VERTEX_SRC, FRAGMENT_SRC, &["projection", "color"], &["pos"], None).unwrap();
+    let target = Framebuffer::new(&ctx, 4, 4).with_color(TextureFormat::Rgba8).build().unwrap();
+    target.bind(&ctx);
+    ctx.clear_color(0., 0., 0., 0.);
+    ctx.clear(WebGl2RenderingContext::COLOR_BUFFER_BIT);
+    ctx.enable(WebGl2RenderingContext::DEPTH_TEST);
+    shader.enable(&ctx);
+
+    let quad = DebugQuad::new(&ctx).unwrap();
+    quad.draw_texture(&ctx, &red, ScreenCorner::TopRight, 0.5).unwrap();
+    let pixels = read_pixels(&ctx, 0, 0, 4, 4).unwrap();
+    let pixel = |x: usize, y: usize| &pixels[(y * 4 + x) * 4..][..4];
+    // Rows are top down
+    assert_eq!(pixel(3, 0), [255, 0, 0, 255]);
+    assert_eq!(pixel(2, 1), [255, 0, 0, 255]);
+    assert_eq!(pixel(1, 1), [0, 0, 0, 0]);
+    assert_eq!(pixel(3, 2), [0, 0, 0, 0]);
+
+    assert!(ctx.is_enabled(WebGl2RenderingContext::DEPTH_TEST));
+    assert!(!ctx.is_enabled(WebGl2RenderingContext::SCISSOR_TEST));
+    let viewport: js_sys::Int32Array = ctx.get_parameter(WebGl2RenderingContext::VIEWPORT).unwrap().dyn_into().unwrap();
+    assert_eq!(viewport.to_vec(), [0, 0, 4, 4]);
+    let program: WebGlProgram = ctx.get_parameter(WebGl2RenderingContext::CURRENT_PROGRAM).unwrap().dyn_into().unwrap();
+    assert_eq!(&program, shader.program());
+    ctx.disable(WebGl2RenderingContext::DEPTH_TEST);
+    target.unbind(&ctx, 1, 1);
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn debug_quad_rejects_missing_cascades() {
+    let ctx = context();
+    let cascades = CascadedShadowMap::new(&ctx, 16, 2).unwrap();
+    let quad = DebugQuad::new(&ctx).unwrap();
+    assert!(quad.draw_cascade(&ctx, &cascades, 2, ScreenCorner::BottomLeft, 0.5).is_err());
+}
+
+#[cfg(feature = "gl-debug")]
+#[wasm_bindgen_test]
+#[should_panic(expected = "GL error INVALID_VALUE after texImage2D")]
+fn gl_debug_reports_invalid_tex_image() {
+    let ctx = context();
+    let texture = ctx.create_texture().unwrap();
+    ctx.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture));
+    // Negative sizes are recorded as INVALID_VALUE rather than thrown
+    ctx.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+        WebGl2RenderingContext::TEXTURE_2D, 0, WebGl2RenderingContext::RGBA8 as i32, -1, -1, 0,
+        WebGl2RenderingContext::RGBA, WebGl2RenderingContext::UNSIGNED_BYTE, None,
+    ).unwrap();
+    wasmgl::renderer::check_gl(&ctx, "texImage2D");
+}
+
+#[wasm_bindgen_test]
+fn read_pixels_returns_top_down_rows() {
+    let ctx = context();
+    let target = Framebuffer::new(&ctx, 1, 2).with_color(TextureFormat::Rgba8).build().unwrap();
+    target.bind(&ctx);
+    ctx.enable(WebGl2RenderingContext::SCISSOR_TEST);
+    ctx.scissor(0, 0, 1, 1);
+    ctx.clear_color(1., 0., 0., 1.);
+    ctx.clear(WebGl2RenderingContext::COLOR_BUFFER_BIT);
+    ctx.disable(WebGl2RenderingContext::SCISSOR_TEST);
+    let pixels = read_pixels(&ctx, 0, 0, 1, 2).unwrap();
+    target.unbind(&ctx, 1, 1);
+    // The cleared bottom row comes last
+    assert_eq!(pixels, [0, 0, 0, 0, 255, 0, 0, 255]);
+}
+
+#[wasm_bindgen_test]
+fn read_pixels_of_an_empty_rectangle_is_empty() {
+    let ctx = context();
+    assert!(read_pixels(&ctx, 0, 0, 0, 4).unwrap().is_empty());
+    assert!(read_pixels(&ctx, 0, 0, 4, 0).unwrap().is_empty());
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn read_pixels_rejects_depth_only_framebuffers() {
+    let ctx = context();
+    let target = Framebuffer::new(&ctx, 2, 2).with_depth_texture().build().unwrap();
+    target.bind(&ctx);
+    let err = read_pixels(&ctx, 0, 0, 2, 2).err().unwrap();
+    target.unbind(&ctx, 1, 1);
+    assert!(err.to_string().contains("no color attachment"), "{}", err);
+}
+
+#[wasm_bindgen_test]
+fn vbo_read_back_round_trips() {
+    let ctx = context();
+    let vertices: Vec<Vertex> = (0..4)
+        .map(|i| Vertex {
+            pos: Position { x: i as f32, y: -1., z: 0.5 },
+            normal: Position { x: 0., y: 1., z: i as f32 * 0.25 },
+            uv: [i as f32, 0.5],
+        })
+        .collect();
+    let vbo = VBO::new(&ctx, Some(vertices.clone()), WebGl2RenderingContext::ARRAY_BUFFER, WebGl2RenderingContext::STATIC_DRAW).unwrap();
+    vbo.update(&ctx);
+    assert_eq!(vbo.read_back(&ctx), vertices);
+}
+
+/// Compares `VBO::update` with `update_unchecked`, for a buffer the size of
+/// the demo's grass (1000 vertices) and a larger one. Run with
+/// `wasm-pack test --chrome --headless -- --include-ignored` and read the
+/// console output.
+#[wasm_bindgen_test]
+#[ignore]
+fn bench_vbo_update() {
+    let ctx = context();
+    let performance = web_sys::window().unwrap().performance().unwrap();
+    for count in [1_000, 100_000] {
+        let vertex = Vertex { pos: Position { x: 1., y: 2., z: 3. }, normal: Position { x: 0., y: 1., z: 0. }, uv: [0., 0.] };
+        let vbo = VBO::new(&ctx, Some(vec![vertex; count]), WebGl2RenderingContext::ARRAY_BUFFER, WebGl2RenderingContext::DYNAMIC_DRAW).unwrap();
+        let time = |upload: &dyn Fn()| {
+            let start = performance.now();
+            for _ in 0..100 {
+                vbo.mark_dirty();
+                upload();
+            }
+            ctx.finish();
+            (performance.now() - start) / 100.
+        };
+        let copied = time(&|| {
+            vbo.update(&ctx);
+        });
+        let viewed = time(&|| unsafe {
+            vbo.update_unchecked(&ctx);
+        });
+        console_log!("{} vertices: update {:.3}ms, update_unchecked {:.3}ms", count, copied, viewed);
+    }
+}
+
+#[wasm_bindgen_test]
+async fn stopped_render_loop_is_not_called_again() {
+    let calls = Rc::new(Cell::new(0));
+    let counter = calls.clone();
+    let handle = render_loop(move |_, _| counter.set(counter.get() + 1)).unwrap();
+    next_frame().await;
+    next_frame().await;
+    // The initial resize call, then at least one frame
+    assert!(calls.get() > 1, "{}", calls.get());
+
+    handle.stop();
+    let stopped_at = calls.get();
+    for _ in 0..3 {
+        next_frame().await;
+    }
+    web_sys::window().unwrap().dispatch_event(&Event::new("resize").unwrap()).unwrap();
+    assert_eq!(calls.get(), stopped_at);
+    assert!(handle.is_stopped());
+}
+
+#[wasm_bindgen_test]
+async fn dropped_render_loops_stop_firing() {
+    let calls = Rc::new(Cell::new(0));
+    for _ in 0..2 {
+        let counter = calls.clone();
+        let handle = render_loop(move |_, _| counter.set(counter.get() + 1)).unwrap();
+        next_frame().await;
+        drop(handle);
+    }
+    let dropped_at = calls.get();
+    for _ in 0..3 {
+        next_frame().await;
+    }
+    web_sys::window().unwrap().dispatch_event(&Event::new("resize").unwrap()).unwrap();
+    assert_eq!(calls.get(), dropped_at);
+    // Only the test's clone is left, the loops' closures were freed
+    assert_eq!(Rc::strong_count(&calls), 1);
+}
+
+#[wasm_bindgen_test]
+async fn paused_render_loop_skips_frames() {
+    let frames = Rc::new(Cell::new(0));
+    let deltas = Rc::new(std::cell::RefCell::new(Vec::new()));
+    let (counter, log) = (frames.clone(), deltas.clone());
+    let handle = render_loop(move |resize, delta| {
+        if !resize {
+            counter.set(counter.get() + 1);
+            log.borrow_mut().push(delta);
+        }
+    }).unwrap();
+    next_frame().await;
+    next_frame().await;
+
+    handle.pause();
+    assert!(handle.is_paused());
+    let paused_at = frames.get();
+    for _ in 0..3 {
+        next_frame().await;
+    }
+    assert_eq!(frames.get(), paused_at);
+
+    deltas.borrow_mut().clear();
+    handle.resume();
+    next_frame().await;
+    next_frame().await;
+    assert!(frames.get() > paused_at);
+    // The paused time isn't reported as one long frame
+    assert_eq!(deltas.borrow()[0], 0.);
+    handle.stop();
+}
+
+#[wasm_bindgen_test]
+async fn demo_frames_render_without_gl_errors() {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let canvas: HtmlCanvasElement = document.create_element("canvas").unwrap().dyn_into().unwrap();
+    canvas.set_width(64);
+    canvas.set_height(64);
+    document.body().unwrap().append_child(&canvas).unwrap();
+    let handle = init(canvas.clone()).unwrap();
+    // The demo runs with shadow cascades and post processing. From the second
+    // frame on, unit 0 has held the post process input, which is the main
+    // pass's render target
+    for _ in 0..3 {
+        next_frame().await;
+    }
+    handle.stop();
+    let ctx: WebGl2RenderingContext = canvas.get_context("webgl2").unwrap().unwrap().dyn_into().unwrap();
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+    canvas.remove();
+}
+
+#[wasm_bindgen_test]
+fn init_with_id_reports_missing_and_non_canvas_elements() {
+    let message = |id| init_with_id(id).err().unwrap().as_string().unwrap();
+    assert_eq!(message("no-such-element"), "No element with id \"no-such-element\"");
+
+    let document = web_sys::window().unwrap().document().unwrap();
+    let div = document.create_element("div").unwrap();
+    div.set_id("not-a-canvas");
+    document.body().unwrap().append_child(&div).unwrap();
+    assert_eq!(message("not-a-canvas"), "Element \"not-a-canvas\" is a <div>, not a <canvas>");
+    div.remove();
+}
+
+#[wasm_bindgen_test]
+fn dispatched_key_events_update_input_state() {
+    let window = web_sys::window().unwrap();
+    let canvas: HtmlCanvasElement = window.document().unwrap().create_element("canvas").unwrap().dyn_into().unwrap();
+    let input = InputState::attach(&canvas).unwrap();
+    let key = |kind: &str, code: &str| {
+        let init = KeyboardEventInit::new();
+        init.set_code(code);
+        let event = KeyboardEvent::new_with_keyboard_event_init_dict(kind, &init).unwrap();
+        window.dispatch_event(&event).unwrap();
+    };
+
+    key("keydown", "KeyW");
+    key("keydown", "Space");
+    assert!(input.borrow().is_key_down("KeyW"));
+    key("keyup", "KeyW");
+    assert!(!input.borrow().is_key_down("KeyW"));
+    assert!(input.borrow().is_key_down("Space"));
+    window.dispatch_event(&Event::new("blur").unwrap()).unwrap();
+    assert!(!input.borrow().is_key_down("Space"));
+
+    // Dispatching after the handle (and its listeners) are gone is harmless
+    drop(input);
+    key("keydown", "KeyW");
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Tint {
+    color: [f32; 3],
+}
+
+unsafe impl Std140 for Tint {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PaddedTint {
+    color: [f32; 3],
+    _padding: f32,
+}
+
+unsafe impl Std140 for PaddedTint {}
+
+#[wasm_bindgen_test]
+fn time_uniform_is_registered_when_used() {
+    let ctx = context();
+    let fragment = FRAGMENT_SRC
+        .replace("uniform vec3 color;", "uniform vec3 color;\nuniform float uTime;")
+        .replace("vec4(color, 1)", "vec4(color * uTime, 1)");
+    let timed = Shader::new(&ctx, VERTEX_SRC, &fragment, &["color"], &["pos"], None).unwrap();
+    assert!(timed.get_uniform(TIME_UNIFORM).is_some());
+    timed.enable(&ctx);
+    timed.set_time(&ctx, 2.5);
+    let time = ctx.get_uniform(timed.program(), timed.get_uniform(TIME_UNIFORM).unwrap());
+    assert_eq!(time.as_f64(), Some(2.5));
+
+    let untimed = Shader::new(&ctx, VERTEX_SRC, FRAGMENT_SRC, &["color"], &["pos"], None).unwrap();
+    assert!(untimed.get_uniform(TIME_UNIFORM).is_none());
+    untimed.enable(&ctx);
+    untimed.set_time(&ctx, 2.5);
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn light_sets_fill_the_main_shader_light_array() {
+    let ctx = context();
+    let includes = HashMap::from([
+        ("instancing", include_str!("../src/shaders/instancing.glsl")),
+        ("frame_data", include_str!("../src/shaders/frame_data.glsl")),
+    ]);
+    let shader = Shader::new_with_includes(
+        &ctx,
+        include_str!("../src/shaders/main.vsh"),
+        include_str!("../src/shaders/main.fsh"),
+        &includes,
+        None,
+    )
+    .unwrap();
+    // Every element of the array is registered, not just those in use
+    let last = format!("uLights[{}].color", LightSet::MAX_LIGHTS - 1);
+    assert!(shader.get_uniform(&last).is_some());
+
+    let mut lights = LightSet::default();
+    lights.add(Light::Directional { direction: Vector3::new(0., -2., 0.), color: Color::default(), intensity: 1. }).unwrap();
+    let orange = Color { r: 1., g: 0.5, b: 0., a: 1. };
+    lights.add(Light::Point { position: Vector3::new(1., 2., 3.), color: orange, intensity: 0.5, range: 4. }).unwrap();
+    shader.enable(&ctx);
+    lights.apply(&ctx, &shader);
+
+    let uniform = |name: &str| ctx.get_uniform(shader.program(), shader.get_uniform(name).unwrap());
+    let vector = |name: &str| js_sys::Float32Array::from(uniform(name)).to_vec();
+    assert_eq!(uniform("uLightCount").as_f64(), Some(2.));
+    assert_eq!(vector("uLights[0].direction"), [0., -1., 0.]);
+    assert_eq!(uniform("uLights[1].kind").as_f64(), Some(1.));
+    assert_eq!(vector("uLights[1].color"), [1., 0.5, 0.]);
+    assert_eq!(vector("uLights[1].position"), [1., 2., 3.]);
+    assert_eq!(uniform("uLights[1].range").as_f64(), Some(4.));
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn materials_upload_only_the_uniforms_a_shader_has() {
+    let ctx = context();
+    let fragment = FRAGMENT_SRC
+        .replace("uniform vec3 color;", "uniform vec3 baseColor;\nuniform float shininess;")
+        .replace("vec4(color, 1)", "vec4(baseColor * shininess, 1)");
+    let shader = Shader::new_auto(&ctx, VERTEX_SRC, &fragment, None).unwrap();
+    assert!(shader.get_uniform("specularColor").is_none());
+    let material = Material { base_color: Vector3::new(0.5, 0.25, 1.), shininess: 8., ..Material::default() };
+    shader.enable(&ctx);
+    material.apply(&ctx, &shader);
+    let uniform = |name| ctx.get_uniform(shader.program(), shader.find_uniform(name));
+    assert_eq!(js_sys::Float32Array::from(uniform("baseColor")).to_vec(), [0.5, 0.25, 1.]);
+    assert_eq!(uniform("shininess").as_f64(), Some(8.));
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn meshes_without_vertex_colors_read_white() {
+    let ctx = context();
+    let vertex = VERTEX_SRC
+        .replace("in vec3 pos;", "in vec3 pos;\nin vec4 color;\nout vec4 v_color;")
+        .replace("gl_Position =", "v_color = color;\n\tgl_Position =");
+    let fragment = FRAGMENT_SRC
+        .replace("uniform vec3 color;", "in vec4 v_color;")
+        .replace("vec4(color, 1)", "v_color");
+    let shader = Shader::new_auto(&ctx, &vertex, &fragment, None).unwrap();
+    let location = shader.find_attr(VERTEX_COLOR);
+    ctx.vertex_attrib4f(location, 0., 0., 0., 0.);
+    Scene::new().draw_opaque(&ctx, &BindCache::new(), &shader);
+    let current = ctx.get_vertex_attrib(location, WebGl2RenderingContext::CURRENT_VERTEX_ATTRIB).unwrap();
+    assert_eq!(js_sys::Float32Array::from(current).to_vec(), [1., 1., 1., 1.]);
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn transparent_meshes_are_ordered_back_to_front() {
+    let ctx = context();
+    let mesh = |z: f32, transparent| {
+        let vao = VaoBuilder::new(&ctx)
+            .unwrap()
+            .add_vertex_buffer("mesh", vec![Position::default(); 3], WebGl2RenderingContext::STATIC_DRAW)
+            .unwrap()
+            .set_index_buffer(&[0, 1, 2], WebGl2RenderingContext::STATIC_DRAW)
+            .unwrap()
+            .build();
+        let mut mesh = Mesh::indexed(vao).unwrap();
+        mesh.model = Matrix4::new_translation(&Vector3::new(0., 0., z));
+        mesh.material.transparent = transparent;
+        mesh
+    };
+    let mut scene = Scene::new();
+    let near = scene.add(mesh(1., true));
+    scene.add(mesh(-10., false));
+    let far = scene.add(mesh(-1., true));
+    // Looking down -z from z = 5, then down +z from z = -5
+    let front = Matrix4::new_translation(&Vector3::new(0., 0., -5.));
+    assert_eq!(scene.transparent_order(&front), [far, near]);
+    let back = Matrix4::new_rotation(Vector3::y() * std::f32::consts::PI) * Matrix4::new_translation(&Vector3::new(0., 0., 5.));
+    assert_eq!(scene.transparent_order(&back), [near, far]);
+}
+
+#[wasm_bindgen_test]
+fn transparent_render_state_blends_without_writing_depth() {
+    let ctx = context();
+    RenderState::transparent().apply(&ctx);
+    let parameter = |name| ctx.get_parameter(name).unwrap();
+    assert!(ctx.is_enabled(WebGl2RenderingContext::BLEND));
+    assert!(ctx.is_enabled(WebGl2RenderingContext::DEPTH_TEST));
+    assert_eq!(parameter(WebGl2RenderingContext::DEPTH_WRITEMASK).as_bool(), Some(false));
+    assert_eq!(
+        parameter(WebGl2RenderingContext::BLEND_DST_RGB).as_f64(),
+        Some(WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA as f64));
+    assert_eq!(parameter(WebGl2RenderingContext::BLEND_SRC_ALPHA).as_f64(), Some(WebGl2RenderingContext::ONE as f64));
+
+    RenderState::opaque().apply(&ctx);
+    assert!(!ctx.is_enabled(WebGl2RenderingContext::BLEND));
+    assert_eq!(parameter(WebGl2RenderingContext::DEPTH_WRITEMASK).as_bool(), Some(true));
+}
+
+#[wasm_bindgen_test]
+fn cull_modes_set_the_culled_face() {
+    let ctx = context();
+    let cull_face = |ctx: &WebGl2RenderingContext| ctx.get_parameter(WebGl2RenderingContext::CULL_FACE_MODE).unwrap().as_f64();
+    CullMode::Front.apply(&ctx);
+    assert!(ctx.is_enabled(WebGl2RenderingContext::CULL_FACE));
+    assert_eq!(cull_face(&ctx), Some(WebGl2RenderingContext::FRONT as f64));
+    CullMode::Back.apply(&ctx);
+    assert_eq!(cull_face(&ctx), Some(WebGl2RenderingContext::BACK as f64));
+
+    // Scenes leave culling off for whatever draws next
+    let shader = Shader::new_auto(&ctx, VERTEX_SRC, FRAGMENT_SRC, None).unwrap();
+    Scene::new().draw_opaque_culling(&ctx, &BindCache::new(), &shader, Some(CullMode::Front));
+    assert!(!ctx.is_enabled(WebGl2RenderingContext::CULL_FACE));
+}
+
+#[wasm_bindgen_test]
+fn shadow_texel_size_is_one_over_the_map_size() {
+    let ctx = context();
+    let fragment = FRAGMENT_SRC
+        .replace("uniform vec3 color;", "uniform vec3 color;\nuniform int shadowKernelRadius;\nuniform float shadowTexelSize;\nuniform float shadowBias;")
+        .replace("vec4(color, 1)", "vec4(color * shadowTexelSize * float(shadowKernelRadius) + shadowBias, 1)");
+    let shader = Shader::new_auto(&ctx, VERTEX_SRC, &fragment, None).unwrap();
+    let uniform = |name| ctx.get_uniform(shader.program(), shader.get_uniform(name).unwrap()).as_f64().unwrap();
+
+    let mut shadow_map = ShadowMap::new(&ctx, 512).unwrap();
+    shadow_map.config.filter = ShadowFilter::Pcf5x5;
+    shader.enable(&ctx);
+    shadow_map.apply(&ctx, &shader);
+    assert_eq!(uniform("shadowTexelSize") as f32, 1. / 512.);
+    assert_eq!(uniform("shadowKernelRadius"), 2.);
+
+    shadow_map.resize(&ctx, 2048).unwrap();
+    shadow_map.apply(&ctx, &shader);
+    assert_eq!(uniform("shadowTexelSize") as f32, 1. / 2048.);
+}
+
+#[wasm_bindgen_test]
+fn shadow_map_allocates_and_renders_at_its_size() {
+    let ctx = context();
+    let shadow_map = ShadowMap::new(&ctx, 2048).unwrap();
+    let texture = shadow_map.texture();
+    assert_eq!((texture.width(), texture.height(), texture.format()), (2048, 2048, TextureFormat::Depth32F));
+
+    shadow_map.begin(&ctx);
+    let viewport: js_sys::Int32Array = ctx.get_parameter(WebGl2RenderingContext::VIEWPORT).unwrap().dyn_into().unwrap();
+    shadow_map.end(&ctx, 1, 1);
+    assert_eq!(viewport.to_vec(), [0, 0, 2048, 2048]);
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn cascades_render_into_each_layer() {
+    let ctx = context();
+    let before = live_gl_objects();
+    let mut cascades = CascadedShadowMap::new(&ctx, 64, 3).unwrap();
+    assert!(cascades.set_count(&ctx, 0).is_err());
+    assert!(cascades.set_count(&ctx, CascadedShadowMap::MAX_CASCADES + 1).is_err());
+    assert_eq!(cascades.count(), 3);
+
+    let camera = Camera::new(Vector3::new(0., 1., 4.), 1., 0.1, 100.);
+    cascades.max_distance = 20.;
+    cascades.update(&camera, &DirectionalLight::new(Vector3::new(0., -1., 0.)), Vector3::repeat(-5.), Vector3::repeat(5.));
+    assert_eq!(cascades.splits().len(), 3);
+    assert_eq!(cascades.splits()[2], 20.);
+    let mut rendered = Vec::new();
+    cascades.render(&ctx, 1, 1, |i, projection_view| {
+        assert_eq!(*projection_view, cascades.projection_views()[i]);
+        rendered.push(i);
+    });
+    assert_eq!(rendered, [0, 1, 2]);
+
+    cascades.set_count(&ctx, 2).unwrap();
+    assert_eq!(cascades.count(), 2);
+    // The texture array and the framebuffer
+    assert_eq!(live_gl_objects(), before + 2);
+    drop(cascades);
+    assert_eq!(live_gl_objects(), before);
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn point_shadows_render_every_face_when_due() {
+    let ctx = context();
+    let before = live_gl_objects();
+    let mut shadow = PointShadow::new(&ctx, 64, Vector3::new(0., 1., 0.), 5.).unwrap();
+    shadow.throttle = UpdateThrottle::new(2);
+    let mut faces = Vec::new();
+    assert!(shadow.render(&ctx, 1, 1, |face, _| {
+        let viewport: js_sys::Int32Array = ctx.get_parameter(WebGl2RenderingContext::VIEWPORT).unwrap().dyn_into().unwrap();
+        assert_eq!(viewport.to_vec(), [0, 0, 64, 64]);
+        faces.push(face);
+    }));
+    assert_eq!(faces, [0, 1, 2, 3, 4, 5]);
+    assert!(!shadow.render(&ctx, 1, 1, |_, _| panic!("throttled frames don't render")));
+    assert!(shadow.render(&ctx, 1, 1, |_, _| {}));
+    // The cube map and the framebuffer
+    assert_eq!(live_gl_objects(), before + 2);
+    drop(shadow);
+    assert_eq!(live_gl_objects(), before);
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}
+
+#[wasm_bindgen_test]
+fn uniform_block_size_must_match() {
+    let ctx = context();
+    let fragment = FRAGMENT_SRC
+        .replace("uniform vec3 color;", "layout(std140) uniform Tint { vec3 color; };");
+    let shader = Shader::new_auto(&ctx, VERTEX_SRC, &fragment, None).unwrap();
+
+    let unpadded = UniformBuffer::<Tint>::new(&ctx, 1).unwrap();
+    let err = shader.bind_uniform_block(&ctx, "Tint", &unpadded).err().unwrap();
+    assert!(err.to_string().contains("16 bytes"), "{}", err);
+
+    let padded = UniformBuffer::<PaddedTint>::new(&ctx, 1).unwrap();
+    shader.bind_uniform_block(&ctx, "Tint", &padded).unwrap();
+    padded.update(&ctx, &PaddedTint { color: [1., 0.5, 0.], _padding: 0. });
+    assert!(matches!(
+        shader.bind_uniform_block(&ctx, "Missing", &padded),
+        Err(WasmGlError::MissingUniform { .. })));
+    assert_eq!(ctx.get_error(), WebGl2RenderingContext::NO_ERROR);
+}